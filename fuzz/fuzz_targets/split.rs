@@ -0,0 +1,42 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use pll::split::{clean_record, Delims, ManySplit};
+
+fuzz_target!(|input: (Option<Vec<u8>>, bool, Option<u16>, Vec<u8>)| {
+    let (bytes, escapes, max_len, data) = input;
+    // no delimiter set stands for --unicode-ws
+    let delims = match &bytes {
+        Some(bytes) => Delims::Bytes(bytes.clone()),
+        None => Delims::UnicodeWs,
+    };
+    let max_len = max_len.map_or(usize::MAX, usize::from);
+    let mut joined = vec![];
+    for arg in data
+        .as_slice()
+        .split_any(&delims)
+        .max_len(max_len)
+        .escapes(escapes)
+    {
+        let Ok(arg) = arg else {
+            // only a record over --max-record-size fails, and nothing comes after it
+            assert!(max_len < data.len());
+            assert!(data.starts_with(&joined));
+            return;
+        };
+        assert!(!arg.is_empty());
+        assert!(arg.len() <= max_len);
+        if let Some(cleaned) = clean_record(&delims, escapes, &arg) {
+            assert!(!cleaned.is_empty());
+            assert!(cleaned.len() <= arg.len());
+            if let (Some(bytes), false) = (&bytes, escapes) {
+                assert!(!bytes.contains(&cleaned[0]));
+                assert!(!bytes.contains(&cleaned[cleaned.len() - 1]));
+            }
+        }
+        joined.extend_from_slice(&arg);
+    }
+    // splitting never drops nor reorders bytes
+    assert_eq!(joined, data);
+});
@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use pll::{ArgBuilder, ArgBuilderMaker, DynArgBuilderMaker};
+
+fuzz_target!(|input: (Vec<String>, Vec<String>)| {
+    let (template, words) = input;
+    let Ok(maker) = DynArgBuilderMaker::template(template) else {
+        return;
+    };
+    let mut builder = maker.make();
+    let mut full: Option<Vec<String>> = None;
+    for word in &words {
+        let finalized = builder.push_arg(word);
+        // a full template drops the records pushed after it
+        if let Some(arg_list) = &full {
+            assert!(finalized);
+            assert_eq!(&builder.arg_list(), arg_list);
+        } else if finalized {
+            assert!(builder.viable());
+            full = Some(builder.arg_list());
+        }
+    }
+    builder.arg_list();
+});
@@ -0,0 +1,58 @@
+use std::ffi::CString;
+
+/// User jobs run as with --user
+#[derive(Clone, Copy, Debug)]
+pub struct User {
+    pub uid: libc::uid_t,
+    /// Primary group of the user
+    pub gid: libc::gid_t,
+}
+
+/// Looks up a user by name or numeric id
+pub fn parse_user(s: &str) -> Result<User, String> {
+    let name = CString::new(s).map_err(|_| format!("invalid user name '{}'", s))?;
+    // SAFETY: called while parsing arguments, before any other thread could use the static result buffer
+    let entry = match s.parse::<libc::uid_t>() {
+        Ok(uid) => unsafe { libc::getpwuid(uid) },
+        Err(_) => unsafe { libc::getpwnam(name.as_ptr()) },
+    };
+    if entry.is_null() {
+        return Err(format!("unknown user '{}'", s));
+    }
+    // SAFETY: checked for null above, the entry stays valid until the next lookup
+    let entry = unsafe { &*entry };
+    Ok(User {
+        uid: entry.pw_uid,
+        gid: entry.pw_gid,
+    })
+}
+
+/// Looks up a group by name or numeric id
+pub fn parse_group(s: &str) -> Result<libc::gid_t, String> {
+    if let Ok(gid) = s.parse::<libc::gid_t>() {
+        return Ok(gid);
+    }
+    let name = CString::new(s).map_err(|_| format!("invalid group name '{}'", s))?;
+    // SAFETY: same as in parse_user
+    let entry = unsafe { libc::getgrnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(format!("unknown group '{}'", s));
+    }
+    // SAFETY: checked for null above
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_group, parse_user};
+
+    #[test]
+    fn lookup_works() {
+        let root = parse_user("root").unwrap();
+        assert_eq!((root.uid, root.gid), (0, 0));
+        assert_eq!(parse_user("0").unwrap().uid, 0);
+        assert!(parse_user("no-such-user-here").is_err());
+        assert_eq!(parse_group("root"), Ok(0));
+        assert_eq!(parse_group("12"), Ok(12));
+    }
+}
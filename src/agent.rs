@@ -0,0 +1,498 @@
+use crate::output::tag_lines;
+use crate::slots::Slots;
+use crate::status::exit_code;
+use crate::transport::{self, Channel};
+use crate::wakeup;
+use clap::builder::RangedU64ValueParser;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::{env, process, thread};
+use tracing::{debug, info, warn};
+
+#[derive(clap::Args, Debug)]
+pub struct AgentArgs {
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    /// Address to accept controller connections on
+    ///
+    /// Any other than a loopback address needs --token or --tls-client-ca, controllers can run any command.
+    listen: SocketAddr,
+
+    #[arg(short = 'p', long, default_value_t = 16, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    /// Max number of processes this agent runs at the same time, across every controller
+    max_parallelism: usize,
+
+    #[arg(long, requires = "tls_key")]
+    /// Serve connections over TLS using this PEM certificate chain
+    tls_cert: Option<PathBuf>,
+
+    #[arg(long, requires = "tls_cert")]
+    /// PEM private key matching --tls-cert
+    tls_key: Option<PathBuf>,
+
+    #[arg(long, requires = "tls_cert")]
+    /// Require controllers to present a certificate signed by this PEM CA (mutual TLS)
+    tls_client_ca: Option<PathBuf>,
+
+    #[arg(long, env = "PLL_AGENT_TOKEN", hide_env_values = true)]
+    /// Only accept controllers presenting this token
+    token: Option<String>,
+}
+
+// Settings used by a controller to reach its agents, not a doc comment since clap would show it as the about text
+// of pll
+#[derive(clap::Args, Debug)]
+pub struct ControllerArgs {
+    #[arg(long = "agent", value_name = "HOST:PORT")]
+    /// Run jobs on a remote `pll agent` instead of spawning them locally, can be repeated
+    ///
+    /// Jobs go to the agent with the most free slots. Each agent enforces its own slot count on top of
+    /// --max-parallelism. The output of each job is printed once it finishes, with every line tagged with the
+    /// agent address and the job sequence number.
+    pub agents: Vec<String>,
+
+    #[arg(long, requires = "agents")]
+    /// Talk to agents over TLS, trusting certificates signed by this PEM CA
+    agent_ca: Option<PathBuf>,
+
+    #[arg(long, requires_all = ["agent_ca", "agent_key"])]
+    /// PEM client certificate presented to agents requiring mutual TLS
+    agent_cert: Option<PathBuf>,
+
+    #[arg(long, requires = "agent_cert")]
+    /// PEM private key matching --agent-cert
+    agent_key: Option<PathBuf>,
+
+    #[arg(
+        long,
+        env = "PLL_AGENT_TOKEN",
+        hide_env_values = true,
+        requires = "agents"
+    )]
+    /// Token presented to agents started with --token
+    agent_token: Option<String>,
+
+    #[arg(long, value_name = "PATTERN", requires = "agents")]
+    /// Copy the files matching PATTERN in the agent working directory back here with rsync after each job,
+    /// can be repeated
+    ///
+    /// Files are removed from the agent once transferred. The agent host must be reachable over ssh.
+    pub return_rsync: Vec<String>,
+}
+
+/// A message sent by the controller, encoded as a single line of JSON
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ControllerMsg {
+    /// First message of every connection when the controller has a token
+    Auth {
+        token: String,
+    },
+    Run {
+        id: usize,
+        argv: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// A message sent by the agent, encoded as a single line of JSON
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum AgentMsg {
+    /// First message of every connection, announcing how many jobs the agent runs at once and where
+    Hello {
+        slots: usize,
+        #[serde(default)]
+        workdir: Option<PathBuf>,
+    },
+    /// Sent instead of the greeting when the controller failed to authenticate
+    Denied {
+        reason: String,
+    },
+    Output {
+        id: usize,
+        stream: Stream,
+        line: String,
+    },
+    Exit {
+        id: usize,
+        exit_code: i32,
+    },
+}
+
+type SharedWriter = Arc<Mutex<Channel>>;
+
+fn send(writer: &SharedWriter, msg: &AgentMsg) -> io::Result<()> {
+    let mut line = serde_json::to_vec(msg)?;
+    line.push(b'\n');
+    writer.lock().unwrap().write_all(&line)
+}
+
+fn forward_lines(id: usize, stream: Stream, output: impl Read, writer: SharedWriter) {
+    for line in BufReader::new(output).lines() {
+        let Ok(line) = line else { break };
+        if send(&writer, &AgentMsg::Output { id, stream, line }).is_err() {
+            break;
+        }
+    }
+}
+
+fn run_job(id: usize, argv: Vec<String>, writer: SharedWriter) -> io::Result<()> {
+    let exit_code = match process::Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(process::Stdio::null())
+        .stdout(process::Stdio::piped())
+        .stderr(process::Stdio::piped())
+        .spawn()
+    {
+        Ok(mut child) => {
+            let readers = [
+                child.stdout.take().map(|out| {
+                    let writer = writer.clone();
+                    thread::spawn(move || forward_lines(id, Stream::Stdout, out, writer))
+                }),
+                child.stderr.take().map(|err| {
+                    let writer = writer.clone();
+                    thread::spawn(move || forward_lines(id, Stream::Stderr, err, writer))
+                }),
+            ];
+            let status = child.wait()?;
+            for reader in readers.into_iter().flatten() {
+                let _ = reader.join();
+            }
+            exit_code(status)
+        }
+        Err(e) => {
+            let line = format!("unable to spawn process: {}", e);
+            let stream = Stream::Stderr;
+            send(&writer, &AgentMsg::Output { id, stream, line })?;
+            127
+        }
+    };
+    send(&writer, &AgentMsg::Exit { id, exit_code })
+}
+
+/// Compares tokens without bailing out at the first mismatching byte
+pub fn token_matches(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+struct Listener {
+    slots: Arc<Slots>,
+    total: usize,
+    tls: Option<Arc<rustls::ServerConfig>>,
+    token: Option<String>,
+}
+
+fn handle_controller(listener: &Listener, stream: TcpStream) -> io::Result<()> {
+    let stream = match &listener.tls {
+        Some(config) => transport::accept(config, stream)?,
+        None => Channel::Tcp(stream),
+    };
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let mut lines = BufReader::new(stream).lines();
+    if let Some(expected) = &listener.token {
+        let authenticated = match lines.next().transpose()? {
+            Some(line) => match serde_json::from_str(&line) {
+                Ok(ControllerMsg::Auth { token }) => token_matches(expected, &token),
+                _ => false,
+            },
+            None => false,
+        };
+        if !authenticated {
+            let reason = "invalid or missing token".to_owned();
+            return send(&writer, &AgentMsg::Denied { reason });
+        }
+    }
+    send(
+        &writer,
+        &AgentMsg::Hello {
+            slots: listener.total,
+            workdir: env::current_dir().ok(),
+        },
+    )?;
+    for line in lines {
+        match serde_json::from_str::<ControllerMsg>(&line?) {
+            Ok(ControllerMsg::Run { id, argv }) if !argv.is_empty() => {
+                let (slots, writer) = (listener.slots.clone(), writer.clone());
+                thread::spawn(move || {
+                    slots.acquire();
+                    debug!(id, ?argv, "running job");
+                    let result = run_job(id, argv, writer);
+                    slots.release();
+                    if let Err(e) = result {
+                        warn!(id, "failed to report job: {}", e);
+                    }
+                });
+            }
+            Ok(ControllerMsg::Run { id, .. }) => {
+                send(&writer, &AgentMsg::Exit { id, exit_code: 127 })?;
+            }
+            Ok(ControllerMsg::Auth { .. }) => {}
+            Err(e) => warn!("invalid message from controller: {}", e),
+        }
+    }
+    Ok(())
+}
+
+pub fn run(args: AgentArgs) -> i32 {
+    if !args.listen.ip().is_loopback() && args.token.is_none() && args.tls_client_ca.is_none() {
+        eprintln!(
+            "--listen {} can be reached from other machines, it needs --token or --tls-client-ca",
+            args.listen
+        );
+        return 1;
+    }
+    let tls = match (&args.tls_cert, &args.tls_key) {
+        (Some(cert), Some(key)) => {
+            match transport::server_config(cert, key, args.tls_client_ca.as_deref()) {
+                Ok(config) => Some(config),
+                Err(e) => {
+                    eprintln!("unable to load tls configuration: {}", e);
+                    return 1;
+                }
+            }
+        }
+        _ => None,
+    };
+    let tcp_listener = match TcpListener::bind(args.listen) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("unable to listen on {}: {}", args.listen, e);
+            return 1;
+        }
+    };
+    let listener = Arc::new(Listener {
+        slots: Arc::new(Slots::new(args.max_parallelism)),
+        total: args.max_parallelism,
+        tls,
+        token: args.token,
+    });
+    info!(addr = %args.listen, "agent listening");
+    for stream in tcp_listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let listener = listener.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_controller(&listener, stream) {
+                        debug!("controller disconnected: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("failed to accept connection: {}", e),
+        }
+    }
+    0
+}
+
+/// Exit codes reported by an agent, keyed by job id
+#[derive(Default)]
+struct Exits {
+    codes: HashMap<usize, i32>,
+    /// Set once the connection dropped, jobs still pending will never be reported
+    closed: bool,
+}
+
+/// Exit code reported for jobs lost along with the connection to their agent
+const LOST_EXIT_CODE: i32 = 255;
+
+/// Controller side of a connection to an agent
+pub struct AgentConn {
+    pub addr: String,
+    pub slots: usize,
+    /// Directory the agent runs its jobs in, when it told
+    workdir: Option<PathBuf>,
+    writer: Channel,
+    exits: Arc<Mutex<Exits>>,
+    /// Jobs sent to this agent that haven't been reported as finished yet
+    pub in_flight: usize,
+}
+
+/// A job running on an agent
+pub struct RemoteJob {
+    id: usize,
+    /// Index of the agent running the job in the controller's list
+    pub agent: usize,
+    exits: Arc<Mutex<Exits>>,
+}
+
+impl RemoteJob {
+    /// Exit code of the job once the agent reported it finished
+    pub fn try_wait(&self) -> Option<i32> {
+        let mut exits = self.exits.lock().unwrap();
+        match exits.codes.remove(&self.id) {
+            Some(code) => Some(code),
+            None if exits.closed => Some(LOST_EXIT_CODE),
+            None => None,
+        }
+    }
+}
+
+/// Collects what the agent streams back and its exit codes, marking every job as failed if the connection drops
+///
+/// The output of each job is held until it exits and then printed in one piece, every line tagged with the agent
+/// address and the job sequence number so runs spread over many hosts stay readable.
+fn read_agent(addr: String, reader: impl BufRead, exits: Arc<Mutex<Exits>>) {
+    let mut outputs: HashMap<usize, (Vec<u8>, Vec<u8>)> = HashMap::new();
+    for line in reader.lines() {
+        let msg = line.and_then(|l| serde_json::from_str::<AgentMsg>(&l).map_err(io::Error::from));
+        match msg {
+            Ok(AgentMsg::Output { id, stream, line }) => {
+                let (stdout, stderr) = outputs.entry(id).or_default();
+                let buf = match stream {
+                    Stream::Stdout => stdout,
+                    Stream::Stderr => stderr,
+                };
+                buf.extend_from_slice(line.as_bytes());
+                buf.push(b'\n');
+            }
+            Ok(AgentMsg::Exit { id, exit_code }) => {
+                if let Some((stdout, stderr)) = outputs.remove(&id) {
+                    let tag = format!("[{} {}]", addr, id);
+                    print!("{}", tag_lines(&tag, &stdout));
+                    eprint!("{}", tag_lines(&tag, &stderr));
+                }
+                exits.lock().unwrap().codes.insert(id, exit_code);
+                wakeup::notify();
+            }
+            Ok(AgentMsg::Hello { .. } | AgentMsg::Denied { .. }) => {}
+            Err(e) => {
+                warn!(%addr, "lost connection to agent: {}", e);
+                break;
+            }
+        }
+    }
+    exits.lock().unwrap().closed = true;
+    wakeup::notify();
+}
+
+impl AgentConn {
+    fn connect(
+        addr: &str,
+        tls: Option<&Arc<rustls::ClientConfig>>,
+        token: Option<&str>,
+    ) -> io::Result<AgentConn> {
+        let mut writer = match tls {
+            Some(config) => transport::connect(config, addr)?,
+            None => Channel::Tcp(TcpStream::connect(addr)?),
+        };
+        if let Some(token) = token {
+            let auth = ControllerMsg::Auth {
+                token: token.to_owned(),
+            };
+            writeln!(writer, "{}", serde_json::to_string(&auth)?)?;
+        }
+        let mut reader = BufReader::new(writer.try_clone()?);
+        let mut hello = String::new();
+        writer.set_read_timeout(Some(transport::HANDSHAKE_TIMEOUT))?;
+        reader.read_line(&mut hello)?;
+        writer.set_read_timeout(None)?;
+        let (slots, workdir) = match serde_json::from_str(&hello)? {
+            AgentMsg::Hello { slots, workdir } => (slots, workdir),
+            AgentMsg::Denied { reason } => {
+                return Err(io::Error::new(io::ErrorKind::PermissionDenied, reason));
+            }
+            other => {
+                let msg = format!("unexpected greeting from agent: {:?}", other);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+            }
+        };
+        let exits = Arc::new(Mutex::new(Exits::default()));
+        let (thread_addr, thread_exits) = (addr.to_owned(), exits.clone());
+        thread::spawn(move || read_agent(thread_addr, reader, thread_exits));
+        info!(addr, slots, "connected to agent");
+        Ok(AgentConn {
+            addr: addr.to_owned(),
+            slots,
+            workdir,
+            writer,
+            exits,
+            in_flight: 0,
+        })
+    }
+
+    /// Sends a job to the agent, `agent` is the index of this connection in the controller's list and `id` the
+    /// sequence number of the job in the run
+    pub fn run(&mut self, agent: usize, id: usize, argv: Vec<String>) -> io::Result<RemoteJob> {
+        let mut line = serde_json::to_vec(&ControllerMsg::Run { id, argv })?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        self.in_flight += 1;
+        Ok(RemoteJob {
+            id,
+            agent,
+            exits: self.exits.clone(),
+        })
+    }
+
+    /// Moves the files matching `patterns` in the agent working directory to the current directory
+    pub fn fetch(&self, patterns: &[String]) -> io::Result<()> {
+        let workdir = self.workdir.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "agent didn't report its working directory",
+            )
+        })?;
+        let host = transport::host(&self.addr);
+        let sources = patterns
+            .iter()
+            .map(|pattern| format!("{}:{}", host, workdir.join(pattern).display()));
+        let status = process::Command::new("rsync")
+            .args(["--archive", "--remove-source-files"])
+            .args(sources)
+            .arg(".")
+            .stdin(process::Stdio::null())
+            .status()?;
+        // 23 is a partial transfer, which is what rsync reports when a pattern matched nothing
+        match status.code() {
+            Some(0 | 23) => Ok(()),
+            _ => Err(io::Error::other(format!("rsync {}", status))),
+        }
+    }
+}
+
+impl ControllerArgs {
+    /// Opens a connection to every agent
+    pub fn connect(&self) -> io::Result<Vec<AgentConn>> {
+        let tls = match &self.agent_ca {
+            Some(ca) => {
+                let identity = self.agent_cert.as_deref().zip(self.agent_key.as_deref());
+                Some(transport::client_config(ca, identity)?)
+            }
+            None => None,
+        };
+        self.agents
+            .iter()
+            .map(|addr| {
+                AgentConn::connect(addr, tls.as_ref(), self.agent_token.as_deref())
+                    .map_err(|e| io::Error::new(e.kind(), format!("agent {}: {}", addr, e)))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::token_matches;
+
+    #[test]
+    fn token_matches_works() {
+        assert!(token_matches("secret", "secret"));
+        assert!(!token_matches("secret", "secreT"));
+        assert!(!token_matches("secret", "secret2"));
+    }
+}
@@ -0,0 +1,99 @@
+use std::io;
+use std::net::TcpListener;
+
+/// A value reserved for each slot, handed to the jobs running in it through an environment variable and a
+/// placeholder in their arguments
+pub struct Allocation {
+    pub var: String,
+    pub placeholder: String,
+    /// Indexed by slot
+    pub values: Vec<String>,
+}
+
+impl Allocation {
+    /// Replaces the placeholder in every argument with the value of the slot
+    pub fn fill(&self, slot: usize, args: &[String]) -> Vec<String> {
+        let value = &self.values[slot];
+        args.iter()
+            .map(|a| a.replace(&self.placeholder, value))
+            .collect()
+    }
+}
+
+/// Picks `count` distinct free TCP ports for each slot, as `$PLL_PORT`/`{port}`, `$PLL_PORT2`/`{port2}` and so on
+///
+/// Ports are found by letting the kernel pick them, all listeners are kept open until every port is known so
+/// none is picked twice.
+pub fn ports(slots: usize, count: usize) -> io::Result<Vec<Allocation>> {
+    let listeners = (0..slots * count)
+        .map(|_| TcpListener::bind("127.0.0.1:0"))
+        .collect::<io::Result<Vec<_>>>()?;
+    let mut ports = listeners
+        .iter()
+        .map(|l| Ok(l.local_addr()?.port().to_string()))
+        .collect::<io::Result<Vec<_>>>()?
+        .into_iter();
+    let mut allocations: Vec<Allocation> = (0..count)
+        .map(|i| {
+            let suffix = if i == 0 {
+                String::new()
+            } else {
+                (i + 1).to_string()
+            };
+            Allocation {
+                var: format!("PLL_PORT{}", suffix),
+                placeholder: format!("{{port{}}}", suffix),
+                values: vec![],
+            }
+        })
+        .collect();
+    for _ in 0..slots {
+        for allocation in &mut allocations {
+            allocation.values.push(ports.next().unwrap());
+        }
+    }
+    Ok(allocations)
+}
+
+/// Hands out comma separated values, one per slot, as `$PLL_ALLOC`/`{alloc}`
+pub fn values(slots: usize, csv: &str) -> Result<Allocation, String> {
+    let values: Vec<String> = csv.split(',').map(|v| v.trim().to_owned()).collect();
+    if values.len() < slots {
+        return Err(format!(
+            "{} values given for {} slots, one is needed for each",
+            values.len(),
+            slots
+        ));
+    }
+    Ok(Allocation {
+        var: "PLL_ALLOC".into(),
+        placeholder: "{alloc}".into(),
+        values,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ports, values};
+    use std::collections::HashSet;
+
+    #[test]
+    fn ports_works() {
+        let allocations = ports(3, 2).unwrap();
+        assert_eq!(allocations[1].var, "PLL_PORT2");
+        let unique: HashSet<&String> = allocations.iter().flat_map(|a| &a.values).collect();
+        assert_eq!(unique.len(), 6);
+        let args = vec!["--listen=:{port}".to_owned()];
+        let filled = allocations[0].fill(1, &args);
+        assert_eq!(filled[0], format!("--listen=:{}", allocations[0].values[1]));
+    }
+
+    #[test]
+    fn values_works() {
+        assert!(values(3, "a,b").is_err());
+        assert_eq!(
+            values(2, "db1, db2,db3").unwrap().values,
+            ["db1", "db2", "db3"]
+        );
+    }
+}
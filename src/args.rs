@@ -1,21 +1,54 @@
+use crate::output::Shell;
+use crate::placeholder::{self, Part};
+
+/// Program argument replaced by the path of a file holding the records of the job, which aren't appended then
+pub const FILE_PLACEHOLDER: &str = "{f}";
+
 pub struct AppendArgs {
     initial_args: Vec<String>,
     args: Vec<String>,
     max_args: usize,
     min_args: usize,
+    /// Max length of the argument list, counting a separator after each argument
+    max_chars: usize,
+    /// Finalize once the arguments, not counting the initial ones, add up to this many bytes
+    batch_bytes: usize,
+    batched_bytes: usize,
+    /// Append the arguments quoted to the last initial argument, the command line of --shell
+    shell: Option<Shell>,
+}
+
+impl AppendArgs {
+    fn len_chars(&self) -> usize {
+        self.initial_args
+            .iter()
+            .chain(self.args.iter())
+            .map(|a| a.len() + 1)
+            .sum()
+    }
 }
 
 pub trait ArgBuilder {
     fn push_arg(&mut self, arg: &str) -> bool;
     fn arg_list(&self) -> Vec<String>;
+    /// The arguments pushed so far, without the ones given up front
+    fn inputs(&self) -> Vec<String>;
     fn viable(&self) -> bool;
+    /// Whether `arg` can be pushed without going over the size limit
+    fn fits(&self, arg: &str) -> bool;
+    /// Index in the argument list of the arguments with `{#}`, which are filled in once the job gets its sequence
+    /// number
+    fn numbered(&self) -> Vec<usize> {
+        vec![]
+    }
 }
 
 impl ArgBuilder for AppendArgs {
     fn push_arg(&mut self, arg: &str) -> bool {
         assert!(self.args.len() <= self.max_args);
         self.args.push(arg.to_owned());
-        self.args.len() == self.max_args
+        self.batched_bytes += arg.len();
+        self.args.len() == self.max_args || self.batched_bytes >= self.batch_bytes
     }
 
     /// Returns true when the execution is finalized (ie cannot accept more arguments)
@@ -23,24 +56,60 @@ impl ArgBuilder for AppendArgs {
         self.args.len() >= self.min_args
     }
 
+    fn fits(&self, arg: &str) -> bool {
+        // the new argument takes one more character for its separator
+        self.len_chars() + arg.len() < self.max_chars
+    }
+
     fn arg_list(&self) -> Vec<String> {
+        if self
+            .initial_args
+            .iter()
+            .any(|a| a.contains(FILE_PLACEHOLDER))
+        {
+            return self.initial_args.clone();
+        }
+        if let Some(shell) = self.shell {
+            let mut arg_list = self.initial_args.clone();
+            if let Some(line) = arg_list.last_mut() {
+                for arg in &self.args {
+                    line.push(' ');
+                    line.push_str(&shell.quote(arg));
+                }
+            }
+            return arg_list;
+        }
         self.initial_args
             .iter()
             .cloned()
             .chain(self.args.iter().cloned())
             .collect()
     }
+
+    fn inputs(&self) -> Vec<String> {
+        self.args.clone()
+    }
 }
 
 pub struct TemplateArgs {
     arg_list: Vec<TemplateArg>,
     idx: usize,
     finalized_count: usize,
+    inputs: Vec<String>,
+    /// Number of inputs filling every placeholder
+    arity: usize,
+    /// Quote the inputs for this shell, with --shell
+    quote: Option<Shell>,
+    numbered: Vec<usize>,
 }
 
 enum TemplateArg {
     IndexedPlaceHolder(usize),
     Value(String),
+    /// Text and placeholders with filters or arithmetic, rendered once the input of the given index was pushed
+    Computed(Vec<Part>, usize),
+    /// Word with `{#}` kept as is once the input of the given index was pushed, for `fill_numbered`
+    Numbered(String, usize),
 }
 
 pub enum ArgBuilderType {
@@ -63,35 +132,76 @@ impl ArgBuilder for ArgBuilderType {
         }
     }
 
+    fn inputs(&self) -> Vec<String> {
+        match self {
+            ArgBuilderType::Append(append) => append.inputs(),
+            ArgBuilderType::Template(template) => template.inputs(),
+        }
+    }
+
     fn viable(&self) -> bool {
         match self {
             ArgBuilderType::Append(append) => append.viable(),
             ArgBuilderType::Template(template) => template.viable(),
         }
     }
+
+    fn fits(&self, arg: &str) -> bool {
+        match self {
+            ArgBuilderType::Append(append) => append.fits(arg),
+            ArgBuilderType::Template(template) => template.fits(arg),
+        }
+    }
+
+    fn numbered(&self) -> Vec<usize> {
+        match self {
+            ArgBuilderType::Append(append) => append.numbered(),
+            ArgBuilderType::Template(template) => template.numbered(),
+        }
+    }
 }
 
 impl TemplateArgs {
-    fn new(templ: Vec<String>) -> Result<TemplateArgs, String> {
-        let arg_list: Vec<TemplateArg> = templ
+    /// Words without a placeholder are kept as they are, braces included, like `{print $1}` for awk
+    pub fn new(templ: Vec<String>) -> Result<TemplateArgs, String> {
+        let words = templ
             .iter()
-            .enumerate()
-            .map(|(idx, val)| match (val.find("{"), val.find("}")) {
-                (None, None) => TemplateArg::Value(val.clone()),
-                (Some(_), None) => TemplateArg::IndexedPlaceHolder(idx),
-                (None, Some(_)) => TemplateArg::IndexedPlaceHolder(idx),
-                (Some(i), Some(j)) => {
-                    let actual_idx = if j < i || j == i + 1 {
-                        idx
-                    } else if let Ok(templ_idx) = val[i + 1..j].parse::<usize>() {
-                        templ_idx
-                    } else {
-                        idx
-                    };
-                    TemplateArg::IndexedPlaceHolder(actual_idx)
-                }
-            })
-            .collect();
+            .map(|word| placeholder::parse_word(word))
+            .collect::<Result<Vec<_>, _>>()?;
+        let arity = words
+            .iter()
+            .flatten()
+            .map(|parts| placeholder::max_idx(parts) + 1)
+            .max()
+            .unwrap_or(0);
+        let mut arg_list = vec![];
+        let mut numbered = vec![];
+        for (idx, (word, parts)) in templ.into_iter().zip(words).enumerate() {
+            // `{}` takes every input, so it waits for the last one
+            let ready_at = parts
+                .as_ref()
+                .map(|parts| match placeholder::uses_all(parts) {
+                    true => arity - 1,
+                    false => placeholder::max_idx(parts),
+                });
+            if word.contains("{#}") {
+                numbered.push(idx);
+                arg_list.push(match ready_at {
+                    Some(at) => TemplateArg::Numbered(word, at),
+                    None => TemplateArg::Value(word),
+                });
+                continue;
+            }
+            arg_list.push(match (parts, ready_at) {
+                (Some(parts), Some(at)) => match &parts[..] {
+                    [Part::Field(field)] if field.is_plain() => {
+                        TemplateArg::IndexedPlaceHolder(field.idx)
+                    }
+                    _ => TemplateArg::Computed(parts, at),
+                },
+                _ => TemplateArg::Value(word),
+            });
+        }
         let finalized_count = arg_list
             .iter()
             .filter(|x| matches!(x, TemplateArg::Value(_)))
@@ -100,20 +210,43 @@ impl TemplateArgs {
             arg_list,
             idx: 0,
             finalized_count,
+            inputs: vec![],
+            arity,
+            quote: None,
+            numbered,
         })
     }
+
+    /// Number of inputs it takes to fill every placeholder
+    pub fn arity(&self) -> usize {
+        self.arity
+    }
 }
 
 impl ArgBuilder for TemplateArgs {
     fn push_arg(&mut self, arg: &str) -> bool {
-        assert!(!self.viable());
+        // a full template has nowhere to put the record
+        if self.viable() {
+            return true;
+        }
+        self.inputs.push(arg.to_owned());
         for i in 0..self.arg_list.len() {
-            if let TemplateArg::IndexedPlaceHolder(templ_idx) = self.arg_list[i] {
-                if self.idx == templ_idx {
-                    self.arg_list[i] = TemplateArg::Value(arg.to_owned());
-                    self.finalized_count += 1;
+            let value = match &self.arg_list[i] {
+                TemplateArg::IndexedPlaceHolder(templ_idx) if self.idx == *templ_idx => {
+                    match self.quote {
+                        Some(shell) => shell.quote(arg),
+                        None => arg.to_owned(),
+                    }
                 }
-            }
+                TemplateArg::Computed(parts, at) if self.idx == *at => match self.quote {
+                    Some(shell) => placeholder::render_quoted(parts, &self.inputs, shell),
+                    None => placeholder::render(parts, &self.inputs),
+                },
+                TemplateArg::Numbered(word, at) if self.idx == *at => word.clone(),
+                _ => continue,
+            };
+            self.arg_list[i] = TemplateArg::Value(value);
+            self.finalized_count += 1;
         }
         self.idx += 1;
         self.viable()
@@ -129,12 +262,75 @@ impl ArgBuilder for TemplateArgs {
             .collect()
     }
 
+    fn inputs(&self) -> Vec<String> {
+        self.inputs.clone()
+    }
+
+    /// A template without placeholder still takes a record per job, like GNU parallel
     fn viable(&self) -> bool {
-        self.finalized_count == self.arg_list.len()
+        self.finalized_count == self.arg_list.len() && self.inputs.len() >= self.arity.max(1)
+    }
+
+    fn fits(&self, _arg: &str) -> bool {
+        true
+    }
+
+    fn numbered(&self) -> Vec<usize> {
+        self.numbered.clone()
+    }
+}
+/// Builds the argv of a follow-up command from its words, filling `{}` with the inputs of the job
+///
+/// A word that is only `{}` becomes one word per input, other words get the inputs joined with spaces. The inputs
+/// are appended when no word has `{}`.
+pub fn fill_words(words: &[String], inputs: &[String]) -> Vec<String> {
+    if !words.iter().any(|w| w.contains("{}")) {
+        return words.iter().chain(inputs).cloned().collect();
+    }
+    let mut argv = vec![];
+    for word in words {
+        if word == "{}" {
+            argv.extend(inputs.iter().cloned());
+        } else {
+            argv.push(word.replace("{}", &inputs.join(" ")));
+        }
+    }
+    argv
+}
+
+/// Builds the argv a --wrap command puts in front of each job, replacing `{#}` with the job sequence number
+///
+/// `{}` is filled like in `fill_words`, though the inputs are never appended, and `{N}` placeholders work like in
+/// templates. An input the job doesn't have is empty.
+pub fn wrap_words(words: &[String], seq: usize, inputs: &[String]) -> Vec<String> {
+    let mut argv = vec![];
+    for word in words {
+        if word == "{}" {
+            argv.extend(inputs.iter().cloned());
+            continue;
+        }
+        // the word comes from the command line, no input was put in it yet
+        let word = word.replace("{#}", &seq.to_string());
+        let word = match placeholder::parse_word(&word) {
+            Ok(Some(parts)) => {
+                let mut inputs = inputs.to_vec();
+                inputs.resize(
+                    inputs.len().max(placeholder::max_idx(&parts) + 1),
+                    String::new(),
+                );
+                placeholder::render(&parts, &inputs)
+            }
+            _ => word,
+        };
+        argv.push(word);
     }
+    argv
 }
+
 pub trait ArgBuilderMaker<T: ArgBuilder> {
     fn make(&self) -> T;
+    /// Fills in an argument the builder reported as numbered, for the job with sequence number `seq`
+    fn fill_numbered(&self, arg: &str, seq: usize, inputs: &[String]) -> String;
 }
 
 pub struct DynArgBuilderMaker {
@@ -142,26 +338,74 @@ pub struct DynArgBuilderMaker {
     pub initial_args: Vec<String>,
     pub max_args: usize,
     pub min_args: usize,
+    pub max_chars: usize,
+    pub batch_bytes: usize,
+    /// With --shell, the initial arguments are the flags of the shell and a command line the inputs are quoted for
+    pub shell: Option<Shell>,
+}
+
+impl DynArgBuilderMaker {
+    /// Appends up to `max_args` records to `initial_args` for each job
+    pub fn append(initial_args: Vec<String>, max_args: usize) -> DynArgBuilderMaker {
+        DynArgBuilderMaker {
+            is_template: false,
+            initial_args,
+            max_args,
+            min_args: 1,
+            max_chars: usize::MAX,
+            batch_bytes: usize::MAX,
+            shell: None,
+        }
+    }
+
+    /// Fills in the placeholders of `template` with the records of each job, failing when one is invalid
+    pub fn template(template: Vec<String>) -> Result<DynArgBuilderMaker, String> {
+        TemplateArgs::new(template.clone())?;
+        Ok(DynArgBuilderMaker {
+            is_template: true,
+            ..DynArgBuilderMaker::append(template, usize::MAX)
+        })
+    }
 }
 
 impl ArgBuilderMaker<ArgBuilderType> for DynArgBuilderMaker {
     fn make(&self) -> ArgBuilderType {
         if self.is_template {
-            ArgBuilderType::Template(TemplateArgs::new(self.initial_args.clone()).unwrap())
+            let mut template = TemplateArgs::new(self.initial_args.clone()).unwrap();
+            template.quote = self.shell;
+            ArgBuilderType::Template(template)
         } else {
             ArgBuilderType::Append(AppendArgs {
                 initial_args: self.initial_args.clone(),
                 args: vec![],
                 max_args: self.max_args,
                 min_args: self.min_args,
+                max_chars: self.max_chars,
+                batch_bytes: self.batch_bytes,
+                batched_bytes: 0,
+                shell: self.shell,
             })
         }
     }
+
+    fn fill_numbered(&self, arg: &str, seq: usize, inputs: &[String]) -> String {
+        // the word comes from the template, no input was put in it yet
+        let arg = arg.replace("{#}", &seq.to_string());
+        match (placeholder::parse_word(&arg), self.shell) {
+            (Ok(Some(parts)), Some(shell)) => placeholder::render_quoted(&parts, inputs, shell),
+            (Ok(Some(parts)), None) => placeholder::render(&parts, inputs),
+            _ => arg,
+        }
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::{AppendArgs, ArgBuilder, TemplateArgs};
+    use super::{
+        fill_words, wrap_words, AppendArgs, ArgBuilder, ArgBuilderMaker, DynArgBuilderMaker,
+        TemplateArgs,
+    };
+    use crate::output::Shell;
 
     #[test]
     fn append_args_works() {
@@ -170,10 +414,17 @@ mod test {
             args: vec![],
             max_args: 2,
             min_args: 1,
+            max_chars: usize::MAX,
+            batch_bytes: usize::MAX,
+            batched_bytes: 0,
+            shell: None,
         };
         builder.push_arg("foo");
         assert!(builder.push_arg("bar"));
         assert_eq!(builder.arg_list(), ["initial", "foo", "bar"]);
+        builder.initial_args.push("--input={f}".into());
+        assert_eq!(builder.arg_list(), ["initial", "--input={f}"]);
+        assert_eq!(builder.inputs(), ["foo", "bar"]);
     }
 
     #[test]
@@ -183,12 +434,86 @@ mod test {
             args: vec![],
             max_args: 2,
             min_args: 1,
+            max_chars: usize::MAX,
+            batch_bytes: usize::MAX,
+            batched_bytes: 0,
+            shell: None,
         };
         assert!(!builder.push_arg("foo"));
         assert!(builder.viable());
         assert_eq!(builder.arg_list(), ["initial", "foo"]);
     }
 
+    #[test]
+    fn append_args_max_chars_works() {
+        let mut builder = AppendArgs {
+            initial_args: vec!["initial".into()],
+            args: vec![],
+            max_args: 3,
+            min_args: 1,
+            max_chars: 15,
+            batch_bytes: usize::MAX,
+            batched_bytes: 0,
+            shell: None,
+        };
+        assert!(builder.fits("foo"));
+        builder.push_arg("foo");
+        assert!(builder.fits("ba"));
+        assert!(!builder.fits("bar"));
+    }
+
+    #[test]
+    fn append_args_batch_bytes_works() {
+        let mut builder = AppendArgs {
+            initial_args: vec!["initial".into()],
+            args: vec![],
+            max_args: usize::MAX,
+            min_args: 1,
+            max_chars: usize::MAX,
+            batch_bytes: 6,
+            batched_bytes: 0,
+            shell: None,
+        };
+        assert!(!builder.push_arg("foo"));
+        assert!(!builder.push_arg("ba"));
+        assert!(builder.push_arg("baz"));
+        assert_eq!(builder.arg_list(), ["initial", "foo", "ba", "baz"]);
+    }
+
+    #[test]
+    fn fill_words_works() {
+        let words = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+        let inputs = words("a b");
+        assert_eq!(fill_words(&words("rm -f"), &inputs), ["rm", "-f", "a", "b"]);
+        assert_eq!(
+            fill_words(&words("cp {} out/"), &inputs),
+            ["cp", "a", "b", "out/"]
+        );
+        assert_eq!(
+            fill_words(&words("touch {}.done"), &inputs),
+            ["touch", "a b.done"]
+        );
+    }
+
+    #[test]
+    fn wrap_words_works() {
+        let words = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+        let inputs = vec!["in/a.txt".to_owned(), "b".to_owned()];
+        assert_eq!(
+            wrap_words(&words("strace -o {#}.{0|basename}.trace --"), 4, &inputs),
+            ["strace", "-o", "4.a.txt.trace", "--"]
+        );
+        assert_eq!(
+            wrap_words(&words("echo {} {2}x"), 0, &inputs),
+            ["echo", "in/a.txt", "b", "x"]
+        );
+        let inputs = vec!["a{}b{#}".to_owned()];
+        assert_eq!(
+            wrap_words(&words("echo W{} {#}"), 2, &inputs),
+            ["echo", "Wa{}b{#}", "2"]
+        );
+    }
+
     #[test]
     fn template_args_works() {
         let mut builder =
@@ -196,5 +521,77 @@ mod test {
         builder.push_arg("foo");
         assert!(builder.push_arg("bar"));
         assert_eq!(builder.arg_list(), ["initial", "foo", "bar"]);
+
+        let mut builder =
+            TemplateArgs::new(vec!["{1}".into(), "{0|upper}-{1+1}.out".into()]).unwrap();
+        assert_eq!(builder.arity(), 2);
+        assert!(!builder.push_arg("a"));
+        assert!(builder.push_arg("1"));
+        assert_eq!(builder.arg_list(), ["1", "A-2.out"]);
+
+        let words = ["{print $1}", "{0}{0}", "{1}", "{/.}", "{#}-{0}"];
+        let mut builder = TemplateArgs::new(words.map(String::from).to_vec()).unwrap();
+        assert_eq!(builder.arity(), 2);
+        assert!(!builder.push_arg("a/x.c"));
+        assert!(builder.push_arg("b"));
+        assert_eq!(
+            builder.arg_list(),
+            ["{print $1}", "a/x.ca/x.c", "b", "x b", "{#}-{0}"]
+        );
+        assert_eq!(builder.numbered(), [4]);
+    }
+
+    #[test]
+    fn template_without_placeholder_works() {
+        let mut builder = TemplateArgs::new(vec!["hi".into()]).unwrap();
+        assert!(!builder.viable());
+        assert!(builder.push_arg("a"));
+        assert_eq!(builder.arg_list(), ["hi"]);
+        assert_eq!(builder.inputs(), ["a"]);
+        // the record is dropped, the template being full already
+        assert!(builder.push_arg("b"));
+        assert_eq!(builder.inputs(), ["a"]);
+        let mut builder = TemplateArgs::new(vec!["{#}".into()]).unwrap();
+        assert!(builder.push_arg("a"));
+        assert_eq!(builder.arg_list(), ["{#}"]);
+        assert_eq!(builder.numbered(), [0]);
+    }
+
+    #[test]
+    fn shell_works() {
+        let maker = DynArgBuilderMaker {
+            is_template: false,
+            initial_args: vec!["-c".into(), "wc -l".into()],
+            max_args: usize::MAX,
+            min_args: 0,
+            max_chars: usize::MAX,
+            batch_bytes: usize::MAX,
+            shell: Some(Shell::Sh),
+        };
+        let mut builder = maker.make();
+        builder.push_arg("a b");
+        builder.push_arg("c");
+        assert_eq!(builder.arg_list(), ["-c", "wc -l 'a b' c"]);
+
+        let maker = DynArgBuilderMaker {
+            is_template: true,
+            initial_args: vec!["-c".into(), "cat {} > {.}-{#}.txt".into()],
+            ..maker
+        };
+        let mut builder = maker.make();
+        assert!(builder.push_arg("it's.md"));
+        let inputs = builder.inputs();
+        assert_eq!(
+            maker.fill_numbered(&builder.arg_list()[1], 3, &inputs),
+            "cat 'it'\\''s.md' > 'it'\\''s'-3.txt"
+        );
+        let maker = DynArgBuilderMaker {
+            initial_args: vec!["-Command".into(), "Get-Content {}".into()],
+            shell: Some(Shell::Pwsh),
+            ..maker
+        };
+        let mut builder = maker.make();
+        assert!(builder.push_arg("it's.md"));
+        assert_eq!(builder.arg_list(), ["-Command", "Get-Content 'it''s.md'"]);
     }
 }
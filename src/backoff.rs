@@ -0,0 +1,91 @@
+use crate::stop::parse_duration;
+use std::time::{Duration, Instant};
+
+/// Circuit breaker pausing dispatch after a streak of failed jobs
+///
+/// Every streak of `failures` failures in a row pauses for twice as long as the previous one, a successful job
+/// brings the pause back to its initial length. Failures during a pause are from jobs started before it and
+/// aren't counted.
+#[derive(Clone, Debug)]
+pub struct Backoff {
+    failures: usize,
+    pause: Duration,
+    next_pause: Duration,
+    streak: usize,
+    paused_until: Option<Instant>,
+}
+
+impl Backoff {
+    /// Parses `N:DURATION`, like `5:30s`
+    pub fn parse(s: &str) -> Result<Backoff, String> {
+        let (failures, pause) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected N:DURATION, got '{}'", s))?;
+        let failures = match failures.parse::<usize>() {
+            Ok(n) if n > 0 => n,
+            _ => return Err(format!("invalid failure count '{}'", failures)),
+        };
+        let pause = parse_duration(pause)?;
+        Ok(Backoff {
+            failures,
+            pause,
+            next_pause: pause,
+            streak: 0,
+            paused_until: None,
+        })
+    }
+
+    /// Accounts for a finished job, returning how long dispatch pauses when it ends a streak of failures
+    pub fn job_finished(&mut self, success: bool) -> Option<Duration> {
+        if success {
+            self.streak = 0;
+            self.next_pause = self.pause;
+            return None;
+        }
+        if self.remaining().is_some() {
+            // jobs started before the pause don't make it any longer
+            return None;
+        }
+        self.streak += 1;
+        if self.streak < self.failures {
+            return None;
+        }
+        let pause = self.next_pause;
+        self.streak = 0;
+        self.next_pause = pause.saturating_mul(2);
+        self.paused_until = Some(Instant::now() + pause);
+        Some(pause)
+    }
+
+    /// Time left before jobs can be started again
+    pub fn remaining(&self) -> Option<Duration> {
+        self.paused_until?
+            .checked_duration_since(Instant::now())
+            .filter(|d| !d.is_zero())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Backoff;
+    use std::time::Duration;
+
+    #[test]
+    fn backoff_works() {
+        assert!(Backoff::parse("0:1s").is_err());
+        assert!(Backoff::parse("3").is_err());
+        let mut backoff = Backoff::parse("2:10s").unwrap();
+        assert_eq!(backoff.job_finished(false), None);
+        assert_eq!(backoff.remaining(), None);
+        assert_eq!(backoff.job_finished(false), Some(Duration::from_secs(10)));
+        assert!(backoff.remaining().is_some());
+        assert_eq!(backoff.job_finished(false), None);
+        backoff.paused_until = None;
+        backoff.job_finished(false);
+        assert_eq!(backoff.job_finished(false), Some(Duration::from_secs(20)));
+        backoff.paused_until = None;
+        backoff.job_finished(true);
+        backoff.job_finished(false);
+        assert_eq!(backoff.job_finished(false), Some(Duration::from_secs(10)));
+    }
+}
@@ -0,0 +1,341 @@
+use crate::output::shell_quote;
+use crate::status::pid_alive;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+#[derive(clap::Args, Debug)]
+pub struct StatusArgs {
+    /// Handle printed by `pll --bg`, every known run is listed when omitted
+    handle: Option<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct AttachArgs {
+    /// Handle printed by `pll --bg`
+    handle: String,
+}
+
+/// Progress of a detached run, rewritten as its jobs start and finish
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RunState {
+    pub pid: u32,
+    pub command: Vec<String>,
+    /// Seconds since the epoch
+    pub started_at: u64,
+    #[serde(default)]
+    pub finished_at: Option<u64>,
+    pub spawned: usize,
+    pub finished: usize,
+    pub failed: usize,
+    /// Set once all the input was read, from then on `spawned` is the final job count
+    pub input_done: bool,
+    /// Expected job count given up front, for inputs whose length isn't known until they end
+    #[serde(default)]
+    pub total: Option<usize>,
+    /// Seconds left as predicted from the durations of past runs, when every job left has one
+    #[serde(default)]
+    pub eta: Option<u64>,
+    pub done: bool,
+}
+
+/// Directory holding one subdirectory per detached run, named after its handle
+pub fn runs_dir() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .map_or_else(env::temp_dir, PathBuf::from)
+        .join("pll-runs")
+}
+
+pub fn state_path(dir: &Path) -> PathBuf {
+    dir.join("state.json")
+}
+
+pub fn output_path(dir: &Path) -> PathBuf {
+    dir.join("output.log")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// A run going on in the background, keeping its state file up to date
+pub struct Run {
+    dir: PathBuf,
+    state: RunState,
+}
+
+impl Run {
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn save(&self) {
+        // written aside and renamed so readers never see a partial file
+        let tmp = self.dir.join("state.json.tmp");
+        let result = serde_json::to_vec(&self.state)
+            .map_err(io::Error::from)
+            .and_then(|json| fs::write(&tmp, json))
+            .and_then(|_| fs::rename(&tmp, state_path(&self.dir)));
+        if let Err(e) = result {
+            warn!("failed to save run state: {}", e);
+        }
+    }
+
+    pub fn set_total(&mut self, total: usize) {
+        self.state.total = Some(total);
+        self.save();
+    }
+
+    pub fn job_spawned(&mut self) {
+        self.state.spawned += 1;
+        self.save();
+    }
+
+    pub fn job_finished(&mut self, exit_code: i32) {
+        self.state.finished += 1;
+        if exit_code != 0 {
+            self.state.failed += 1;
+        }
+        self.save();
+    }
+
+    pub fn set_eta(&mut self, eta: Option<u64>) {
+        if self.state.eta != eta {
+            self.state.eta = eta;
+            self.save();
+        }
+    }
+
+    pub fn input_done(&mut self) {
+        self.state.input_done = true;
+        self.save();
+    }
+
+    pub fn finish(&mut self) {
+        self.state.done = true;
+        self.state.finished_at = Some(now());
+        self.save();
+    }
+}
+
+/// Forks into a new session detached from the terminal, the parent prints the run handle and exits
+///
+/// Stdout and stderr of the run, and so of its jobs, go to the output log of the run directory. Stdin is kept so
+/// arguments piped into pll are still read. Must be called before any thread is started.
+pub fn detach(command: Vec<String>) -> io::Result<Run> {
+    fs::create_dir_all(runs_dir())?;
+    // SAFETY: no other thread is running yet so the child starts from a consistent state
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if pid > 0 {
+        // created here too so the handle is usable as soon as it is printed
+        let dir = runs_dir().join(pid.to_string());
+        fs::create_dir_all(&dir)?;
+        println!("{}", pid);
+        eprintln!(
+            "running in the background, check on it with `pll status {}`, `pll attach {}` or `pll top {}`",
+            pid, pid, pid
+        );
+        process::exit(0);
+    }
+
+    let dir = runs_dir().join(process::id().to_string());
+    fs::create_dir_all(&dir)?;
+    let log = File::create(output_path(&dir))?;
+    // SAFETY: plain syscalls on file descriptors owned by this process
+    unsafe {
+        libc::setsid();
+        libc::dup2(log.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(log.as_raw_fd(), libc::STDERR_FILENO);
+    }
+    let run = Run {
+        dir,
+        state: RunState {
+            pid: process::id(),
+            command,
+            started_at: now(),
+            ..RunState::default()
+        },
+    };
+    run.save();
+    Ok(run)
+}
+
+fn load(dir: &Path) -> io::Result<RunState> {
+    Ok(serde_json::from_slice(&fs::read(state_path(dir))?)?)
+}
+
+/// One line summary of a run, `now` being in seconds since the epoch
+fn describe(handle: &str, state: &RunState, alive: bool, now: u64) -> String {
+    let elapsed = state
+        .finished_at
+        .unwrap_or(now)
+        .saturating_sub(state.started_at);
+    let status = match (state.done, alive) {
+        (true, _) => "done",
+        (false, true) => "running",
+        (false, false) => "lost",
+    };
+    // without a hint the total is only known once the input is exhausted
+    let total = match state.total {
+        Some(total) if !state.input_done => Some(total.max(state.spawned)),
+        _ => state.input_done.then_some(state.spawned),
+    };
+    let mut line = format!("{} {}: {}/", handle, status, state.finished);
+    match total {
+        Some(total) => line += &total.to_string(),
+        None => line += &format!("{}+", state.spawned),
+    }
+    line += &format!(
+        " jobs finished, {} failed, {}s elapsed",
+        state.failed, elapsed
+    );
+    if let Some(total) = total.filter(|&t| t > 0 && !state.done) {
+        line += &format!(", {}%", state.finished * 100 / total);
+        if let (true, Some(eta)) = (alive, state.eta) {
+            line += &format!(", eta {}s", eta);
+        } else if alive && state.finished > 0 {
+            let remaining = (total - state.finished) as u64;
+            line += &format!(", eta {}s", elapsed * remaining / state.finished as u64);
+        }
+    }
+    line
+}
+
+fn state_of(handle: &str) -> io::Result<(PathBuf, RunState)> {
+    let dir = runs_dir().join(handle);
+    match load(&dir) {
+        Ok(state) => Ok((dir, state)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(io::Error::new(
+            e.kind(),
+            format!("no background run {}", handle),
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn status(args: StatusArgs) -> i32 {
+    let handles = match args.handle {
+        Some(handle) => vec![handle],
+        None => match fs::read_dir(runs_dir()) {
+            Ok(entries) => {
+                let mut handles: Vec<String> = entries
+                    .filter_map(|e| e.ok()?.file_name().into_string().ok())
+                    .collect();
+                handles.sort_by_key(|h| h.parse::<u32>().unwrap_or(u32::MAX));
+                handles
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+            Err(e) => {
+                eprintln!("unable to list background runs: {}", e);
+                return 1;
+            }
+        },
+    };
+    let mut code = 0;
+    for handle in handles {
+        match state_of(&handle) {
+            Ok((_, state)) => {
+                let alive = pid_alive(state.pid as i32);
+                println!("{}", describe(&handle, &state, alive, now()));
+                let command: Vec<String> = state.command.iter().map(|a| shell_quote(a)).collect();
+                println!("  {}", command.join(" "));
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                code = 1;
+            }
+        }
+    }
+    code
+}
+
+/// Streams the output log of a run until it finishes, exiting with 1 if any of its jobs failed
+pub fn attach(args: AttachArgs) -> i32 {
+    let (dir, _) = match state_of(&args.handle) {
+        Ok(found) => found,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let mut log = match File::open(output_path(&dir)) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("unable to open output of run {}: {}", args.handle, e);
+            return 1;
+        }
+    };
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        // read the state first so output written right before the run finished isn't missed
+        let state = load(&dir);
+        loop {
+            match log.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    let _ = io::stdout().write_all(&buf[..n]);
+                }
+                Err(e) => {
+                    eprintln!("failed to read output: {}", e);
+                    return 1;
+                }
+            }
+        }
+        let _ = io::stdout().flush();
+        match state {
+            Ok(state) if state.done => return (state.failed > 0) as i32,
+            Ok(state) if !pid_alive(state.pid as i32) => {
+                eprintln!("run {} exited before finishing", args.handle);
+                return 1;
+            }
+            Ok(_) => {}
+            Err(e) => warn!("failed to read run state: {}", e),
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{describe, RunState};
+
+    #[test]
+    fn describe_works() {
+        let mut state = RunState {
+            started_at: 100,
+            spawned: 10,
+            finished: 4,
+            failed: 1,
+            ..RunState::default()
+        };
+        assert_eq!(
+            describe("7", &state, true, 120),
+            "7 running: 4/10+ jobs finished, 1 failed, 20s elapsed"
+        );
+        state.total = Some(20);
+        assert_eq!(
+            describe("7", &state, true, 120),
+            "7 running: 4/20 jobs finished, 1 failed, 20s elapsed, 20%, eta 80s"
+        );
+        state.input_done = true;
+        assert_eq!(
+            describe("7", &state, true, 120),
+            "7 running: 4/10 jobs finished, 1 failed, 20s elapsed, 40%, eta 30s"
+        );
+        state.eta = Some(5);
+        assert!(describe("7", &state, true, 120).ends_with("40%, eta 5s"));
+        assert!(describe("7", &state, false, 120).starts_with("7 lost:"));
+    }
+}
@@ -0,0 +1,124 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// FNV-1a, stable across runs and builds unlike the hasher of the standard library
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Hash of a record, along with the contents of the file it names when there is one
+fn hash(record: &str) -> io::Result<u64> {
+    let mut hasher = Fnv::new();
+    hasher.write(record.as_bytes());
+    if Path::new(record).is_file() {
+        hasher.write(b"\0");
+        let mut file = File::open(record)?;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            match file.read(&mut buf)? {
+                0 => break,
+                n => hasher.write(&buf[..n]),
+            }
+        }
+    }
+    Ok(hasher.0)
+}
+
+/// Hashes of the records of the jobs that succeeded, kept between runs with --changed-only
+pub struct Changes {
+    path: PathBuf,
+    done: HashSet<u64>,
+    /// Hashed when the record was read, the job may well change its own input
+    pending: HashMap<String, u64>,
+}
+
+impl Changes {
+    /// Loads the cache, one hash per line, starting from an empty one when there is none
+    pub fn load(path: PathBuf) -> io::Result<Changes> {
+        let done = match fs::read_to_string(&path) {
+            Ok(hashes) => hashes
+                .lines()
+                .map(|line| u64::from_str_radix(line, 16))
+                .collect::<Result<_, _>>()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Changes {
+            path,
+            done,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Whether a job already succeeded for the record as it is now
+    ///
+    /// Records that can't be hashed, like files that can't be read, always count as changed.
+    pub fn unchanged(&mut self, record: &str) -> bool {
+        let Ok(hash) = hash(record) else {
+            return false;
+        };
+        if self.done.contains(&hash) {
+            return true;
+        }
+        self.pending.insert(record.to_owned(), hash);
+        false
+    }
+
+    /// Accounts for a finished job, only the records of the ones that succeeded are remembered
+    pub fn finished(&mut self, records: &[String], success: bool) {
+        for record in records {
+            if let (Some(hash), true) = (self.pending.remove(record), success) {
+                self.done.insert(hash);
+            }
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut hashes: Vec<String> = self.done.iter().map(|h| format!("{:016x}", h)).collect();
+        hashes.sort();
+        hashes.push(String::new());
+        // written aside and renamed so an interrupted run never leaves a partial cache
+        let mut tmp = self.path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, hashes.join("\n"))?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Changes;
+    use std::collections::{HashMap, HashSet};
+    use std::path::PathBuf;
+
+    #[test]
+    fn changes_work() {
+        let mut changes = Changes {
+            path: PathBuf::new(),
+            done: HashSet::new(),
+            pending: HashMap::new(),
+        };
+        let records = ["a".to_owned(), "b".to_owned()];
+        assert!(!changes.unchanged("a"));
+        assert!(!changes.unchanged("b"));
+        changes.finished(&records[..1], true);
+        changes.finished(&records[1..], false);
+        assert!(changes.unchanged("a"));
+        assert!(!changes.unchanged("b"));
+        assert!(changes.pending.contains_key("b"));
+    }
+}
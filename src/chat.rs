@@ -0,0 +1,136 @@
+use crate::output::shell_quote;
+use crate::status::{Failures, Outcome};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+/// Least time between two messages about failed jobs, failures in between are only counted
+const FAILURE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Service receiving --on-fail-notify messages
+#[derive(Clone, Debug)]
+pub enum Target {
+    /// Topic URL of an ntfy server, posted the message as plain text
+    Ntfy(String),
+    /// Slack incoming webhook, posted the message as JSON
+    Slack(String),
+}
+
+/// Parses `ntfy://HOST/TOPIC` or the URL of a Slack incoming webhook
+pub fn parse_target(s: &str) -> Result<Target, String> {
+    if let Some(topic) = s.strip_prefix("ntfy://") {
+        if !topic.contains('/') {
+            return Err(format!("expected ntfy://HOST/TOPIC, got '{}'", s));
+        }
+        return Ok(Target::Ntfy(format!("https://{}", topic)));
+    }
+    if s.starts_with("https://") || s.starts_with("http://") {
+        return Ok(Target::Slack(s.to_owned()));
+    }
+    Err(format!(
+        "expected ntfy://HOST/TOPIC or a Slack webhook URL, got '{}'",
+        s
+    ))
+}
+
+fn post(target: &Target, message: &str) -> Result<(), ureq::Error> {
+    match target {
+        Target::Ntfy(url) => ureq::post(url).send(message)?,
+        Target::Slack(url) => ureq::post(url).send_json(serde_json::json!({ "text": message }))?,
+    };
+    Ok(())
+}
+
+/// Pushes failed jobs and the run summary to a chat service from a background thread
+pub struct Notifier {
+    sender: mpsc::Sender<String>,
+    thread: thread::JoinHandle<()>,
+    last_sent: Option<Instant>,
+    /// Failures not reported because they came too soon after the previous message
+    suppressed: usize,
+}
+
+impl Notifier {
+    pub fn new(target: Target) -> Notifier {
+        let (sender, receiver) = mpsc::channel::<String>();
+        let thread = thread::spawn(move || {
+            for message in receiver {
+                match post(&target, &message) {
+                    Ok(_) => debug!("failure notification delivered"),
+                    Err(e) => warn!("failure notification delivery failed: {}", e),
+                }
+            }
+        });
+        Notifier {
+            sender,
+            thread,
+            last_sent: None,
+            suppressed: 0,
+        }
+    }
+
+    pub fn job_failed(&mut self, seq: usize, command: &[String], exit_code: i32, outcome: Outcome) {
+        if self
+            .last_sent
+            .is_some_and(|t| t.elapsed() < FAILURE_INTERVAL)
+        {
+            self.suppressed += 1;
+            return;
+        }
+        let command: Vec<String> = command.iter().map(|a| shell_quote(a)).collect();
+        let how = match outcome {
+            Outcome::Signaled => "killed by a signal",
+            Outcome::SpawnFailed => "failed to start",
+            Outcome::TimedOut => "timed out",
+            Outcome::Success | Outcome::Failed => "failed",
+        };
+        let mut message = format!(
+            "pll job {} {} (exit code {}): {}",
+            seq,
+            how,
+            exit_code,
+            command.join(" ")
+        );
+        if self.suppressed > 0 {
+            message += &format!(", {} more failed since the last message", self.suppressed);
+        }
+        self.suppressed = 0;
+        self.last_sent = Some(Instant::now());
+        let _ = self.sender.send(message);
+    }
+
+    /// Sends the run summary and blocks until every message was delivered
+    pub fn finish(self, jobs: usize, failures: &Failures) {
+        let message = if failures.failed == 0 {
+            format!("pll finished: {} jobs succeeded", jobs)
+        } else {
+            format!(
+                "pll finished: {} of {} jobs failed ({} nonzero exit, {} signaled, {} failed to start)",
+                failures.failed, jobs, failures.nonzero, failures.signaled, failures.spawn_failed
+            )
+        };
+        let _ = self.sender.send(message);
+        drop(self.sender);
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_target, Target};
+
+    #[test]
+    fn parse_target_works() {
+        assert!(matches!(
+            parse_target("ntfy://ntfy.sh/builds"),
+            Ok(Target::Ntfy(url)) if url == "https://ntfy.sh/builds"
+        ));
+        assert!(matches!(
+            parse_target("https://hooks.slack.com/services/x"),
+            Ok(Target::Slack(_))
+        ));
+        assert!(parse_target("ntfy://builds").is_err());
+        assert!(parse_target("slack").is_err());
+    }
+}
@@ -0,0 +1,1286 @@
+#[cfg(feature = "otel")]
+use crate::otel;
+use crate::{
+    account, agent, alloc, args, backoff, bg, changes, chat, client, collate, control, daemon,
+    filter, heartbeat, history, input, joblog, log, map, output, placeholder, pool, power, printer,
+    remaining, repl, sandbox, schedule, sem, simulate, snapshot, source, stage, status, stop,
+    trace, tune, usage, wakeup, webhook,
+};
+use clap::builder::RangedU64ValueParser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use std::collections::HashSet;
+use std::env;
+use std::io::Read;
+use std::path::PathBuf;
+use std::process;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    run: RunArgs,
+
+    #[command(flatten)]
+    log: log::LogArgs,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Spawn programs in parallel using arguments read from stdin (default when no subcommand is given)
+    Run(Box<RunArgs>),
+    /// Run a single program once a slot of a named semaphore is available
+    Sem(sem::SemArgs),
+    /// Read lines interactively and dispatch each one as a job, printing tagged results as they complete
+    Repl(repl::ReplArgs),
+    /// Run jobs sent by controller pll instances connected with --agent
+    Agent(agent::AgentArgs),
+    /// Keep a pool alive and run jobs submitted by other processes over a Unix socket
+    Daemon(daemon::DaemonArgs),
+    /// Submit a job to a running daemon, printing its id
+    Submit(client::SubmitArgs),
+    /// Block until jobs submitted to a running daemon finish
+    Wait(client::WaitArgs),
+    /// Show the progress of runs started with --bg
+    Status(bg::StatusArgs),
+    /// Stream the output of a run started with --bg until it finishes
+    Attach(bg::AttachArgs),
+    /// Show the live jobs of a run listening on a control socket, killing or retrying them
+    Top(control::TopArgs),
+    /// Run the jobs logged with --joblog again, with the options given here
+    Replay(Box<ReplayArgs>),
+    /// Print a completion script for the given shell to stdout
+    Completions { shell: Shell },
+}
+
+#[derive(clap::Args, Debug)]
+// the commands come from the joblog
+#[command(mut_arg("program", |arg| arg.hide(true)))]
+struct ReplayArgs {
+    /// Joblog of the run to replay
+    file: PathBuf,
+
+    #[arg(long)]
+    /// Only run the jobs that didn't succeed, the last time they ran when the log was carried on with --resume
+    only_failed: bool,
+
+    #[command(flatten)]
+    run: RunArgs,
+}
+
+#[derive(clap::Args, Debug)]
+struct RunArgs {
+    #[arg(short, long)]
+    /// A string with all the characters that will be used to split arguments
+    ///
+    /// Can be repeated, the characters of every string are used.
+    delim: Vec<String>,
+
+    #[arg(short = '0', long = "null")]
+    /// Use null character ('\0') as a separator
+    ///
+    /// If a delimiter string is provide alongside this flag, the null character will be added to that list.
+    null_sep: bool,
+
+    #[arg(long, conflicts_with_all = ["delim", "null_sep"])]
+    /// Split arguments on any Unicode whitespace, like no-break or ideographic spaces, instead of only newlines,
+    /// tabs and spaces
+    unicode_ws: bool,
+
+    #[arg(long, value_name = "SIZE", value_parser = usage::parse_size, conflicts_with_all = ["max_lines", "redis_queue"])]
+    /// Stop reading input at a record longer than this, like 1M, instead of buffering it whole
+    ///
+    /// Guards against a missing delimiter, for instance when feeding a binary by mistake. pll exits with 1 once the
+    /// jobs already started finished.
+    max_record_size: Option<u64>,
+
+    #[arg(long, conflicts_with_all = ["null_sep", "max_lines", "redis_queue"])]
+    /// Let a backslash escape the character after it in the input, like xargs traditionally does, so `a\ b` is
+    /// a single argument
+    ///
+    /// Backslashes are removed from the arguments, `\\` giving a single backslash.
+    backslash_escapes: bool,
+
+    #[arg(short = 'E', long = "eof", value_name = "STR")]
+    /// Stop reading input at the first record equal to STR, like `xargs -E`
+    ///
+    /// That record and everything after it are ignored, for producers that append a sentinel after the real data.
+    eof: Option<String>,
+
+    #[arg(short = 'p', long, short_alias = 'j', alias = "jobs", allow_negative_numbers = true, value_parser = parse_parallelism)]
+    /// Max number of processes running at the same time [default: 16]
+    ///
+    /// A negative number leaves that many CPUs free, `-p -2` running two processes less than there are CPUs,
+    /// though always at least one.
+    max_parallelism: Option<usize>,
+
+    #[arg(long)]
+    /// The jobs mostly wait on the network or disk, so run many more of them than there are CPUs
+    ///
+    /// Without -p, runs 8 jobs per CPU at the same time instead of 16 overall. --auto-tune starts from there too
+    /// and doesn't hold back on a high load average, which counts processes waiting on disk.
+    io_bound: bool,
+
+    #[arg(long)]
+    /// Adjust the number of jobs running at the same time to what finishes the most jobs per second
+    ///
+    /// Starts with as many jobs as there are CPUs and goes up or down one job every 5 seconds depending on how
+    /// throughput changed, never over --max-parallelism, and only down while the load average is well above the
+    /// number of CPUs.
+    auto_tune: bool,
+
+    #[arg(long, default_value_t = 0)]
+    /// Number the jobs starting from this value
+    ///
+    /// Sequence numbers show up in logs, webhook events and agent output tags. Lets resumed or sharded runs keep
+    /// numbering where earlier ones stopped.
+    seq_start: usize,
+
+    #[arg(long, conflicts_with_all = ["dry_run", "open_tty"])]
+    /// Detach from the terminal and keep running in the background, printing a handle for the run
+    ///
+    /// The output of the run is written to a log next to a state file tracking its progress, see `pll status`
+    /// and `pll attach`.
+    bg: bool,
+
+    #[arg(long, value_name = "SIZE", value_parser = usage::parse_size, conflicts_with = "agents")]
+    /// Kill jobs whose processes use more resident memory than this, like 512M or 2G
+    ///
+    /// Memory is sampled twice per second and summed over each job and every process it started, so jobs that
+    /// fork around rlimits are caught too.
+    kill_if_rss: Option<u64>,
+
+    #[arg(long, conflicts_with_all = ["agents", "simulate"])]
+    /// Only count a job as done once every process it started exited, daemons included
+    ///
+    /// pll becomes a subreaper so processes orphaned by their parent are reparented to it rather than to init.
+    /// Processes are attributed to jobs by sampling, so ones started and orphaned within a few milliseconds may
+    /// still be missed.
+    reap_orphans: bool,
+
+    #[arg(long, value_name = "SIZE", value_parser = usage::parse_size, conflicts_with_all = ["agents", "simulate", "pipe_stdout"])]
+    /// Kill jobs once they printed more than this to stdout, like 10M, counting as a failure of its own
+    ///
+    /// Guards against running a chatty command over a lot of input. The output up to the limit is still passed on.
+    kill_if_output: Option<u64>,
+
+    #[arg(long, value_name = "SIGNAL", default_value = "TERM", value_parser = stop::parse_signal)]
+    /// Signal sent to running jobs, along with every process they started, when pll is interrupted or terminated,
+    /// to jobs running for longer than --timeout and to the jobs left running with --halt now or --any
+    ///
+    /// Each job runs in a process group of its own for this, unless --open-tty is given.
+    stop_signal: i32,
+
+    #[arg(long, value_name = "DURATION", default_value = "10s", value_parser = stop::parse_duration)]
+    /// How long processes get to exit after --stop-signal before being killed with SIGKILL
+    kill_grace: Duration,
+
+    #[arg(long, value_name = "FILE")]
+    /// When pll is interrupted or terminated, write the input records it didn't get to in FILE, each followed by
+    /// a NUL, so the run can be resumed with `pll -0 ... < FILE`
+    ///
+    /// Those are the records of the jobs that were stopped, then of the ones not started yet and then the input
+    /// left to read, which pll reads to the end first. A second signal makes it exit right away. A path like
+    /// /dev/fd/3 writes to an open file descriptor.
+    remaining: Option<PathBuf>,
+
+    #[arg(long, requires = "bg")]
+    /// Number of jobs the run is expected to have, for progress reporting when the input is a stream
+    total: Option<usize>,
+
+    #[arg(long, requires = "bg", conflicts_with_all = ["total", "redis_queue"])]
+    /// Read the whole input before starting any job so progress reporting knows the job count
+    ///
+    /// Arguments are buffered in memory until the input ends.
+    count_first: bool,
+
+    #[arg(long, hide = true, value_name = "SPEC", conflicts_with = "agents")]
+    /// Don't run anything, give each job the exit code and duration scripted in this file
+    simulate: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Print the commands that would run, one per line, without running them
+    dry_run: bool,
+
+    #[arg(short = 'n', long = "max-args", default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    /// Controls the max number of arguments that can be used to spawn a program
+    ///
+    /// Cannot be combined with a template.
+    max_args_count: usize,
+
+    #[arg(long = "min-args", default_value_t = 1)]
+    /// Determines the min number of arguments required to spawn a program
+    ///
+    /// Only relevant in case there aren't enough arguments to fill up to max count. Cannot be combined with
+    /// a template.
+    min_args_count: usize,
+
+    #[arg(
+        short = 'L',
+        long = "max-lines",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..),
+        conflicts_with_all = ["max_args_count", "min_args_count", "template", "delim", "null_sep", "redis_queue"]
+    )]
+    /// Build each command from the words of up to this many input lines
+    ///
+    /// Words of the same line always go to the same command, blank lines are skipped.
+    max_lines: Option<usize>,
+
+    #[arg(short = 's', long, value_parser = RangedU64ValueParser::<usize>::new().range(1..), conflicts_with = "template")]
+    /// Max number of characters in a command line, counting the program, its arguments and a separator after each
+    ///
+    /// Arguments are batched up to --max-args as long as the command stays under this size.
+    max_chars: Option<usize>,
+
+    #[arg(
+        long,
+        value_name = "SIZE",
+        value_parser = usage::parse_size,
+        conflicts_with_all = ["max_args_count", "template", "max_lines", "collate_by"]
+    )]
+    /// Run a command as soon as its arguments add up to SIZE bytes, like `64M`, however many they are
+    batch_bytes: Option<u64>,
+
+    #[arg(
+        long,
+        value_name = "CMD",
+        conflicts_with_all = ["template", "each", "reduce", "then", "tee", "agents", "simulate"]
+    )]
+    /// Run this command in the same slot once the job's program succeeds, can be repeated to run several in turn
+    ///
+    /// CMD is split on whitespace and run without a shell, the first failing command ends the job. `{}` is
+    /// replaced by the arguments of the job, which are appended when there is no `{}`.
+    and_then: Vec<String>,
+
+    #[arg(long, value_name = "CMD")]
+    /// Run every job through this command, like `--wrap 'timeout 60 --'` or `--wrap 'strace -fo {#}.trace --'`
+    ///
+    /// CMD is split on whitespace and the job's program and arguments are appended to it. `{#}` is replaced by
+    /// the job sequence number, `{}` by its arguments and `{N}` by its Nth input record, with the arithmetic and
+    /// filters of templates.
+    wrap: Option<String>,
+
+    #[arg(long, value_name = "CMD", conflicts_with_all = ["agents", "simulate"])]
+    /// Shell command run in the slot of each job right before its program, which only runs if CMD succeeds
+    ///
+    /// `{}` is replaced by the quoted arguments of the job, which are appended when there is no `{}`. The output
+    /// of CMD goes to stderr.
+    before: Option<String>,
+
+    #[arg(long, value_name = "CMD", conflicts_with_all = ["agents", "simulate"])]
+    /// Shell command run in the slot of each job once it is done, whatever its outcome, with its exit code in
+    /// $PLL_EXIT_CODE
+    ///
+    /// Takes `{}` like --before and also prints to stderr. A failing CMD is reported but doesn't change the
+    /// outcome of the job.
+    after: Option<String>,
+
+    #[arg(long, value_name = "CMD", conflicts_with = "dry_run")]
+    /// Shell command run once before the first job, pll exits with its code when it fails without running any job
+    setup: Option<String>,
+
+    #[arg(long, value_name = "CMD", conflicts_with = "dry_run")]
+    /// Shell command run once after the last job finished, even when jobs failed or pll was interrupted
+    ///
+    /// pll exits with the code of CMD when it fails.
+    teardown: Option<String>,
+
+    #[arg(long, value_name = "OCTAL", value_parser = parse_umask)]
+    /// File mode creation mask of the jobs, like 022, instead of the one pll was started with
+    umask: Option<libc::mode_t>,
+
+    #[arg(long, value_name = "NAME", value_parser = account::parse_user, conflicts_with_all = ["agents", "simulate"])]
+    /// Run the jobs as this user, by name or uid, and its primary group unless --group is given
+    ///
+    /// Only possible when pll runs as root.
+    user: Option<account::User>,
+
+    #[arg(long, value_name = "NAME", value_parser = account::parse_group, conflicts_with_all = ["agents", "simulate"])]
+    /// Run the jobs with this group, by name or gid
+    group: Option<libc::gid_t>,
+
+    #[arg(long, conflicts_with_all = ["agents", "simulate"])]
+    /// Run each job with a read-only view of the filesystem and a private, empty /tmp
+    ///
+    /// Uses a mount namespace, along with a user namespace when not running as root.
+    sandbox: bool,
+
+    #[arg(long, conflicts_with_all = ["agents", "simulate"])]
+    /// Run each job in a network namespace of its own, leaving it without any network access, not even loopback
+    no_network: bool,
+
+    #[arg(long, value_name = "PATH", requires = "sandbox")]
+    /// Keep this path writable inside the --sandbox, can be repeated, except for paths under the private /tmp
+    sandbox_rw: Vec<PathBuf>,
+
+    #[arg(long, value_name = "N", conflicts_with_all = ["agents", "simulate"], value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    /// Reserve N free TCP ports for each slot, so jobs running at the same time never share one
+    ///
+    /// Jobs get the first port in $PLL_PORT and in place of `{port}` in their arguments, the next ones in
+    /// $PLL_PORT2 and `{port2}` and so on.
+    alloc_ports: Option<usize>,
+
+    #[arg(long, value_name = "VALUES", conflicts_with_all = ["agents", "simulate"])]
+    /// Comma separated values, one for each slot, handed to jobs in $PLL_ALLOC and in place of `{alloc}`
+    alloc: Option<String>,
+
+    #[arg(long, value_name = "PATH")]
+    /// Listen on this Unix socket for `pll top` to show the running jobs and act on them
+    ///
+    /// Runs started with --bg always listen on a socket in their run directory.
+    control: Option<PathBuf>,
+
+    #[arg(long, value_name = "STRING")]
+    /// Input record which isn't used as an argument but runs the arguments gathered so far right away
+    flush_on: Option<String>,
+
+    #[arg(long, value_name = "K/M", value_parser = parse_shard)]
+    /// Only use the input records whose index is K modulo M, counting from 0
+    ///
+    /// Splits one input over M invocations with nothing to coordinate them, like `--shard 0/4` up to
+    /// `--shard 3/4` on four machines reading the same list. Records are counted as read, before any filtering.
+    shard: Option<(usize, usize)>,
+
+    #[arg(short = 'x', long, requires = "max_chars")]
+    /// Exit with an error when a single argument doesn't fit in --max-chars instead of running it on its own
+    exit_on_oversize: bool,
+
+    #[arg(long, value_name = "NAME")]
+    /// Set this environment variable in each process to the index of the slot it runs in
+    ///
+    /// Slots go from 0 to max parallelism - 1 and are reused as processes exit, so no two processes running at
+    /// the same time share one.
+    process_slot_var: Option<String>,
+
+    #[arg(short = 'o', long, conflicts_with = "agents")]
+    /// Give each process the terminal as stdin instead of an empty input
+    ///
+    /// Lets interactive programs prompt the user even though pll reads its own stdin for arguments.
+    open_tty: bool,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires_if("-", "redis_queue"),
+        conflicts_with_all = ["open_tty", "agents", "simulate"]
+    )]
+    /// Give each process a copy of the contents of FILE as stdin instead of an empty input
+    ///
+    /// FILE is read once before any job starts. With `-` it is read from stdin, so arguments have to come from
+    /// --redis-queue.
+    stdin_broadcast: Option<PathBuf>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["open_tty", "stdin_broadcast", "agents", "simulate"]
+    )]
+    /// Connect the stdin of each job to its own file, `{0}` in FILE being replaced by its first input record
+    ///
+    /// `{1}` is the second record of the job and so on, `{}` is all of them joined with spaces, like
+    /// `--stdin-from 'cases/{0}.in'`. Jobs whose file can't be opened fail as if they couldn't be started.
+    stdin_from: Option<String>,
+
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "template", "max_args_count", "min_args_count", "max_lines", "max_chars", "batch_bytes", "eof",
+            "collate_by", "filter", "map", "match_regex", "skip_regex", "flush_on", "shard", "redis_queue",
+            "arg_file", "stdin_broadcast", "stdin_from", "open_tty", "agents", "simulate", "remaining",
+            "skip_if_newer", "changed_only", "each", "round_robin",
+        ]
+    )]
+    /// Write the input to the stdin of the jobs in blocks instead of passing the records as arguments
+    ///
+    /// For programs reading their data on stdin, like `pll --pipe sort`, which only get the arguments given on
+    /// the command line. Blocks are made of whole records, lines unless -d, -0 or --unicode-ws say otherwise.
+    pipe: bool,
+
+    #[arg(long, value_name = "N", requires = "pipe", conflicts_with = "block_size", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    /// Give each job N records with --pipe instead of a block of --block-size
+    block_records: Option<usize>,
+
+    #[arg(long, value_name = "SIZE", requires = "pipe", value_parser = usage::parse_size)]
+    /// Size of the blocks given to each job with --pipe, like 64K or 10M [default: 1M]
+    ///
+    /// A block ends with the record taking it to SIZE or over, records are never cut in two.
+    block_size: Option<u64>,
+
+    #[arg(long, value_name = "DURATION", value_parser = stop::parse_duration)]
+    /// Print a status line like `running=7 done=1032 failed=3 queued~=0` to stderr every DURATION
+    ///
+    /// Meant for the logs of unattended runs. queued~ only counts the commands already built, not the input left.
+    heartbeat: Option<Duration>,
+
+    #[arg(long, value_name = "PATH")]
+    /// Keep a JSON snapshot of the progress and of what every slot runs in this file, rewritten every 2 seconds
+    ///
+    /// The file is replaced atomically so readers always see a whole snapshot, the last one has `finished` set.
+    status_file: Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Write when each job started and finished, and in which slot, to FILE in the Chrome trace event format
+    ///
+    /// Open it in Perfetto or chrome://tracing to see the run as a Gantt chart, with one row per slot.
+    trace_out: Option<PathBuf>,
+
+    #[arg(long, value_name = "FILE")]
+    /// Log every job to FILE as soon as it finished, with its sequence number, start time, runtime, exit code and
+    /// command
+    ///
+    /// The columns are tab separated and the same as in the joblog of GNU parallel.
+    joblog: Option<PathBuf>,
+
+    #[arg(long, requires = "joblog")]
+    /// Skip the jobs the --joblog of a previous run shows as succeeded, and keep adding to it
+    ///
+    /// Jobs are told apart by sequence number, so the input has to be the same as in that run. A job whose command
+    /// changed still runs.
+    resume: bool,
+
+    #[cfg(feature = "otel")]
+    #[arg(long, value_name = "URL", env = "OTEL_EXPORTER_OTLP_ENDPOINT")]
+    /// Export a span per job and one for the whole run to the OpenTelemetry collector at URL, over OTLP/HTTP
+    ///
+    /// URL is the base of the collector, like `http://localhost:4318`. Job spans have the arguments, exit code,
+    /// host and slot of the job as attributes.
+    otlp_endpoint: Option<String>,
+
+    #[arg(long)]
+    /// Remember how long each command took in ~/.cache/pll, so `pll status` can tell when the next run of the same
+    /// jobs will be done
+    ///
+    /// The estimate only replaces the one made from the average duration of the jobs once all the input was read
+    /// and every job left ran successfully before.
+    runtime_cache: bool,
+
+    #[arg(long, value_name = "CACHE")]
+    /// Skip the records a job already succeeded for in a previous run, keeping track of them in the CACHE file
+    ///
+    /// Records are hashed, along with the contents of the file they name when there is one, so a record is run
+    /// again as soon as its file changed.
+    changed_only: Option<PathBuf>,
+
+    #[arg(long, value_name = "OUTPUT")]
+    /// Skip the records naming a file older than the OUTPUT file made from it, like a parallel make
+    ///
+    /// `{}` in OUTPUT is replaced by the record, and so is `{0}` along with its filters, like
+    /// `--skip-if-newer 'out/{0|basename|noext}.png'`.
+    skip_if_newer: Option<String>,
+
+    #[arg(long, value_enum, default_value_t, requires_if("sjf", "runtime_cache"))]
+    /// Order jobs are started in
+    ///
+    /// Anything but fifo reads the whole input before starting any job.
+    schedule: schedule::Schedule,
+
+    #[arg(long)]
+    /// Keep going when an argument split on whitespace contains quotes or backslashes
+    ///
+    /// By default pll stops reading input at the first such argument, since whitespace the quotes were meant to
+    /// protect was split on anyway. Only applies with the default separators.
+    force: bool,
+
+    #[arg(short = 'r', long)]
+    /// Don't run the program at all when there is no input
+    ///
+    /// Without it the program still runs once on empty input when it needs no arguments, for instance with
+    /// `--min-args 0` or a template without placeholders.
+    no_run_if_empty: bool,
+
+    #[arg(long, value_name = "CMD", value_parser = map::parse)]
+    /// Rewrite each input record before using it as an argument, can be repeated to chain transforms
+    ///
+    /// CMD is run with `sh -c`, reading the record on stdin and writing its replacement on stdout. Records for
+    /// which it fails are skipped. Built-in transforms avoid spawning a process: @trim, @lower, @upper,
+    /// @realpath, @basename, @dirname and @urldecode.
+    map: Vec<map::Transform>,
+
+    #[arg(long, value_name = "PATTERN")]
+    /// Drop input records matching this regex before anything else is done with them, can be repeated
+    skip_regex: Vec<regex::Regex>,
+
+    #[arg(long = "match", value_name = "PATTERN")]
+    /// Only use input records matching this regex, can be repeated to accept records matching any of them
+    ///
+    /// Records left out are counted as skipped, like with --skip-regex.
+    match_regex: Vec<regex::Regex>,
+
+    #[arg(long, value_name = "CMD", conflicts_with_all = ["agents", "simulate", "pipe_stdout"])]
+    /// Once every job finished, pipe their output into this shell command
+    ///
+    /// The output of each job is captured instead of printed, and handed to CMD in input order. pll exits with
+    /// the status of CMD when it fails.
+    reduce: Option<String>,
+
+    #[arg(long, conflicts_with_all = ["agents", "simulate", "pipe_stdout", "reduce", "then", "each", "round_robin", "tee"])]
+    /// Race the jobs: once one succeeds, kill the others, start no more and print only the output of the winner
+    ///
+    /// Like `pll --any -- curl -sf` with a list of mirror URLs as input, to download from whichever answers first.
+    /// The output of each job is held back until it finished. pll exits with 1 when no job succeeded.
+    any: bool,
+
+    #[arg(long, value_enum, default_value_t)]
+    /// What happens to the rest of the run once a job fails
+    ///
+    /// Whatever the policy, pll exits with the number of jobs that failed, up to 101, and with 0 when they all
+    /// succeeded. Only jobs run locally are killed with `now`.
+    halt: status::Halt,
+
+    #[arg(long, value_name = "CMD", conflicts_with_all = ["agents", "simulate", "pipe_stdout", "reduce"])]
+    /// Run this shell command for every line printed by the jobs, as a second stage of the pipeline
+    ///
+    /// `{}` is replaced by the quoted line, which is appended when there is no `{}`. Lines are handed over as soon
+    /// as they are printed. pll exits with 1 if any job of the second stage fails.
+    then: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "CMD",
+        conflicts_with_all = ["program", "template", "agents", "simulate", "pipe_stdout", "reduce", "then"]
+    )]
+    /// Run every one of these shell commands, each as its own job, for every argument list; can be repeated
+    ///
+    /// `{}` is replaced by the quoted arguments, which are appended when there is no `{}`. Every line printed by
+    /// a job is tagged with its command, like `[sha256sum {}] ...`.
+    each: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "TOKEN",
+        num_args = 0..=1,
+        default_missing_value = "+++",
+        conflicts_with_all = ["template", "each", "agents", "simulate", "pipe_stdout", "reduce", "then"]
+    )]
+    /// Split the program on TOKEN, `+++` by default, into alternative commands that argument lists are handed to
+    /// in turn
+    ///
+    /// Like `pll --round-robin -- curl -s http://a/ +++ curl -s http://b/` to spread requests over two
+    /// endpoints. Every line printed by a job is tagged with its command, like `[curl -s http://b/] ...`.
+    round_robin: Option<String>,
+
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["agents", "simulate", "pipe_stdout", "reduce", "then"])]
+    /// Also write the output of every job to its own file, while still printing it tagged with the job number
+    ///
+    /// `{#}` in FILE is replaced by the job sequence number and `{}` by its arguments, like `logs/{#}.log`.
+    tee: Option<String>,
+
+    #[arg(long, value_name = "COL", conflicts_with = "each", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    /// Group jobs by this whitespace separated field of their first argument, counting from 1
+    ///
+    /// Jobs of the same group don't run more than --group-limit at a time. Jobs of other groups keep starting
+    /// meanwhile, so with lines like `HOST PATH` read using `-d $'\n'`, `--group-by 1` is gentle with each host.
+    group_by: Option<usize>,
+
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    /// Max number of jobs of the same --group-by group running at once
+    group_limit: usize,
+
+    #[arg(long, value_name = "N:DURATION", value_parser = backoff::Backoff::parse)]
+    /// Stop starting jobs for DURATION once N jobs failed in a row, like `5:30s`
+    ///
+    /// The pause doubles every time it happens again, until a job succeeds.
+    backoff_on_failures: Option<backoff::Backoff>,
+
+    #[arg(long, value_name = "DURATION", value_parser = stop::parse_duration)]
+    /// Kill jobs still running after DURATION, like `30s` or `5m`, counting as a failure of its own
+    ///
+    /// Only jobs run locally are killed, along with every process they started, with --stop-signal and then SIGKILL
+    /// once --kill-grace passed. The time counts from the start of the --before hook, if any, to the end of the
+    /// last --and-then command. Their output ends with a TIMEOUT line with --tag, --prefix or --keep-order, and pll
+    /// exits with 124 when they are the only jobs that failed.
+    timeout: Option<Duration>,
+
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    /// Run failed jobs again, up to N times, before counting them as failed
+    ///
+    /// Jobs killed from `pll top`, or because of --any or --halt, aren't run again. Every attempt keeps the
+    /// sequence number of the job, and has a line of its own in the --joblog.
+    retries: usize,
+
+    #[arg(long, value_name = "DURATION", default_value = "0s", value_parser = stop::parse_duration, requires = "retries")]
+    /// How long a failed job waits before it is run again with --retries
+    retry_delay: Duration,
+
+    #[arg(long, value_name = "DURATION", value_parser = stop::parse_duration)]
+    /// Wait at least DURATION between starting two jobs, to go easy on a rate limited service
+    delay: Option<Duration>,
+
+    #[arg(long, value_name = "CMD")]
+    /// Shell command run before starting each job, which is held back until CMD exits with 0
+    ///
+    /// CMD is tried again every second while it fails, so jobs can be throttled on anything it can check.
+    limit: Option<String>,
+
+    #[arg(long, value_name = "PERCENT", num_args = 0..=1, default_missing_value = "20", value_parser = RangedU64ValueParser::<u8>::new().range(0..=100))]
+    /// Run half the jobs while on battery or while the CPU is throttled for heat, and none below PERCENT of charge
+    ///
+    /// PERCENT defaults to 20. Jobs already running are left alone, new ones start again once the machine is
+    /// plugged in. The battery and temperature are checked every 10 seconds.
+    power_aware: Option<u8>,
+
+    #[arg(
+        long,
+        value_name = "COL",
+        conflicts_with_all = ["max_args_count", "min_args_count", "template", "max_lines"],
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    /// Run consecutive records with the same whitespace separated field, counting from 1, as a single job
+    collate_by: Option<usize>,
+
+    #[arg(long, requires = "collate_by")]
+    /// With --collate-by, group records with the same key wherever they are, holding them until the input ends
+    collate_all: bool,
+
+    #[arg(long, value_name = "N", requires = "then", value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    /// Max number of second stage jobs running at once, defaults to --max-parallelism
+    then_jobs: Option<usize>,
+
+    #[arg(long, value_name = "CMD")]
+    /// Only run jobs for the records this shell command succeeds for, like 'test -s {}'
+    ///
+    /// `{}` is replaced by the quoted record, which is appended when there is no `{}`. Tests run in parallel, up
+    /// to --max-parallelism at a time, and records that pass are used as they come.
+    filter: Option<String>,
+
+    #[arg(long, default_value_t = false)]
+    /// When enabled the output of each execution will only be written to stdout after the process exits
+    ///
+    /// Useful when it's undesireable to stream the ouput of several programs running in parallel. Stderr is held
+    /// back too, and printed right after stdout.
+    pipe_stdout: bool,
+
+    #[arg(short = 'k', long, conflicts_with_all = ["agents", "simulate", "kill_if_output", "reduce", "any", "then", "each", "round_robin", "tee"])]
+    /// Print the output of the jobs in the order of their input, whatever order they finish in
+    ///
+    /// The oldest job running prints as it goes, the output of the others is held back until it's their turn.
+    /// Stderr is held back along with stdout.
+    keep_order: bool,
+
+    #[arg(long, conflicts_with_all = ["prefix", "agents", "simulate", "kill_if_output", "reduce", "any", "then", "each", "round_robin", "tee"])]
+    /// Start every line printed by a job, on stdout and stderr, with its first argument and a tab
+    tag: bool,
+
+    #[arg(long, conflicts_with_all = ["agents", "simulate", "kill_if_output", "reduce", "any", "then", "each", "round_robin", "tee"])]
+    /// Start every line printed by a job, on stdout and stderr, with its sequence number and a tab
+    prefix: bool,
+
+    #[arg(short = 'l', long = "template", conflicts_with_all = ["max_args_count", "min_args_count"])]
+    /// When enabled the program strings will be processed as a template
+    ///
+    /// Example: "ssh {0}@{2} {1}" will read three arguments and replace the appropriate indices before spawning the process
+    ///
+    /// Placeholders can be part of a larger argument and take arithmetic and filters, like `{0}-{1|lower}.out` or
+    /// `--page={2+1}`. Arithmetic is done on integers with + - * / or %, filters are lower, upper, basename,
+    /// dirname and noext and apply after the arithmetic, in turn.
+    ///
+    /// `{}` is every input of the job, `{.}` the same without extension, `{/}` their basename, `{//}` their
+    /// dirname and `{/.}` their basename without extension. `{#}` is the sequence number of the job. Arguments
+    /// without any placeholder are left alone, braces included.
+    template: bool,
+
+    #[arg(
+        short = 'c',
+        long,
+        value_name = "SHELL",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "sh",
+        conflicts_with_all = ["each", "round_robin"]
+    )]
+    /// Run the program and its arguments as a single shell command line with `sh -c`, or the given shell, so
+    /// pipes and redirections work
+    ///
+    /// Input records are quoted for that shell, like `pll -c -l -- 'gunzip -c {} | wc -l > {.}.count'`.
+    shell: Option<output::Shell>,
+
+    #[arg(long)]
+    /// POST a JSON event to this URL whenever a job finishes, plus a summary once the run is over
+    webhook: Option<String>,
+
+    #[arg(long, value_name = "TARGET", value_parser = chat::parse_target)]
+    /// Push failed jobs and the run summary to ntfy, as ntfy://HOST/TOPIC, or to a Slack incoming webhook URL
+    ///
+    /// At most one failure is pushed every 10 seconds, the ones in between are counted in the next message.
+    on_fail_notify: Option<chat::Target>,
+
+    #[arg(long)]
+    /// Fire a desktop notification summarizing successes and failures when the run finishes
+    ///
+    /// Falls back to ringing the terminal bell when notifications aren't available.
+    notify: bool,
+
+    #[arg(long, num_args = 2, value_names = ["URL", "KEY"])]
+    /// Pop arguments from a Redis list (BLPOP) instead of reading them from stdin
+    ///
+    /// Each list item is used as a single argument. pll keeps consuming the list until interrupted.
+    redis_queue: Option<Vec<String>>,
+
+    #[arg(
+        short = 'a',
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["max_lines", "batch_bytes", "collate_by", "filter", "redis_queue", "skip_if_newer", "changed_only", "remaining"]
+    )]
+    /// Read input records from FILE, one per line, instead of stdin; can be repeated, `-` is stdin
+    ///
+    /// Like with `:::`, each job gets a record of every source, and there is a job for every combination of them.
+    arg_file: Vec<PathBuf>,
+
+    #[arg(long, value_name = "SEP", default_value = source::ARGS_SEPARATOR)]
+    /// Separate the program from the records listed after it with SEP instead of `:::`, for programs taking
+    /// `:::` as an argument
+    arg_sep: String,
+
+    #[arg(long, value_name = "SEP", default_value = source::ARG_FILES_SEPARATOR)]
+    /// Separate the program from the files listed after it with SEP instead of `::::`
+    arg_file_sep: String,
+
+    #[command(flatten)]
+    agents: agent::ControllerArgs,
+
+    /// Program to run and its first arguments, the input records being appended to them
+    ///
+    /// When an argument contains `{f}` the records of each job are written to a temporary file instead, one per
+    /// line, and `{f}` is replaced by its path. The file is removed once the job finished.
+    ///
+    /// Records can be listed after the program instead of read from stdin, following `:::`, like
+    /// `pll -l -- convert {0} -resize {1} {0}.{1}.png ::: a.jpg b.jpg ::: 50% 200%`. Each `:::` starts another
+    /// source, after the --arg-file ones. Files following `::::` are sources too, one record per line. Jobs get
+    /// a record of every source, `{N}` being the one of the Nth, and there is a job for every combination of
+    /// them.
+    program: Vec<String>,
+}
+
+fn parse_parallelism(s: &str) -> Result<usize, String> {
+    let n: i64 = s.parse().map_err(|_| format!("invalid number '{}'", s))?;
+    match n {
+        0 => Err("must be at least 1, or negative to count from the number of CPUs".to_owned()),
+        1.. => Ok(n as usize),
+        _ => {
+            let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+            Ok(cpus.saturating_sub(n.unsigned_abs() as usize).max(1))
+        }
+    }
+}
+
+/// Parallelism when -p isn't given
+fn default_parallelism(io_bound: bool) -> usize {
+    match io_bound {
+        true => {
+            thread::available_parallelism().map_or(1, |n| n.get()) * tune::IO_BOUND_JOBS_PER_CPU
+        }
+        false => 16,
+    }
+}
+
+fn parse_shard(s: &str) -> Result<(usize, usize), String> {
+    let (k, m) = s.split_once('/').ok_or("expected K/M, like 0/4")?;
+    let k: usize = k
+        .parse()
+        .map_err(|_| format!("invalid shard index '{}'", k))?;
+    let m: usize = m
+        .parse()
+        .map_err(|_| format!("invalid shard count '{}'", m))?;
+    match k < m {
+        true => Ok((k, m)),
+        false => Err(format!("shard index must be below {}", m)),
+    }
+}
+
+fn parse_umask(s: &str) -> Result<libc::mode_t, String> {
+    match libc::mode_t::from_str_radix(s, 8) {
+        Ok(mask) if mask <= 0o777 => Ok(mask),
+        _ => Err(format!("invalid octal mask '{}'", s)),
+    }
+}
+
+/// Exits the way the pool failed
+fn or_exit<T>(result: Result<T, pool::Error>) -> T {
+    result.unwrap_or_else(|e| {
+        // the --teardown command and the --remaining file reported themselves
+        if !matches!(e, pool::Error::Teardown(_) | pool::Error::Stopped(_)) {
+            eprintln!("{}", e);
+        }
+        process::exit(e.exit_code())
+    })
+}
+
+/// Runs the `pll` command line
+pub fn main() {
+    let cli = Cli::parse();
+    if let Err(e) = log::init(&cli.log) {
+        eprintln!("unable to open log file: {}", e);
+        process::exit(1);
+    }
+    match cli.command {
+        None => run(cli.run, vec![]),
+        Some(Command::Run(args)) => run(*args, vec![]),
+        Some(Command::Replay(args)) => replay(*args),
+        Some(Command::Sem(args)) => process::exit(sem::run(args)),
+        Some(Command::Repl(args)) => process::exit(repl::run(args)),
+        Some(Command::Daemon(args)) => process::exit(daemon::run(args)),
+        Some(Command::Agent(args)) => process::exit(agent::run(args)),
+        Some(Command::Submit(args)) => process::exit(client::submit(args)),
+        Some(Command::Wait(args)) => process::exit(client::wait(args)),
+        Some(Command::Status(args)) => process::exit(bg::status(args)),
+        Some(Command::Attach(args)) => process::exit(bg::attach(args)),
+        Some(Command::Top(args)) => process::exit(control::top(args)),
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(shell, &mut Cli::command(), "pll", &mut std::io::stdout())
+        }
+    }
+}
+
+/// Runs every command line of a joblog with `sh -c`, as records of a source of their own
+fn replay(mut args: ReplayArgs) {
+    if !args.run.program.is_empty() {
+        eprintln!("pll replay runs the commands of the joblog, it takes no program");
+        process::exit(1);
+    }
+    let log = std::fs::read_to_string(&args.file).unwrap_or_else(|e| {
+        eprintln!("unable to read {}: {}", args.file.display(), e);
+        process::exit(1);
+    });
+    let commands = joblog::commands(&log, args.only_failed);
+    if commands.is_empty() {
+        return;
+    }
+    args.run.program = vec!["sh".into(), "-c".into()];
+    run(args.run, vec![source::Source::Args(commands)])
+}
+
+/// `sources` come before the --arg-file and `:::` ones
+fn run(args: RunArgs, mut sources: Vec<source::Source>) {
+    let max_parallelism = args
+        .max_parallelism
+        .unwrap_or_else(|| default_parallelism(args.io_bound));
+    if args.min_args_count > args.max_args_count {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--min-args cannot be larger than --max-args",
+            )
+            .exit();
+    }
+
+    // SAFETY: geteuid(2) can't fail
+    if (args.user.is_some() || args.group.is_some()) && unsafe { libc::geteuid() } != 0 {
+        eprintln!("--user and --group need pll to run as root");
+        process::exit(1);
+    }
+
+    let background = args.bg.then(|| {
+        let mut run = bg::detach(env::args().collect()).unwrap_or_else(|e| {
+            eprintln!("unable to start in the background: {}", e);
+            process::exit(1);
+        });
+        if let Some(total) = args.total {
+            run.set_total(total);
+        }
+        run
+    });
+
+    if args.reap_orphans {
+        if let Err(e) = usage::become_subreaper() {
+            eprintln!("unable to become a subreaper: {}", e);
+            process::exit(1);
+        }
+    }
+    let running = Arc::new(Mutex::new(HashSet::new()));
+    let teardown = args.teardown.clone();
+    let drain = args.remaining.is_some();
+    if let Err(e) = wakeup::watch_children() {
+        eprintln!("unable to handle signals: {}", e);
+        process::exit(1);
+    }
+    if let Err(e) = stop::watch(
+        running.clone(),
+        args.stop_signal,
+        args.kill_grace,
+        teardown,
+        drain,
+    ) {
+        eprintln!("unable to handle signals: {}", e);
+        process::exit(1);
+    }
+    // listening only once signals are blocked, the watcher thread must be the one receiving them
+    let control_socket = args
+        .control
+        .or_else(|| background.as_ref().map(|run| control::bg_socket(run.dir())));
+    let control = control_socket.map(|path| {
+        let board = Arc::new(control::Board::default());
+        if let Err(e) = control::listen(&path, board.clone()) {
+            eprintln!("unable to listen on {}: {}", path.display(), e);
+            process::exit(1);
+        }
+        board
+    });
+
+    let uid = args.user.map(|u| u.uid);
+    let gid = args.group.or(args.user.map(|u| u.gid));
+    let sandbox = (args.sandbox || args.no_network).then(|| {
+        // SAFETY: getting the ids of the current process can't fail
+        let ids = unsafe { (libc::geteuid(), libc::getegid()) };
+        let sandbox = sandbox::Sandbox::new(
+            args.sandbox,
+            args.no_network,
+            args.sandbox_rw,
+            uid.unwrap_or(ids.0),
+            gid.unwrap_or(ids.1),
+        );
+        Arc::new(sandbox.unwrap_or_else(|e| {
+            eprintln!("unable to set up the sandbox: {}", e);
+            process::exit(1);
+        }))
+    });
+
+    let mut allocations = vec![];
+    if let Some(count) = args.alloc_ports {
+        allocations = alloc::ports(max_parallelism, count).unwrap_or_else(|e| {
+            eprintln!("unable to find free ports: {}", e);
+            process::exit(1);
+        });
+    }
+    if let Some(csv) = &args.alloc {
+        allocations.push(alloc::values(max_parallelism, csv).unwrap_or_else(|e| {
+            eprintln!("invalid --alloc: {}", e);
+            process::exit(1);
+        }));
+    }
+
+    if let Some(setup) = &args.setup {
+        let code = pool::run_once("--setup", setup);
+        if code != 0 {
+            process::exit(code);
+        }
+    }
+
+    let stdin_broadcast = args.stdin_broadcast.map(|path| {
+        let payload = if path.as_os_str() == "-" {
+            let mut payload = vec![];
+            std::io::stdin().read_to_end(&mut payload).map(|_| payload)
+        } else {
+            std::fs::read(&path)
+        };
+        Arc::new(payload.unwrap_or_else(|e| {
+            eprintln!("unable to read {}: {}", path.display(), e);
+            process::exit(1);
+        }))
+    });
+
+    let default_delims = args.delim.is_empty() && !args.null_sep;
+    let delims = input::delims(&args.delim, args.null_sep, args.unicode_ws, args.pipe);
+    if args.arg_sep == args.arg_file_sep {
+        eprintln!("--arg-sep and --arg-file-sep must differ");
+        process::exit(1);
+    }
+    let (words, listed) = source::split_args(&args.program, &args.arg_sep, &args.arg_file_sep);
+    sources.extend(args.arg_file.iter().cloned().map(source::Source::File));
+    sources.extend(listed);
+    let incompatible = [
+        ("--max-lines", args.max_lines.is_some()),
+        ("--batch-bytes", args.batch_bytes.is_some()),
+        ("--collate-by", args.collate_by.is_some()),
+        ("--filter", args.filter.is_some()),
+        ("--redis-queue", args.redis_queue.is_some()),
+        ("--skip-if-newer", args.skip_if_newer.is_some()),
+        ("--changed-only", args.changed_only.is_some()),
+        ("--remaining", args.remaining.is_some()),
+        ("--pipe", args.pipe),
+    ];
+    if let (false, Some((flag, _))) = (
+        sources.is_empty(),
+        incompatible.iter().find(|(_, given)| *given),
+    ) {
+        eprintln!("{} can't be used with ::: input", flag);
+        process::exit(1);
+    }
+    let alternatives: Vec<Vec<String>> = match &args.round_robin {
+        Some(token) => words
+            .split(|word| word == token)
+            .map(<[String]>::to_vec)
+            .collect(),
+        None => vec![],
+    };
+    if alternatives.iter().any(Vec::is_empty) {
+        eprintln!("--round-robin needs a command on each side of every separator");
+        process::exit(1);
+    }
+    let program = match (args.shell, words.first()) {
+        (Some(shell), _) => shell.program(),
+        _ if !args.each.is_empty() || !alternatives.is_empty() => "sh",
+        (None, Some(program)) => program,
+        (None, None) => "echo",
+    };
+    let initial_args: Vec<String> = match (args.shell, alternatives.is_empty()) {
+        (Some(shell), _) => shell
+            .flags()
+            .iter()
+            .map(|&flag| flag.to_owned())
+            .chain(std::iter::once(words.join(" ")))
+            .collect(),
+        (None, true) => words.iter().skip(1).map(|v| v.to_owned()).collect(),
+        // the words of the alternatives are only put in front of the input records once a job is started
+        (None, false) => vec![],
+    };
+    if let Some(output) = &args.skip_if_newer {
+        match placeholder::parse_word(output) {
+            Ok(Some(parts)) if placeholder::max_idx(&parts) > 0 => {
+                eprintln!("invalid --skip-if-newer: only {{0}} is the record");
+                process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("invalid --skip-if-newer: {}", e);
+                process::exit(1);
+            }
+            _ => {}
+        }
+    }
+    let wrap: Option<Vec<String>> = args
+        .wrap
+        .as_ref()
+        .map(|cmd| cmd.split_whitespace().map(String::from).collect());
+    if let Some(words) = &wrap {
+        if words.is_empty() {
+            eprintln!("invalid --wrap: no command");
+            process::exit(1);
+        }
+        if let Some(Err(e)) = words
+            .iter()
+            .map(|w| placeholder::parse_word(w))
+            .find(Result::is_err)
+        {
+            eprintln!("invalid --wrap: {}", e);
+            process::exit(1);
+        }
+    }
+    if args.template {
+        match args::TemplateArgs::new(initial_args.clone()) {
+            Err(e) => {
+                eprintln!("invalid template: {}", e);
+                process::exit(1);
+            }
+            Ok(template) if !sources.is_empty() && template.arity().max(1) != sources.len() => {
+                eprintln!(
+                    "invalid template: it takes {} records but there are {} input sources",
+                    template.arity().max(1),
+                    sources.len()
+                );
+                process::exit(1);
+            }
+            Ok(_) => {}
+        }
+    }
+    let product = (!sources.is_empty()).then(|| {
+        source::Product::open(sources).unwrap_or_else(|e| {
+            eprintln!("unable to read the input sources: {}", e);
+            process::exit(1);
+        })
+    });
+
+    let proc_builder = args::DynArgBuilderMaker {
+        initial_args,
+        is_template: args.template,
+        max_args: match (args.max_lines.or(args.collate_by), args.batch_bytes) {
+            // a job is started for each combination of the input sources
+            _ if product.is_some() => usize::MAX,
+            (None, None) => args.max_args_count,
+            _ => usize::MAX,
+        },
+        min_args: args.min_args_count,
+        max_chars: args
+            .max_chars
+            .map_or(usize::MAX, |n| n.saturating_sub(program.len() + 1)),
+        batch_bytes: args
+            .batch_bytes
+            .map_or(usize::MAX, |n| usize::try_from(n).unwrap_or(usize::MAX)),
+        shell: args.shell,
+    };
+
+    let simulate = args.simulate.map(|path| {
+        simulate::Script::load(&path).unwrap_or_else(|e| {
+            eprintln!("unable to load simulation spec: {}", e);
+            process::exit(1);
+        })
+    });
+
+    let agents = args.agents.connect().unwrap_or_else(|e| {
+        eprintln!("unable to connect to {}", e);
+        process::exit(1);
+    });
+
+    let stage = args
+        .then
+        .map(|cmd| stage::Stage::new(cmd, args.then_jobs.unwrap_or(max_parallelism)));
+    let options = pool::PoolOptions {
+        max_parallelism,
+        tuner: args
+            .auto_tune
+            .then(|| tune::Tuner::new(max_parallelism, args.io_bound)),
+        printer: (args.pipe_stdout || args.keep_order || args.tag || args.prefix).then(|| {
+            let prefix = match (args.tag, args.prefix) {
+                (true, _) => Some(printer::Prefix::Arg),
+                (_, true) => Some(printer::Prefix::Seq),
+                _ => None,
+            };
+            Arc::new(printer::Printer::new(
+                args.pipe_stdout,
+                args.keep_order,
+                prefix,
+                args.seq_start,
+            ))
+        }),
+        webhook: args.webhook.map(webhook::Webhook::new),
+        on_fail_notify: args.on_fail_notify.map(chat::Notifier::new),
+        notify: args.notify,
+        no_run_if_empty: args.no_run_if_empty,
+        process_slot_var: args.process_slot_var,
+        exit_on_oversize: args.exit_on_oversize,
+        open_tty: args.open_tty,
+        dry_run: args.dry_run,
+        seq_start: args.seq_start,
+        simulate,
+        count_first: args.count_first,
+        kill_if_rss: args.kill_if_rss,
+        reap_orphans: args.reap_orphans,
+        kill_if_output: args.kill_if_output,
+        running,
+        background,
+        agents,
+        return_rsync: args.agents.return_rsync,
+        reduce: args.reduce,
+        any: args.any,
+        halt: args.halt,
+        then: stage.as_ref().map(stage::Stage::sender),
+        each: args.each,
+        alternatives,
+        tee: args.tee,
+        group_by: args.group_by,
+        group_limit: args.group_limit,
+        backoff: args.backoff_on_failures,
+        timeout: args.timeout,
+        stop_signal: args.stop_signal,
+        kill_grace: args.kill_grace,
+        retries: args.retries,
+        retry_delay: args.retry_delay,
+        delay: args.delay,
+        limit: args.limit,
+        power: args.power_aware.map(power::Power::new),
+        control,
+        umask: args.umask,
+        uid,
+        gid,
+        sandbox,
+        allocations,
+        keep_results: false,
+        and_then: args
+            .and_then
+            .iter()
+            .map(|cmd| cmd.split_whitespace().map(String::from).collect())
+            .collect(),
+        wrap,
+        before: args.before,
+        after: args.after,
+        teardown: args.teardown,
+        stdin_broadcast,
+        stdin_from: args.stdin_from,
+        heartbeat: args.heartbeat.map(heartbeat::start),
+        #[cfg(feature = "otel")]
+        otel: args.otlp_endpoint.as_deref().map(otel::Exporter::new),
+        joblog: args.joblog.map(|path| {
+            joblog::Joblog::open(path.clone(), args.resume).unwrap_or_else(|e| {
+                eprintln!("unable to open {}: {}", path.display(), e);
+                process::exit(1);
+            })
+        }),
+        trace: args.trace_out.map(|path| {
+            trace::Trace::create(path.clone()).unwrap_or_else(|e| {
+                eprintln!("unable to write {}: {}", path.display(), e);
+                process::exit(1);
+            })
+        }),
+        status_file: args.status_file.map(|path| {
+            snapshot::StatusFile::create(path.clone()).unwrap_or_else(|e| {
+                eprintln!("unable to write {}: {}", path.display(), e);
+                process::exit(1);
+            })
+        }),
+        schedule: args.schedule,
+        remaining: args.remaining.map(|path| {
+            remaining::Remaining::create(path.clone()).unwrap_or_else(|e| {
+                eprintln!("unable to create {}: {}", path.display(), e);
+                process::exit(1);
+            })
+        }),
+        skip_if_newer: args.skip_if_newer,
+        changed_only: args.changed_only.map(|path| {
+            changes::Changes::load(path.clone()).unwrap_or_else(|e| {
+                eprintln!("unable to load {}: {}", path.display(), e);
+                process::exit(1);
+            })
+        }),
+        history: args.runtime_cache.then(|| {
+            history::History::load().unwrap_or_else(|e| {
+                eprintln!("unable to load the runtime cache: {}", e);
+                process::exit(1);
+            })
+        }),
+    };
+    let mut stages = input::Stages {
+        flush_on: args.flush_on,
+        shard: args.shard,
+        fed: 0,
+        only: args.match_regex,
+        skip: args.skip_regex,
+        map: args.map,
+        filter: args
+            .filter
+            .map(|cmd| filter::Filter::new(cmd, max_parallelism)),
+        collate: args
+            .collate_by
+            .map(|col| collate::Collate::new(col, args.collate_all)),
+    };
+    let input = input::Input {
+        redis_queue: args.redis_queue,
+        product,
+        pipe: args.pipe,
+        delims,
+        default_delims,
+        backslash_escapes: args.backslash_escapes,
+        max_record_size: args.max_record_size,
+        block_records: args.block_records,
+        block_size: args.block_size,
+        max_lines: args.max_lines,
+        eof: args.eof,
+        force: args.force,
+    };
+    let mut pool = pool::ProcPool::new(program.into(), proc_builder, options);
+    let refused = or_exit(input.read(&mut stages, &mut pool));
+    or_exit(stages.finish(&mut pool));
+    or_exit(pool.wait_all());
+    if stop::stopping().is_some() {
+        or_exit(pool.halt(std::iter::empty()));
+    }
+    let lost = args.any && !pool.won();
+    // with --any only the winner matters
+    let jobs_code = if args.any {
+        0
+    } else {
+        pool.failures().exit_code()
+    };
+    drop(pool);
+    let then_failed = stage.map_or(0, stage::Stage::finish);
+    if lost {
+        eprintln!("no job succeeded");
+    }
+    process::exit(status::run_exit_code(refused, then_failed, lost, jobs_code));
+}
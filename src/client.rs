@@ -0,0 +1,106 @@
+use crate::daemon::{Request, Response};
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+#[derive(clap::Args, Debug)]
+pub struct SubmitArgs {
+    #[arg(long)]
+    /// Path of the socket the daemon is listening on
+    socket: PathBuf,
+
+    #[arg(short, long)]
+    /// Block until the job finishes and exit with its exit code
+    wait: bool,
+
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    program: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct WaitArgs {
+    #[arg(long)]
+    /// Path of the socket the daemon is listening on
+    socket: PathBuf,
+
+    /// Jobs to wait for, every submitted job is waited for when none is given
+    ids: Vec<usize>,
+}
+
+struct Connection {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Connection {
+    fn open(socket: &Path) -> io::Result<Connection> {
+        let writer = UnixStream::connect(socket)?;
+        Ok(Connection {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        })
+    }
+
+    fn request(&mut self, request: &Request) -> io::Result<Response> {
+        writeln!(self.writer, "{}", serde_json::to_string(request)?)?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+fn report(result: io::Result<i32>) -> i32 {
+    result.unwrap_or_else(|e| {
+        eprintln!("daemon request failed: {}", e);
+        1
+    })
+}
+
+fn unexpected(response: Response) -> io::Result<i32> {
+    match response {
+        Response::Error { message } => eprintln!("daemon error: {}", message),
+        other => eprintln!("unexpected response from daemon: {:?}", other),
+    }
+    Ok(1)
+}
+
+pub fn submit(args: SubmitArgs) -> i32 {
+    report((|| {
+        let mut conn = Connection::open(&args.socket)?;
+        let id = match conn.request(&Request::Submit { argv: args.program })? {
+            Response::Submitted { id } => id,
+            other => return unexpected(other),
+        };
+        if !args.wait {
+            println!("{}", id);
+            return Ok(0);
+        }
+        match conn.request(&Request::Wait { id: Some(id) })? {
+            Response::Finished { exit_code, .. } => Ok(exit_code),
+            other => unexpected(other),
+        }
+    })())
+}
+
+pub fn wait(args: WaitArgs) -> i32 {
+    report((|| {
+        let mut conn = Connection::open(&args.socket)?;
+        if args.ids.is_empty() {
+            return match conn.request(&Request::Wait { id: None })? {
+                Response::Idle { failed } => Ok(i32::from(failed > 0)),
+                other => unexpected(other),
+            };
+        }
+        let mut exit_code = 0;
+        for id in args.ids {
+            match conn.request(&Request::Wait { id: Some(id) })? {
+                Response::Finished { exit_code: 0, .. } => {}
+                Response::Finished { .. } => exit_code = 1,
+                other => return unexpected(other),
+            }
+        }
+        Ok(exit_code)
+    })())
+}
@@ -0,0 +1,83 @@
+use std::collections::HashMap;
+
+/// Gathers records sharing a key so each group becomes a single job
+///
+/// The key is a whitespace separated field of the record. Groups are made of consecutive records with the same
+/// key, unless `all` is set in which case every record is held until the input ends and records with the same
+/// key end up together wherever they were.
+pub struct Collate {
+    col: usize,
+    all: bool,
+    /// Groups in the order their key was first seen, only the last one is still open without `all`
+    groups: Vec<(String, Vec<String>)>,
+    index: HashMap<String, usize>,
+}
+
+impl Collate {
+    /// `col` counts from 1
+    pub fn new(col: usize, all: bool) -> Collate {
+        Collate {
+            col,
+            all,
+            groups: vec![],
+            index: HashMap::new(),
+        }
+    }
+
+    fn key<'a>(&self, record: &'a str) -> &'a str {
+        record
+            .split_whitespace()
+            .nth(self.col - 1)
+            .unwrap_or_default()
+    }
+
+    /// Adds a record, returning the group it closed when its key differs from the previous record
+    pub fn push(&mut self, record: String) -> Option<Vec<String>> {
+        let key = self.key(&record).to_owned();
+        if self.all {
+            let idx = *self.index.entry(key.clone()).or_insert(self.groups.len());
+            if idx == self.groups.len() {
+                self.groups.push((key, vec![]));
+            }
+            self.groups[idx].1.push(record);
+            return None;
+        }
+        let closed = match self.groups.last() {
+            Some((last, _)) if *last != key => self.groups.pop().map(|(_, group)| group),
+            _ => None,
+        };
+        match self.groups.last_mut() {
+            Some((_, group)) => group.push(record),
+            None => self.groups.push((key, vec![record])),
+        }
+        closed
+    }
+
+    /// Returns the groups still open once the input ended
+    pub fn finish(self) -> Vec<Vec<String>> {
+        self.groups.into_iter().map(|(_, group)| group).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Collate;
+
+    #[test]
+    fn collate_works() {
+        let records = ["a 1", "a 2", "b 1", "a 3"];
+        let mut collate = Collate::new(1, false);
+        let mut groups: Vec<Vec<String>> = records
+            .iter()
+            .filter_map(|r| collate.push(r.to_string()))
+            .collect();
+        groups.extend(collate.finish());
+        assert_eq!(groups, [vec!["a 1", "a 2"], vec!["b 1"], vec!["a 3"]]);
+
+        let mut collate = Collate::new(1, true);
+        assert!(records
+            .iter()
+            .all(|r| collate.push(r.to_string()).is_none()));
+        assert_eq!(collate.finish(), [vec!["a 1", "a 2", "a 3"], vec!["b 1"]]);
+    }
+}
@@ -0,0 +1,379 @@
+use crate::bg::runs_dir;
+use crate::output::shell_quote;
+use crate::pool::Batch;
+use crate::status::{Kill, KillCause};
+use crate::{stop, wakeup};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+#[derive(clap::Args, Debug)]
+pub struct TopArgs {
+    /// Control socket given to `pll --control`, or handle printed by `pll --bg`
+    target: String,
+}
+
+/// A message sent to a run's control socket, encoded as a single line of JSON
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    List,
+    /// Sends SIGTERM to a running local job
+    Kill {
+        seq: usize,
+    },
+    /// Runs the command of a failed job again
+    Retry {
+        seq: usize,
+    },
+}
+
+/// A message sent back by a run, encoded as a single line of JSON
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Jobs { jobs: Vec<JobInfo> },
+    Done,
+    Error { message: String },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobInfo {
+    pub seq: usize,
+    pub command: Vec<String>,
+    /// Only known for local jobs
+    pub pid: Option<u32>,
+    pub elapsed_ms: u64,
+    /// Set once finished, only failed jobs are kept after finishing
+    pub exit_code: Option<i32>,
+}
+
+struct Entry {
+    command: Vec<String>,
+    pid: Option<u32>,
+    started_at: Instant,
+    finished: Option<(i32, Duration)>,
+    /// Signal sent from `pll top`
+    killed: Option<libc::c_int>,
+    /// What the job was started from, to run it again
+    batch: Batch,
+}
+
+/// Jobs of a run as seen from its control socket, kept up to date by the pool
+#[derive(Default)]
+pub struct Board {
+    jobs: Mutex<BTreeMap<usize, Entry>>,
+    retries: Mutex<Vec<Batch>>,
+}
+
+impl Board {
+    pub(crate) fn started(&self, seq: usize, command: &[String], pid: Option<u32>, batch: Batch) {
+        let entry = Entry {
+            command: command.to_vec(),
+            pid,
+            started_at: Instant::now(),
+            finished: None,
+            killed: None,
+            batch,
+        };
+        self.jobs.lock().unwrap().insert(seq, entry);
+    }
+
+    /// Marks the job as finished, telling whether it was killed from `pll top`
+    pub fn finished(&self, seq: usize, exit_code: i32) -> Option<Kill> {
+        let mut jobs = self.jobs.lock().unwrap();
+        let entry = jobs.get_mut(&seq)?;
+        let kill = entry.killed.map(|signal| Kill {
+            cause: KillCause::Control,
+            signal,
+        });
+        if exit_code == 0 {
+            jobs.remove(&seq);
+        } else {
+            entry.finished = Some((exit_code, entry.started_at.elapsed()));
+        }
+        kill
+    }
+
+    /// Jobs whose retry was requested since the last call
+    pub(crate) fn take_retries(&self) -> Vec<Batch> {
+        std::mem::take(&mut self.retries.lock().unwrap())
+    }
+
+    fn list(&self) -> Vec<JobInfo> {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter()
+            .map(|(&seq, entry)| {
+                let elapsed = entry
+                    .finished
+                    .map_or_else(|| entry.started_at.elapsed(), |(_, d)| d);
+                JobInfo {
+                    seq,
+                    command: entry.command.clone(),
+                    pid: entry.pid,
+                    elapsed_ms: elapsed.as_millis() as u64,
+                    exit_code: entry.finished.map(|(code, _)| code),
+                }
+            })
+            .collect()
+    }
+
+    fn kill(&self, seq: usize) -> Result<(), String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        match jobs.get_mut(&seq) {
+            Some(Entry {
+                pid: Some(pid),
+                finished: None,
+                killed,
+                ..
+            }) => {
+                // the pid is only known while the job wasn't reaped
+                stop::kill_tree(*pid, libc::SIGTERM);
+                *killed = Some(libc::SIGTERM);
+                Ok(())
+            }
+            Some(Entry { finished: None, .. }) => Err(format!("job {} isn't running locally", seq)),
+            _ => Err(format!("job {} isn't running", seq)),
+        }
+    }
+
+    fn retry(&self, seq: usize) -> Result<(), String> {
+        let mut jobs = self.jobs.lock().unwrap();
+        match jobs.get(&seq) {
+            Some(Entry {
+                finished: Some(_), ..
+            }) => {
+                let entry = jobs.remove(&seq).unwrap();
+                self.retries.lock().unwrap().push(entry.batch);
+                wakeup::notify();
+                Ok(())
+            }
+            _ => Err(format!("job {} didn't fail", seq)),
+        }
+    }
+}
+
+fn handle_client(board: &Board, stream: UnixStream) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let response = match serde_json::from_str::<Request>(&line?) {
+            Ok(Request::List) => Ok(Response::Jobs { jobs: board.list() }),
+            Ok(Request::Kill { seq }) => board.kill(seq).map(|_| Response::Done),
+            Ok(Request::Retry { seq }) => board.retry(seq).map(|_| Response::Done),
+            Err(e) => Err(format!("invalid request: {}", e)),
+        }
+        .unwrap_or_else(|message| Response::Error { message });
+        writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+    }
+    Ok(())
+}
+
+/// Path of the control socket every background run listens on
+pub fn bg_socket(dir: &Path) -> PathBuf {
+    dir.join("control.sock")
+}
+
+/// Answers requests on a Unix socket from a background thread
+pub fn listen(path: &Path, board: Arc<Board>) -> io::Result<()> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another run is listening on this socket",
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let board = board.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle_client(&board, stream) {
+                            warn!("control client failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("failed to accept control connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+struct Connection {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Connection {
+    fn open(socket: &Path) -> io::Result<Connection> {
+        let writer = UnixStream::connect(socket)?;
+        Ok(Connection {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        })
+    }
+
+    fn request(&mut self, request: &Request) -> io::Result<Response> {
+        writeln!(self.writer, "{}", serde_json::to_string(request)?)?;
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+fn render(jobs: &[JobInfo], message: &str) -> String {
+    // clears the screen and moves to its top
+    let mut screen = String::from("\x1b[H\x1b[2J");
+    screen += &format!(
+        "{:>6} {:>8} {:>8} {:>7}  COMMAND\n",
+        "SEQ", "PID", "TIME", "STATUS"
+    );
+    for job in jobs {
+        let status = job
+            .exit_code
+            .map_or_else(|| "running".to_owned(), |code| format!("exit {}", code));
+        let pid = job.pid.map_or_else(|| "-".to_owned(), |p| p.to_string());
+        let command: Vec<String> = job.command.iter().map(|a| shell_quote(a)).collect();
+        screen += &format!(
+            "{:>6} {:>8} {:>7.1}s {:>7}  {}\n",
+            job.seq,
+            pid,
+            job.elapsed_ms as f64 / 1000.0,
+            status,
+            command.join(" ")
+        );
+    }
+    screen += &format!("\n{}\nk SEQ: kill, r SEQ: retry, q: quit\n> ", message);
+    screen
+}
+
+fn parse_command(line: &str) -> Result<Option<Request>, String> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let seq = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|_| format!("bad job number '{}'", s))
+    };
+    match words[..] {
+        [] => Ok(Some(Request::List)),
+        ["q"] => Ok(None),
+        ["k", s] => Ok(Some(Request::Kill { seq: seq(s)? })),
+        ["r", s] => Ok(Some(Request::Retry { seq: seq(s)? })),
+        _ => Err(format!("unknown command '{}'", line.trim())),
+    }
+}
+
+/// Shows the live jobs of a run, refreshed every second, reading commands acting on them from stdin
+pub fn top(args: TopArgs) -> i32 {
+    let path = match Path::new(&args.target) {
+        p if p.exists() => p.to_owned(),
+        _ => bg_socket(&runs_dir().join(&args.target)),
+    };
+    let mut conn = match Connection::open(&path) {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("unable to connect to {}: {}", path.display(), e);
+            return 1;
+        }
+    };
+    let (sender, commands) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let mut message = String::new();
+    loop {
+        let jobs = match conn.request(&Request::List) {
+            Ok(Response::Jobs { jobs }) => jobs,
+            Ok(other) => {
+                eprintln!("unexpected response from run: {:?}", other);
+                return 1;
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::UnexpectedEof
+                        | io::ErrorKind::BrokenPipe
+                        | io::ErrorKind::ConnectionReset
+                ) =>
+            {
+                println!("\nrun finished");
+                return 0;
+            }
+            Err(e) => {
+                eprintln!("control request failed: {}", e);
+                return 1;
+            }
+        };
+        print!("{}", render(&jobs, &message));
+        let _ = io::stdout().flush();
+        message.clear();
+        let line = match commands.recv_timeout(Duration::from_secs(1)) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => return 0,
+        };
+        let request = match parse_command(&line) {
+            Ok(Some(request)) => request,
+            Ok(None) => return 0,
+            Err(e) => {
+                message = e;
+                continue;
+            }
+        };
+        match conn.request(&request) {
+            Ok(Response::Error { message: e }) => message = e,
+            Ok(_) => {}
+            Err(e) => message = format!("request failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_command, Board, Request};
+    use crate::pool::Batch;
+
+    #[test]
+    fn board_works() {
+        let board = Board::default();
+        board.started(0, &["true".into()], None, Batch::default());
+        board.started(1, &["false".into()], None, Batch::default());
+        assert!(board.kill(0).is_err());
+        board.finished(0, 0);
+        board.finished(1, 1);
+        let jobs = board.list();
+        assert_eq!(jobs.len(), 1);
+        assert_eq!((jobs[0].seq, jobs[0].exit_code), (1, Some(1)));
+        assert!(board.retry(0).is_err());
+        assert!(board.retry(1).is_ok());
+        assert_eq!(board.take_retries().len(), 1);
+        assert!(board.list().is_empty());
+    }
+
+    #[test]
+    fn parse_command_works() {
+        assert!(matches!(
+            parse_command("k 3"),
+            Ok(Some(Request::Kill { seq: 3 }))
+        ));
+        assert!(matches!(parse_command("q"), Ok(None)));
+        assert!(parse_command("k x").is_err());
+    }
+}
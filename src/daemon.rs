@@ -0,0 +1,444 @@
+use crate::agent::token_matches;
+use crate::http;
+use crate::output::tag_lines;
+use crate::status::exit_code;
+use crate::store::{self, JobState, JobStore, MemoryStore, QueuedJob, SqliteStore};
+use clap::builder::RangedU64ValueParser;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use std::{process, thread};
+use tracing::{debug, info, info_span, warn};
+
+#[derive(clap::Args, Debug)]
+pub struct DaemonArgs {
+    #[arg(long)]
+    /// Path of the Unix socket accepting job submissions
+    socket: PathBuf,
+
+    #[arg(short = 'p', long, default_value_t = 16, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    /// Max number of processes running at the same time, shared by every client
+    max_parallelism: usize,
+
+    #[arg(long)]
+    /// Append the tagged output and exit status of every job to this file instead of stdout
+    results_log: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Persist queued jobs, their state, attempts and results in a SQLite database
+    ///
+    /// Jobs left behind by a crashed daemon are resumed on startup, and several daemons pointed at the same
+    /// database, possibly on different machines, cooperatively consume its queue. Each running job is leased to
+    /// the daemon that claimed it and handed to another one when that daemon stops renewing the lease.
+    queue_db: Option<PathBuf>,
+
+    #[arg(long)]
+    /// Also expose a REST API for submitting, listing, inspecting and cancelling jobs on this address
+    ///
+    /// Routes: `POST /jobs` with `{"argv": [...]}`, `GET /jobs`, `GET /jobs/ID`, `GET /jobs/ID/output` and
+    /// `POST /jobs/ID/cancel`.
+    http_addr: Option<SocketAddr>,
+
+    #[arg(
+        long,
+        env = "PLL_HTTP_TOKEN",
+        hide_env_values = true,
+        requires = "http_addr"
+    )]
+    /// Only accept REST API requests with an `Authorization: Bearer TOKEN` header
+    ///
+    /// Required unless --http-addr is a loopback address, anyone reaching the API can run any command.
+    http_token: Option<String>,
+
+    #[arg(long, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    /// Max number of unfinished jobs, submissions block until jobs finish once it is reached
+    ///
+    /// Bounds how much work piles up in the queue when clients submit faster than jobs complete, pushing back on
+    /// the submitters instead.
+    max_pending: Option<usize>,
+}
+
+/// A message sent by a client, encoded as a single line of JSON
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    Submit {
+        argv: Vec<String>,
+    },
+    /// Blocks until the given job finishes, or until every submitted job finishes when no id is given
+    Wait {
+        id: Option<usize>,
+    },
+}
+
+/// A message sent back by the daemon, encoded as a single line of JSON
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Response {
+    Submitted { id: usize },
+    Finished { id: usize, exit_code: i32 },
+    Idle { failed: usize },
+    Error { message: String },
+}
+
+/// How often the store is checked for changes made by other processes sharing it
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+struct Shared {
+    store: Mutex<Box<dyn JobStore>>,
+    /// Pids of the jobs this daemon is currently running
+    running: Mutex<HashMap<usize, u32>>,
+    cond: Condvar,
+    done: Condvar,
+    results: Mutex<Box<dyn Write + Send>>,
+    max_pending: Option<usize>,
+}
+
+impl Shared {
+    fn submit(&self, argv: Vec<String>) -> io::Result<Response> {
+        if argv.is_empty() {
+            return Ok(Response::Error {
+                message: "empty command".into(),
+            });
+        }
+        let mut store = self.store.lock().unwrap();
+        if let Some(max) = self.max_pending {
+            while store.counts()?.0 >= max {
+                store = self.done.wait_timeout(store, POLL_INTERVAL).unwrap().0;
+            }
+        }
+        let id = store.push(argv)?;
+        drop(store);
+        self.cond.notify_one();
+        Ok(Response::Submitted { id })
+    }
+
+    fn next_job(&self) -> QueuedJob {
+        let mut store = self.store.lock().unwrap();
+        loop {
+            match store.claim() {
+                Ok(Some(job)) => return job,
+                Ok(None) => {}
+                Err(e) => warn!("failed to claim job: {}", e),
+            }
+            store = self.cond.wait_timeout(store, POLL_INTERVAL).unwrap().0;
+        }
+    }
+
+    fn finish(&self, id: usize, exit_code: i32, output: &str) {
+        if let Err(e) = self.store.lock().unwrap().finish(id, exit_code, output) {
+            warn!(id, "failed to record job result: {}", e);
+        }
+        self.done.notify_all();
+    }
+
+    fn wait(&self, id: Option<usize>) -> io::Result<Response> {
+        let mut store = self.store.lock().unwrap();
+        loop {
+            match id {
+                Some(id) => {
+                    if let Some(exit_code) = store.get(id)?.exit_code {
+                        return Ok(Response::Finished { id, exit_code });
+                    }
+                }
+                None => {
+                    if let (0, failed) = store.counts()? {
+                        return Ok(Response::Idle { failed });
+                    }
+                }
+            }
+            store = self.done.wait_timeout(store, POLL_INTERVAL).unwrap().0;
+        }
+    }
+
+    fn cancel(&self, id: usize) -> io::Result<JobState> {
+        let previous = self.store.lock().unwrap().cancel(id)?;
+        if previous == JobState::Running {
+            if let Some(&pid) = self.running.lock().unwrap().get(&id) {
+                // SAFETY: plain kill(2) call, the pid belongs to a child that wasn't reaped yet
+                unsafe { libc::kill(pid as libc::pid_t, libc::SIGTERM) };
+            }
+        }
+        self.done.notify_all();
+        Ok(previous)
+    }
+
+    fn record(&self, msg: &str) {
+        let mut results = self.results.lock().unwrap();
+        if let Err(e) = results
+            .write_all(msg.as_bytes())
+            .and_then(|_| results.flush())
+        {
+            warn!("failed to write results: {}", e);
+        }
+    }
+}
+
+/// Keeps renewing the leases of the jobs this daemon is running so other daemons don't steal them
+fn renew_leases(shared: Arc<Shared>) {
+    loop {
+        thread::sleep(store::LEASE / 3);
+        let ids: Vec<usize> = shared.running.lock().unwrap().keys().copied().collect();
+        if let Err(e) = shared.store.lock().unwrap().renew(&ids) {
+            warn!("failed to renew job leases: {}", e);
+        }
+    }
+}
+
+fn worker(shared: Arc<Shared>) {
+    loop {
+        let job = shared.next_job();
+        let span = info_span!("job", id = job.id, argv = ?job.argv);
+        let _entered = span.enter();
+        let tag = format!("[{}]", job.id);
+        debug!("spawning");
+        let output = process::Command::new(&job.argv[0])
+            .args(&job.argv[1..])
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .and_then(|child| {
+                shared.running.lock().unwrap().insert(job.id, child.id());
+                let output = child.wait_with_output();
+                shared.running.lock().unwrap().remove(&job.id);
+                output
+            });
+        let (msg, exit_code) = match output {
+            Ok(output) => {
+                info!(status = %output.status, "exited");
+                let msg = format!(
+                    "{}{}{} {}\n",
+                    tag_lines(&tag, &output.stdout),
+                    tag_lines(&tag, &output.stderr),
+                    tag,
+                    output.status
+                );
+                (msg, exit_code(output.status))
+            }
+            Err(e) => (format!("{} unable to spawn process: {}\n", tag, e), 127),
+        };
+        shared.record(&msg);
+        shared.finish(job.id, exit_code, &msg);
+    }
+}
+
+fn handle_client(shared: &Shared, stream: UnixStream) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let response = match serde_json::from_str::<Request>(&line?) {
+            Ok(Request::Submit { argv }) => shared.submit(argv),
+            Ok(Request::Wait { id }) => shared.wait(id),
+            Err(e) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid request: {}", e),
+            )),
+        }
+        .unwrap_or_else(|e| Response::Error {
+            message: e.to_string(),
+        });
+        writeln!(writer, "{}", serde_json::to_string(&response)?)?;
+    }
+    Ok(())
+}
+
+fn json_response(stream: &mut TcpStream, status: u16, value: &impl Serialize) -> io::Result<()> {
+    let body = serde_json::to_vec(value)?;
+    http::write_response(stream, status, "application/json", &body)
+}
+
+fn error_status(e: &io::Error) -> u16 {
+    match e.kind() {
+        io::ErrorKind::NotFound => 404,
+        io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => 400,
+        _ => 500,
+    }
+}
+
+#[derive(Deserialize)]
+struct SubmitBody {
+    argv: Vec<String>,
+}
+
+fn handle_http(shared: &Shared, mut stream: TcpStream, token: Option<&str>) -> io::Result<()> {
+    let req = match http::read_request(&mut BufReader::new(stream.try_clone()?)) {
+        Err(e) if e.kind() == io::ErrorKind::FileTooLarge => {
+            return http::write_response(&mut stream, 413, "text/plain", b"");
+        }
+        req => req?,
+    };
+    if let Some(expected) = token {
+        let given = req
+            .authorization
+            .as_deref()
+            .and_then(|a| a.strip_prefix("Bearer "));
+        if !given.is_some_and(|given| token_matches(expected, given)) {
+            debug!(path = %req.path, "http request without a valid token");
+            return http::write_response(&mut stream, 401, "text/plain", b"");
+        }
+    }
+    debug!(method = %req.method, path = %req.path, "http request");
+    let segments: Vec<&str> = req.path.trim_matches('/').split('/').collect();
+    let job_id = |s: &str| {
+        s.parse::<usize>()
+            .map_err(|_| io::Error::new(io::ErrorKind::NotFound, "bad job id"))
+    };
+    let result = match (req.method.as_str(), segments.as_slice()) {
+        ("POST", ["jobs"]) => serde_json::from_slice::<SubmitBody>(&req.body)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+            .and_then(|body| shared.submit(body.argv))
+            .and_then(|resp| match resp {
+                Response::Submitted { id } => {
+                    json_response(&mut stream, 201, &Response::Submitted { id })
+                }
+                other => json_response(&mut stream, 400, &other),
+            }),
+        ("GET", ["jobs"]) => {
+            let jobs = shared.store.lock().unwrap().list();
+            jobs.and_then(|jobs| json_response(&mut stream, 200, &jobs))
+        }
+        ("GET", ["jobs", id]) => {
+            let job = job_id(id).and_then(|id| shared.store.lock().unwrap().get(id));
+            job.and_then(|job| json_response(&mut stream, 200, &job))
+        }
+        ("GET", ["jobs", id, "output"]) => {
+            let output = job_id(id).and_then(|id| shared.store.lock().unwrap().output(id));
+            output.and_then(|output| {
+                let body = output.unwrap_or_default();
+                http::write_response(&mut stream, 200, "text/plain", body.as_bytes())
+            })
+        }
+        ("POST", ["jobs", id, "cancel"]) => job_id(id)
+            .and_then(|id| shared.cancel(id))
+            .and_then(|previous| json_response(&mut stream, 200, &previous)),
+        (_, ["jobs", ..]) => return http::write_response(&mut stream, 405, "text/plain", b""),
+        _ => return http::write_response(&mut stream, 404, "text/plain", b""),
+    };
+    result.or_else(|e| {
+        let message = e.to_string();
+        json_response(&mut stream, error_status(&e), &Response::Error { message })
+    })
+}
+
+fn serve_http(shared: Arc<Shared>, listener: TcpListener, token: Option<String>) {
+    let token: Option<Arc<str>> = token.map(Arc::from);
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let (shared, token) = (shared.clone(), token.clone());
+                thread::spawn(move || {
+                    if let Err(e) = handle_http(&shared, stream, token.as_deref()) {
+                        debug!("http request failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("failed to accept http connection: {}", e),
+        }
+    }
+}
+
+/// Binds the socket, replacing a stale socket file left behind by a daemon that is no longer running
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another daemon is listening on this socket",
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+pub fn run(args: DaemonArgs) -> i32 {
+    if let (Some(addr), None) = (args.http_addr, &args.http_token) {
+        if !addr.ip().is_loopback() {
+            eprintln!(
+                "--http-addr {} can be reached from other machines, it needs --http-token",
+                addr
+            );
+            return 1;
+        }
+    }
+    let results: Box<dyn Write + Send> = match &args.results_log {
+        Some(path) => match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => {
+                eprintln!("unable to open results log: {}", e);
+                return 1;
+            }
+        },
+        None => Box::new(io::stdout()),
+    };
+    let store: Box<dyn JobStore> = match &args.queue_db {
+        Some(path) => match SqliteStore::open(path) {
+            Ok(store) => Box::new(store),
+            Err(e) => {
+                eprintln!("unable to open queue database: {}", e);
+                return 1;
+            }
+        },
+        None => Box::<MemoryStore>::default(),
+    };
+    let listener = match bind(&args.socket) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("unable to listen on {}: {}", args.socket.display(), e);
+            return 1;
+        }
+    };
+
+    let shared = Arc::new(Shared {
+        store: Mutex::new(store),
+        running: Mutex::new(HashMap::new()),
+        cond: Condvar::new(),
+        done: Condvar::new(),
+        results: Mutex::new(results),
+        max_pending: args.max_pending,
+    });
+    for _ in 0..args.max_parallelism {
+        let shared = shared.clone();
+        thread::spawn(move || worker(shared));
+    }
+    {
+        let shared = shared.clone();
+        thread::spawn(move || renew_leases(shared));
+    }
+
+    if let Some(addr) = args.http_addr {
+        match TcpListener::bind(addr) {
+            Ok(listener) => {
+                let (shared, token) = (shared.clone(), args.http_token.clone());
+                thread::spawn(move || serve_http(shared, listener, token));
+                info!(%addr, "serving http api");
+            }
+            Err(e) => {
+                eprintln!("unable to listen on {}: {}", addr, e);
+                return 1;
+            }
+        }
+    }
+
+    info!(socket = %args.socket.display(), "listening");
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    if let Err(e) = handle_client(&shared, stream) {
+                        debug!("client disconnected: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("failed to accept connection: {}", e),
+        }
+    }
+    0
+}
@@ -0,0 +1,98 @@
+use crate::output::command_line;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{debug, warn};
+
+/// Runs a test command for each record on a few worker threads, letting through the records it succeeds for
+///
+/// Records come out in the order their test finishes, not the input order. Pushing blocks while every worker is
+/// busy, so the input isn't read ahead of the tests.
+pub struct Filter {
+    records: Option<SyncSender<String>>,
+    passed: Receiver<String>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+fn passes(template: &str, record: &str) -> bool {
+    let line = command_line(template, &[record]);
+    match Command::new("sh")
+        .args(["-c", &line])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .status()
+    {
+        Ok(status) => {
+            debug!(command = %line, %status, "filter finished");
+            status.success()
+        }
+        Err(e) => {
+            warn!(command = %line, "unable to spawn filter: {}", e);
+            false
+        }
+    }
+}
+
+impl Filter {
+    pub fn new(template: String, parallelism: usize) -> Filter {
+        let (records, receiver) = mpsc::sync_channel::<String>(parallelism);
+        let (sender, passed) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let template = Arc::new(template);
+        let workers = (0..parallelism)
+            .map(|_| {
+                let (receiver, sender, template) =
+                    (receiver.clone(), sender.clone(), template.clone());
+                thread::spawn(move || loop {
+                    let record = match receiver.lock().unwrap().recv() {
+                        Ok(record) => record,
+                        Err(_) => break,
+                    };
+                    if passes(&template, &record) && sender.send(record).is_err() {
+                        break;
+                    }
+                })
+            })
+            .collect();
+        Filter {
+            records: Some(records),
+            passed,
+            workers,
+        }
+    }
+
+    /// Queues a record for testing and returns the records that passed so far
+    pub fn push(&mut self, record: String) -> Vec<String> {
+        if let Some(records) = &self.records {
+            let _ = records.send(record);
+        }
+        self.passed.try_iter().collect()
+    }
+
+    /// Waits for the pending tests and returns the remaining records that passed
+    pub fn finish(mut self) -> Vec<String> {
+        self.records = None;
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+        self.passed.try_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Filter;
+
+    #[test]
+    fn filter_works() {
+        let mut filter = Filter::new("test {} -gt 2".into(), 2);
+        let mut passed = vec![];
+        for n in 1..=5 {
+            passed.extend(filter.push(n.to_string()));
+        }
+        passed.extend(filter.finish());
+        passed.sort();
+        assert_eq!(passed, ["3", "4", "5"]);
+    }
+}
@@ -0,0 +1,52 @@
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Where a run is at, as printed by --heartbeat
+#[derive(Serialize, Clone, Copy, Default, Debug)]
+pub struct Counts {
+    pub running: usize,
+    pub done: usize,
+    pub failed: usize,
+    /// Argument lists built but not started yet, the input still to be read isn't known
+    pub queued: usize,
+}
+
+fn line(counts: &Counts) -> String {
+    format!(
+        "running={} done={} failed={} queued~={}",
+        counts.running, counts.done, counts.failed, counts.queued
+    )
+}
+
+/// Prints the counts to stderr every `interval` from a background thread, for as long as pll runs
+///
+/// The thread keeps printing while pll waits for input, which is when a stalled run is hardest to tell apart
+/// from a busy one.
+pub fn start(interval: Duration) -> Arc<Mutex<Counts>> {
+    let counts = Arc::new(Mutex::new(Counts::default()));
+    let shared = counts.clone();
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let counts = *shared.lock().unwrap();
+        eprintln!("{}", line(&counts));
+    });
+    counts
+}
+
+#[cfg(test)]
+mod test {
+    use super::{line, Counts};
+
+    #[test]
+    fn line_works() {
+        let counts = Counts {
+            running: 7,
+            done: 1032,
+            failed: 3,
+            queued: 12,
+        };
+        assert_eq!(line(&counts), "running=7 done=1032 failed=3 queued~=12");
+    }
+}
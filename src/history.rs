@@ -0,0 +1,104 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Directory of the files pll keeps between runs
+pub fn cache_dir() -> PathBuf {
+    env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(env::temp_dir)
+        .join("pll")
+}
+
+/// Identifies a command across runs: its words joined by NUL, with arguments naming files made absolute
+///
+/// The same input given as `./a.txt` or `a.txt` is the same job, while the same relative path from another
+/// directory isn't.
+pub fn key(command: &[String]) -> String {
+    let words: Vec<String> = command
+        .iter()
+        .map(|word| {
+            fs::canonicalize(word)
+                .map_or_else(|_| word.clone(), |path| path.to_string_lossy().into_owned())
+        })
+        .collect();
+    words.join("\0")
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Runtimes {
+    /// Milliseconds by command key, averaged over the past runs
+    jobs: HashMap<String, u64>,
+}
+
+/// Durations of past jobs, used to predict how long the same jobs take in the next runs
+pub struct History {
+    path: PathBuf,
+    runtimes: Runtimes,
+}
+
+impl History {
+    /// Loads the cache, starting from an empty one when there is none
+    pub fn load() -> io::Result<History> {
+        let path = cache_dir().join("runtimes.json");
+        let runtimes = match fs::read(&path) {
+            Ok(json) => serde_json::from_slice(&json)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Runtimes::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(History { path, runtimes })
+    }
+
+    pub fn predict(&self, command: &[String]) -> Option<Duration> {
+        self.runtimes
+            .jobs
+            .get(&key(command))
+            .map(|&ms| Duration::from_millis(ms))
+    }
+
+    /// Accounts for a successful job, older durations weigh half as much as the latest one
+    pub fn record(&mut self, command: &[String], took: Duration) {
+        let ms = took.as_millis() as u64;
+        self.runtimes
+            .jobs
+            .entry(key(command))
+            .and_modify(|avg| *avg = (*avg + ms) / 2)
+            .or_insert(ms);
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        fs::create_dir_all(cache_dir())?;
+        // written aside and renamed so concurrent runs never read a partial file
+        let tmp = self
+            .path
+            .with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&tmp, serde_json::to_vec(&self.runtimes)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{key, History, Runtimes};
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn record_works() {
+        let mut history = History {
+            path: PathBuf::new(),
+            runtimes: Runtimes::default(),
+        };
+        let command = vec!["sleep".to_owned(), "1".to_owned()];
+        assert_eq!(history.predict(&command), None);
+        history.record(&command, Duration::from_millis(1000));
+        history.record(&command, Duration::from_millis(2000));
+        assert_eq!(history.predict(&command), Some(Duration::from_millis(1500)));
+        assert_eq!(key(&command), "sleep\u{0}1");
+    }
+}
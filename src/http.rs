@@ -0,0 +1,123 @@
+use std::io::{self, BufRead, Read, Write};
+
+/// Largest request body read, bigger ones are refused with 413 without reading them
+pub const MAX_BODY: u64 = 1 << 20;
+
+/// The bits of an HTTP/1.1 request the API cares about
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Value of the Authorization header
+    pub authorization: Option<String>,
+    pub body: Vec<u8>,
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_owned())
+}
+
+/// Fails with `ErrorKind::FileTooLarge` when the body is over `MAX_BODY`
+pub fn read_request(reader: &mut impl BufRead) -> io::Result<Request> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_owned(), path.to_owned()),
+        _ => return Err(invalid("malformed request line")),
+    };
+
+    let mut content_length: u64 = 0;
+    let mut authorization = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| invalid("bad content-length"))?;
+            } else if name.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_owned());
+            }
+        }
+    }
+
+    if content_length > MAX_BODY {
+        return Err(io::Error::new(
+            io::ErrorKind::FileTooLarge,
+            "request body too large",
+        ));
+    }
+    let mut body = vec![];
+    reader.take(content_length).read_to_end(&mut body)?;
+    if (body.len() as u64) < content_length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(Request {
+        method,
+        path,
+        authorization,
+        body,
+    })
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+pub fn write_response(
+    writer: &mut impl Write,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        reason(status),
+        content_type,
+        body.len()
+    )?;
+    writer.write_all(body)?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::read_request;
+    use std::io;
+
+    #[test]
+    fn read_request_works() {
+        let raw = b"POST /jobs HTTP/1.1\r\nHost: x\r\nAuthorization: Bearer t\r\nContent-Length: 4\r\n\r\nbodyextra";
+        let req = read_request(&mut &raw[..]).unwrap();
+        assert_eq!((req.method.as_str(), req.path.as_str()), ("POST", "/jobs"));
+        assert_eq!(req.authorization.as_deref(), Some("Bearer t"));
+        assert_eq!(req.body, b"body");
+        let raw = b"POST /jobs HTTP/1.1\r\nContent-Length: 1099511627776\r\n\r\n";
+        let err = read_request(&mut &raw[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::FileTooLarge);
+        let raw = b"POST /jobs HTTP/1.1\r\nContent-Length: 18446744073709551616\r\n\r\n";
+        assert!(read_request(&mut &raw[..]).is_err());
+        let raw = b"POST /jobs HTTP/1.1\r\nContent-Length: 8\r\n\r\nbody";
+        let err = read_request(&mut &raw[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
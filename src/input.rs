@@ -0,0 +1,359 @@
+use crate::pool;
+use crate::split::{clean_record, Delims, ManySplit};
+use crate::{args, collate, filter, map, redis, source, stop};
+use std::io::BufRead;
+use std::process;
+use std::str;
+
+/// Size of the blocks given to the jobs with --pipe when --block-size isn't
+const DEFAULT_BLOCK_SIZE: usize = 1 << 20;
+
+pub(crate) type Pool = pool::ProcPool<args::ArgBuilderType, args::DynArgBuilderMaker>;
+
+/// Steps input records go through before reaching the pool: --flush-on, --shard, --match, --skip-regex, --map,
+/// --filter and --collate-by
+pub(crate) struct Stages {
+    pub flush_on: Option<String>,
+    pub shard: Option<(usize, usize)>,
+    /// Records fed so far, for --shard
+    pub fed: usize,
+    pub only: Vec<regex::Regex>,
+    pub skip: Vec<regex::Regex>,
+    pub map: Vec<map::Transform>,
+    pub filter: Option<filter::Filter>,
+    pub collate: Option<collate::Collate>,
+}
+
+impl Stages {
+    pub fn feed(&mut self, pool: &mut Pool, record: &str) -> Result<(), pool::Error> {
+        if self.flush_on.as_deref() == Some(record) {
+            pool.flush();
+            return Ok(());
+        }
+        if !self.in_shard() {
+            return Ok(());
+        }
+        if !self.matches(record) {
+            pool.skip_arg();
+            return Ok(());
+        }
+        let Some(record) = map::apply(&self.map, record) else {
+            return Ok(());
+        };
+        match &mut self.filter {
+            Some(filter) => {
+                for record in filter.push(record) {
+                    self.push(pool, record)?;
+                }
+                Ok(())
+            }
+            None => self.push(pool, record),
+        }
+    }
+
+    /// Feeds the records of a job made of one record of each `:::` or --arg-file source
+    ///
+    /// Every record must match for the job to run, and --shard splits jobs instead of records.
+    pub fn feed_combination(
+        &mut self,
+        pool: &mut Pool,
+        combination: &[String],
+    ) -> Result<(), pool::Error> {
+        if !self.in_shard() {
+            return Ok(());
+        }
+        let mut records = vec![];
+        for record in combination {
+            if !self.matches(record) {
+                pool.skip_arg();
+                return Ok(());
+            }
+            match map::apply(&self.map, record) {
+                Some(record) => records.push(record),
+                None => return Ok(()),
+            }
+        }
+        pool.push_job(records)
+    }
+
+    /// Counts what is fed, telling whether it belongs to this --shard
+    fn in_shard(&mut self) -> bool {
+        let idx = self.fed;
+        self.fed += 1;
+        self.shard.is_none_or(|(k, m)| idx % m == k)
+    }
+
+    /// Whether a record passes --match and --skip-regex
+    fn matches(&self, record: &str) -> bool {
+        let matched = self.only.is_empty() || self.only.iter().any(|re| re.is_match(record));
+        matched && !self.skip.iter().any(|re| re.is_match(record))
+    }
+
+    fn push(&mut self, pool: &mut Pool, record: String) -> Result<(), pool::Error> {
+        match &mut self.collate {
+            // a --collate-by group is a job of its own
+            Some(collate) => match collate.push(record) {
+                Some(group) => pool.push_job(group),
+                None => Ok(()),
+            },
+            None => pool.push_arg(&record),
+        }
+    }
+
+    /// Pushes the records still held by the stages once the input ended
+    pub fn finish(mut self, pool: &mut Pool) -> Result<(), pool::Error> {
+        for record in self
+            .filter
+            .take()
+            .map(filter::Filter::finish)
+            .unwrap_or_default()
+        {
+            self.push(pool, record)?;
+        }
+        for group in self
+            .collate
+            .map(collate::Collate::finish)
+            .unwrap_or_default()
+        {
+            pool.push_job(group)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether to stop on an argument showing signs of quoting meant to protect its whitespace, warning only once
+///
+/// Backslashes are fine when they are escapes of their own.
+fn refuse_quoted(arg: &str, force: bool, escapes: bool, warned: &mut bool) -> bool {
+    let suspicious: &[char] = if escapes {
+        &['\'', '"']
+    } else {
+        &['\'', '"', '\\']
+    };
+    if !arg.contains(suspicious) {
+        return false;
+    }
+    if !*warned {
+        eprintln!(
+            "quote or backslash in argument {:?}, input is split on whitespace regardless of quoting; use -0 or -d to choose separators{}",
+            arg,
+            if force { "" } else { ", or --force to run anyway" }
+        );
+        *warned = true;
+    }
+    !force
+}
+
+/// Delimiters of the input records given -d, -0 and --unicode-ws, whitespace by default or newlines with --pipe
+pub(crate) fn delims(delim: &[String], null_sep: bool, unicode_ws: bool, pipe: bool) -> Delims {
+    let default_delims = delim.is_empty() && !null_sep;
+    if unicode_ws {
+        Delims::UnicodeWs
+    } else if default_delims && pipe {
+        Delims::Bytes(vec![b'\n'])
+    } else if default_delims {
+        Delims::Bytes(vec![b'\n', b'\t', b' '])
+    } else {
+        let mut d: Vec<u8> = delim.iter().flat_map(|v| v.bytes()).collect();
+        if null_sep {
+            d.push(b'\0');
+        }
+        d.sort_unstable();
+        d.dedup();
+        Delims::Bytes(d)
+    }
+}
+
+/// Where the input records come from and how they are read, the part of the command line the input loop needs
+pub(crate) struct Input {
+    pub redis_queue: Option<Vec<String>>,
+    /// Combinations of the `:::` and --arg-file sources, read instead of stdin
+    pub product: Option<source::Product>,
+    pub pipe: bool,
+    pub delims: Delims,
+    /// Whether the input is split on the default whitespace, arguments looking quoted are refused then
+    pub default_delims: bool,
+    pub backslash_escapes: bool,
+    pub max_record_size: Option<u64>,
+    pub block_records: Option<usize>,
+    pub block_size: Option<u64>,
+    pub max_lines: Option<usize>,
+    pub eof: Option<String>,
+    pub force: bool,
+}
+
+impl Input {
+    /// Feeds every input record to the pool through the stages, returning whether some input was refused
+    ///
+    /// Stops early once the pool halted, and writes the input left with --remaining when pll gets stopped.
+    pub fn read(self, stages: &mut Stages, pool: &mut Pool) -> Result<bool, pool::Error> {
+        let mut quote_warned = false;
+        if let Some(redis_queue) = self.redis_queue {
+            let queue = redis::RedisQueue::connect(&redis_queue[0], &redis_queue[1])
+                .unwrap_or_else(|e| {
+                    eprintln!("unable to connect to redis: {}", e);
+                    process::exit(1);
+                });
+            let mut skipped = false;
+            for result in queue {
+                let buf = match result {
+                    Ok(buf) => buf,
+                    Err(e) => {
+                        eprintln!("failed to pop from redis: {}", e);
+                        return Ok(true);
+                    }
+                };
+                // popped already, so the rest of the queue is still run
+                let Ok(arg) = str::from_utf8(&buf) else {
+                    eprintln!(
+                        "skipping redis item {:?}, it isn't valid UTF-8",
+                        String::from_utf8_lossy(&buf)
+                    );
+                    skipped = true;
+                    continue;
+                };
+                if self.eof.as_deref() == Some(arg) {
+                    break;
+                }
+                stages.feed(pool, arg)?;
+                if pool.halted() {
+                    break;
+                }
+                // what is still queued stays in redis
+                if stop::stopping().is_some() {
+                    pool.halt(std::iter::empty())?;
+                    break;
+                }
+            }
+            return Ok(skipped);
+        } else if let Some(product) = self.product {
+            for combination in product {
+                let combination = combination.unwrap_or_else(|e| {
+                    eprintln!("failed to read input: {}", e);
+                    process::exit(1);
+                });
+                stages.feed_combination(pool, &combination)?;
+                if pool.halted() {
+                    break;
+                }
+            }
+        } else if self.pipe {
+            let max_len = self.max_record_size.map_or(usize::MAX, |n| n as usize);
+            let (max_records, max_bytes) = match (self.block_records, self.block_size) {
+                (Some(records), _) => (records, usize::MAX),
+                (None, size) => (
+                    usize::MAX,
+                    size.map_or(DEFAULT_BLOCK_SIZE, |n| {
+                        usize::try_from(n).unwrap_or(usize::MAX)
+                    }),
+                ),
+            };
+            let blocks = std::io::stdin()
+                .lock()
+                .split_any(&self.delims)
+                .max_len(max_len)
+                .escapes(self.backslash_escapes)
+                .blocks(max_records, max_bytes);
+            for block in blocks {
+                match block {
+                    Ok(block) => pool.push_block(block)?,
+                    Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                        eprintln!("{}, over --max-record-size; is a delimiter missing?", e);
+                        return Ok(true);
+                    }
+                    Err(e) => {
+                        eprintln!("failed to read input: {}", e);
+                        process::exit(1);
+                    }
+                }
+                if pool.halted() {
+                    break;
+                }
+            }
+        } else if let Some(max_lines) = self.max_lines {
+            let mut lines = 0;
+            let mut input = std::io::stdin().lock().lines();
+            while let Some(line) = input.next() {
+                let line = line.expect("failed to read line");
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let mut words = line.split_whitespace();
+                while let Some(word) = words.next() {
+                    if self.eof.as_deref() == Some(word) {
+                        return Ok(false);
+                    }
+                    if refuse_quoted(word, self.force, false, &mut quote_warned) {
+                        return Ok(true);
+                    }
+                    stages.feed(pool, word)?;
+                    if pool.halted() {
+                        return Ok(false);
+                    }
+                    if stop::stopping().is_some() {
+                        let words = words.map(String::from).collect::<Vec<_>>();
+                        let rest = input.map_while(Result::ok).flat_map(|line| {
+                            let words: Vec<String> =
+                                line.split_whitespace().map(String::from).collect();
+                            words
+                        });
+                        pool.halt(words.into_iter().chain(rest))?;
+                        return Ok(false);
+                    }
+                }
+                lines += 1;
+                if lines == max_lines {
+                    pool.flush();
+                    lines = 0;
+                }
+            }
+        } else {
+            let max_len = self.max_record_size.map_or(usize::MAX, |n| n as usize);
+            let mut input = std::io::stdin()
+                .lock()
+                .split_any(&self.delims)
+                .max_len(max_len)
+                .escapes(self.backslash_escapes);
+            while let Some(result) = input.next() {
+                let buf = match result {
+                    Ok(buf) => buf,
+                    Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+                        eprintln!("{}, over --max-record-size; is a delimiter missing?", e);
+                        return Ok(true);
+                    }
+                    Err(e) => panic!("failed to read argument buf: {}", e),
+                };
+                if let Some(arg) = clean_record(&self.delims, self.backslash_escapes, &buf) {
+                    let Ok(arg) = str::from_utf8(&arg) else {
+                        eprintln!(
+                            "record {:?} isn't valid UTF-8",
+                            String::from_utf8_lossy(&arg)
+                        );
+                        return Ok(true);
+                    };
+                    if self.eof.as_deref() == Some(arg) {
+                        break;
+                    }
+                    if self.default_delims
+                        && refuse_quoted(arg, self.force, self.backslash_escapes, &mut quote_warned)
+                    {
+                        return Ok(true);
+                    }
+                    stages.feed(pool, arg)?;
+                }
+                if pool.halted() {
+                    break;
+                }
+                if stop::stopping().is_some() {
+                    let rest = input.map_while(Result::ok).filter_map(|buf| {
+                        clean_record(&self.delims, self.backslash_escapes, &buf)
+                            .map(|arg| String::from_utf8_lossy(&arg).into_owned())
+                    });
+                    pool.halt(rest)?;
+                    break;
+                }
+            }
+        }
+        Ok(false)
+    }
+}
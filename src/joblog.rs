@@ -0,0 +1,167 @@
+use crate::output::shell_quote;
+use crate::status::Outcome;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Same columns as the joblog of GNU parallel, so the tools reading one read the other
+const HEADER: &str = "Seq\tHost\tStarttime\tJobRuntime\tSend\tReceive\tExitval\tSignal\tCommand\n";
+
+fn command_line(command: &[String]) -> String {
+    let words: Vec<String> = command.iter().map(|w| shell_quote(w)).collect();
+    words.join(" ")
+}
+
+/// Sequence number, success and command line of every job in a joblog, in the order they finished
+fn entries(log: &str) -> impl Iterator<Item = (usize, bool, &str)> {
+    log.lines()
+        .filter(|line| !line.starts_with("Seq\t"))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.splitn(9, '\t').collect();
+            let [seq, _, _, _, _, _, exit, signal, command] = fields[..] else {
+                return None;
+            };
+            Some((seq.parse().ok()?, exit == "0" && signal == "0", command))
+        })
+}
+
+/// Commands of the jobs a joblog shows as succeeded, by sequence number
+fn succeeded(log: &str) -> HashMap<usize, String> {
+    entries(log)
+        .filter(|&(_, success, _)| success)
+        .map(|(seq, _, command)| (seq, command.to_owned()))
+        .collect()
+}
+
+/// Command lines of the jobs of a joblog by sequence number, only of the ones that didn't succeed with
+/// `only_failed`
+///
+/// A job logged more than once, by runs carried on with --resume, counts as it ended the last time.
+pub fn commands(log: &str, only_failed: bool) -> Vec<String> {
+    let last: BTreeMap<usize, (bool, &str)> = entries(log)
+        .map(|(seq, success, command)| (seq, (success, command)))
+        .collect();
+    last.into_values()
+        .filter(|&(success, _)| !(only_failed && success))
+        .map(|(_, command)| command.to_owned())
+        .collect()
+}
+
+/// Line of every finished job written with --joblog, as soon as it finished so nothing is lost on a crash
+pub struct Joblog {
+    path: PathBuf,
+    out: File,
+    /// Jobs that succeeded in the runs logged before, with --resume
+    done: HashMap<usize, String>,
+}
+
+impl Joblog {
+    /// Starts a new log, or carries on with the one there is with `resume`
+    pub fn open(path: PathBuf, resume: bool) -> io::Result<Joblog> {
+        let done = match fs::read_to_string(&path) {
+            Ok(log) if resume => succeeded(&log),
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => HashMap::new(),
+        };
+        let exists = resume && fs::metadata(&path).is_ok_and(|m| m.len() > 0);
+        let mut out = File::options()
+            .create(true)
+            .append(resume)
+            .write(true)
+            .truncate(!resume)
+            .open(&path)?;
+        if !exists {
+            out.write_all(HEADER.as_bytes())?;
+        }
+        Ok(Joblog { path, out, done })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the job succeeded in a previous run, which only counts when it ran the same command
+    pub fn done(&self, seq: usize, command: &[String]) -> bool {
+        let Some(logged) = self.done.get(&seq) else {
+            return false;
+        };
+        let line = command_line(command);
+        if *logged != line {
+            warn!(
+                seq,
+                logged,
+                command = line,
+                "joblog doesn't match the input, running the job again"
+            );
+        }
+        *logged == line
+    }
+
+    /// `host` is the agent that ran the job, `:` stands for this machine like in GNU parallel
+    pub fn job_finished(
+        &mut self,
+        seq: usize,
+        host: &str,
+        started_at: Instant,
+        exit_code: i32,
+        outcome: Outcome,
+        command: &[String],
+    ) -> io::Result<()> {
+        let runtime = started_at.elapsed();
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .saturating_sub(runtime);
+        let signal = match outcome {
+            Outcome::Signaled | Outcome::TimedOut => exit_code - 128,
+            _ => 0,
+        };
+        let line = format!(
+            "{}\t{}\t{:.3}\t{:.3}\t0\t0\t{}\t{}\t{}\n",
+            seq,
+            host,
+            start.as_secs_f64(),
+            runtime.as_secs_f64(),
+            exit_code,
+            signal,
+            command_line(command)
+        );
+        self.out.write_all(line.as_bytes())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{commands, succeeded, HEADER};
+
+    #[test]
+    fn succeeded_works() {
+        let log = format!(
+            "{}{}{}{}",
+            HEADER,
+            "0\t:\t1700000000.000\t1.000\t0\t0\t0\t0\techo 'a b'\n",
+            "1\t:\t1700000000.000\t1.000\t0\t0\t1\t0\tfalse\n",
+            "2\t:\t1700000000.000\t1.000\t0\t0\t137\t9\tsleep 9\n",
+        );
+        let done = succeeded(&log);
+        assert_eq!(done.len(), 1);
+        assert_eq!(done[&0], "echo 'a b'");
+    }
+
+    #[test]
+    fn commands_works() {
+        let log = format!(
+            "{}{}{}{}{}",
+            HEADER,
+            "1\t:\t1700000000.000\t1.000\t0\t0\t1\t0\tfalse\n",
+            "0\t:\t1700000000.000\t1.000\t0\t0\t0\t0\techo 'a\tb'\n",
+            "2\t:\t1700000000.000\t1.000\t0\t0\t137\t9\tsleep 9\n",
+            "1\t:\t1700000001.000\t1.000\t0\t0\t0\t0\ttrue\n",
+        );
+        assert_eq!(commands(&log, false), ["echo 'a\tb'", "true", "sleep 9"]);
+        assert_eq!(commands(&log, true), ["sleep 9"]);
+    }
+}
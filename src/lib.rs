@@ -0,0 +1,89 @@
+//! Runs programs in parallel with arguments read from an input, like xargs -P or GNU parallel
+//!
+//! This is what the `pll` binary is built on. The pieces to embed it are:
+//!
+//! - [`ProcPool`], started with [`ProcPool::builder`], runs the jobs with up to `max_parallelism` of them at once.
+//!   Records are pushed with [`ProcPool::push_arg`] or as a whole job with [`ProcPool::push_job`], a job can
+//!   read a block of input on stdin instead with [`ProcPool::push_block`], and [`ProcPool::wait_all`] returns how
+//!   every job ended.
+//! - [`ManySplit::split_any`] splits a reader on any of a set of delimiters, and [`SplitMany::blocks`] groups the
+//!   records in blocks.
+//! - [`ArgBuilder`] and [`ArgBuilderMaker`] turn the records into argument lists, [`DynArgBuilderMaker`] appends
+//!   them to the program or fills in a template like the `pll` command line does.
+//!
+//! ```
+//! use pll::{DynArgBuilderMaker, ProcPool};
+//! use std::time::Duration;
+//!
+//! // runs `test <record> = a` for every record
+//! let maker = DynArgBuilderMaker::template(vec!["{}".into(), "=".into(), "a".into()])?;
+//! let mut pool = ProcPool::builder("test".into(), maker)
+//!     .max_parallelism(2)
+//!     .timeout(Duration::from_secs(10))
+//!     .build()?;
+//! for record in ["a", "b"] {
+//!     pool.push_arg(record)?;
+//! }
+//! let results = pool.wait_all()?;
+//! assert_eq!(results.iter().map(|r| r.exit_code).collect::<Vec<_>>(), [0, 1]);
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+//!
+//! [`status`] tells how the jobs ended. The `pll` command line itself is in a hidden module, for the binary, and
+//! comes with no stability guarantees.
+
+pub(crate) mod account;
+pub(crate) mod agent;
+pub(crate) mod alloc;
+pub mod args;
+pub(crate) mod backoff;
+pub(crate) mod bg;
+pub(crate) mod changes;
+pub(crate) mod chat;
+#[doc(hidden)]
+pub mod cli;
+pub(crate) mod client;
+pub(crate) mod collate;
+pub(crate) mod control;
+pub(crate) mod daemon;
+pub(crate) mod filter;
+pub(crate) mod heartbeat;
+pub(crate) mod history;
+pub(crate) mod http;
+pub(crate) mod input;
+pub(crate) mod joblog;
+pub(crate) mod log;
+pub(crate) mod map;
+pub(crate) mod notify;
+#[cfg(feature = "otel")]
+pub(crate) mod otel;
+pub(crate) mod output;
+pub(crate) mod placeholder;
+pub mod pool;
+pub(crate) mod power;
+pub(crate) mod printer;
+pub(crate) mod redis;
+pub(crate) mod remaining;
+pub(crate) mod repl;
+pub(crate) mod sandbox;
+pub(crate) mod schedule;
+pub(crate) mod sem;
+pub(crate) mod simulate;
+pub(crate) mod slots;
+pub(crate) mod snapshot;
+pub(crate) mod source;
+pub mod split;
+pub(crate) mod stage;
+pub mod status;
+pub(crate) mod stop;
+pub(crate) mod store;
+pub(crate) mod trace;
+pub(crate) mod transport;
+pub(crate) mod tune;
+pub(crate) mod usage;
+pub(crate) mod wakeup;
+pub(crate) mod webhook;
+
+pub use args::{ArgBuilder, ArgBuilderMaker, DynArgBuilderMaker};
+pub use pool::{JobResult, PoolBuilder, PoolOptions, ProcPool};
+pub use split::{Blocks, Delims, ManySplit, SplitMany};
@@ -0,0 +1,36 @@
+use clap::ArgAction;
+use std::fs::File;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing::Level;
+
+#[derive(clap::Args, Debug)]
+pub struct LogArgs {
+    #[arg(short, long, action = ArgAction::Count, global = true)]
+    /// Increase the verbosity of diagnostics (-v info, -vv debug, -vvv trace)
+    verbose: u8,
+
+    #[arg(long, global = true)]
+    /// Write diagnostics to the given file instead of stderr
+    log_file: Option<PathBuf>,
+}
+
+/// Installs the global tracing subscriber according to the verbosity flags
+pub fn init(args: &LogArgs) -> io::Result<()> {
+    let level = match args.verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    };
+    let builder = tracing_subscriber::fmt().with_max_level(level);
+    match &args.log_file {
+        Some(path) => builder
+            .with_ansi(false)
+            .with_writer(Mutex::new(File::create(path)?))
+            .init(),
+        None => builder.with_writer(io::stderr).init(),
+    }
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::{fs, thread};
+use tracing::warn;
+
+type BuiltinFn = fn(&str) -> Option<String>;
+
+/// A step rewriting each input record before it becomes an argument
+#[derive(Clone, Debug)]
+pub enum Transform {
+    Builtin(BuiltinFn),
+    /// Shell command reading the record on stdin and writing its replacement on stdout
+    Command(String),
+}
+
+const BUILTINS: [(&str, BuiltinFn); 7] = [
+    ("trim", |s| Some(s.trim().to_owned())),
+    ("lower", |s| Some(s.to_lowercase())),
+    ("upper", |s| Some(s.to_uppercase())),
+    ("realpath", |s| {
+        fs::canonicalize(s)
+            .ok()
+            .map(|p| p.to_string_lossy().into_owned())
+    }),
+    ("basename", |s| {
+        Path::new(s)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+    }),
+    ("dirname", |s| {
+        Path::new(s)
+            .parent()
+            .map(|p| p.to_string_lossy().into_owned())
+    }),
+    ("urldecode", url_decode),
+];
+
+fn url_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = s.get(i + 1..i + 3)?;
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// Parses a --map value, `@NAME` being a built-in transform and anything else a shell command
+pub fn parse(s: &str) -> Result<Transform, String> {
+    match s.strip_prefix('@') {
+        Some(name) => BUILTINS
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|&(_, f)| Transform::Builtin(f))
+            .ok_or_else(|| {
+                let names: Vec<&str> = BUILTINS.iter().map(|(n, _)| *n).collect();
+                format!(
+                    "unknown transform '{}', expected one of @{}",
+                    name,
+                    names.join(", @")
+                )
+            }),
+        None => Ok(Transform::Command(s.to_owned())),
+    }
+}
+
+fn run_command(command: &str, record: &str) -> Option<String> {
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| warn!(command, "unable to spawn transform: {}", e))
+        .ok()?;
+    let mut stdin = child.stdin.take()?;
+    let record = record.to_owned();
+    // written from another thread so a command producing output before reading all its input can't deadlock
+    let writer = thread::spawn(move || stdin.write_all(record.as_bytes()));
+    let output = child.wait_with_output().ok()?;
+    let _ = writer.join();
+    if !output.status.success() {
+        warn!(command, status = %output.status, "transform failed, skipping record");
+        return None;
+    }
+    let mut out = String::from_utf8(output.stdout).ok()?;
+    if out.ends_with('\n') {
+        out.pop();
+    }
+    Some(out)
+}
+
+/// Runs a record through every transform in order, returning nothing when one of them rejects it
+pub fn apply(transforms: &[Transform], record: &str) -> Option<String> {
+    let mut record = record.to_owned();
+    for transform in transforms {
+        record = match transform {
+            Transform::Builtin(f) => f(&record),
+            Transform::Command(command) => run_command(command, &record),
+        }?;
+    }
+    Some(record)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{apply, parse};
+
+    #[test]
+    fn apply_works() {
+        let transforms = [parse("@urldecode").unwrap(), parse("@upper").unwrap()];
+        assert_eq!(apply(&transforms, "a%20b+c").as_deref(), Some("A B C"));
+        assert_eq!(apply(&transforms, "bad%zz"), None);
+        let transforms = [parse("@basename").unwrap(), parse("tr a b").unwrap()];
+        assert_eq!(apply(&transforms, "/x/banana").as_deref(), Some("bbnbnb"));
+        assert_eq!(apply(&[parse("false").unwrap()], "x"), None);
+        assert!(parse("@nope").is_err());
+    }
+}
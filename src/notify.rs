@@ -0,0 +1,35 @@
+use std::io::{self, Write};
+use std::process;
+
+fn desktop_notification(title: &str, body: &str) -> bool {
+    let mut cmd = if cfg!(target_os = "macos") {
+        let script = format!("display notification {:?} with title {:?}", body, title);
+        let mut cmd = process::Command::new("osascript");
+        cmd.args(["-e", &script]);
+        cmd
+    } else {
+        let mut cmd = process::Command::new("notify-send");
+        cmd.args([title, body]);
+        cmd
+    };
+    cmd.stdin(process::Stdio::null())
+        .stdout(process::Stdio::null())
+        .stderr(process::Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+/// Tells the user the run is over, ringing the terminal bell when no notification daemon is available
+pub fn run_finished(jobs: usize, failed: usize) {
+    let body = if failed == 0 {
+        format!("{} jobs succeeded", jobs)
+    } else {
+        format!("{} of {} jobs failed", failed, jobs)
+    };
+    if !desktop_notification("pll finished", &body) {
+        let mut stderr = io::stderr();
+        let _ = write!(stderr, "\x07");
+        let _ = stderr.flush();
+    }
+}
@@ -0,0 +1,171 @@
+use crate::status::Outcome;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::Read;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tracing::{debug, warn};
+
+/// Spans sent in a single request at most
+const MAX_BATCH: usize = 512;
+
+/// Random id of `N` bytes, hex encoded as OTLP/JSON wants it
+fn random_id<const N: usize>() -> String {
+    let mut bytes = [0u8; N];
+    if File::open("/dev/urandom")
+        .and_then(|mut f| f.read_exact(&mut bytes))
+        .is_err()
+    {
+        // unique enough, ids only have to differ from the other ones of the run
+        bytes
+            .iter_mut()
+            .zip(now_nanos().to_le_bytes())
+            .for_each(|(b, n)| *b = n);
+    }
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos())
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::String(s) => json!({ "stringValue": s }),
+        Value::Number(n) => json!({ "intValue": n }),
+        Value::Array(values) => {
+            let values: Vec<Value> = values
+                .into_iter()
+                .map(|v| json!({ "stringValue": v }))
+                .collect();
+            json!({ "arrayValue": { "values": values } })
+        }
+        other => json!({ "stringValue": other.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+fn request(spans: Vec<Value>) -> Value {
+    json!({
+        "resourceSpans": [{
+            "resource": { "attributes": [attribute("service.name", json!("pll"))] },
+            "scopeSpans": [{ "scope": { "name": "pll" }, "spans": spans }],
+        }]
+    })
+}
+
+/// Exports a span per job and one for the whole run over OTLP/HTTP, with --otlp-endpoint
+///
+/// Spans are posted from a background thread in batches, the run span last once every job finished.
+pub struct Exporter {
+    trace_id: String,
+    root_id: String,
+    started_at: u128,
+    sender: mpsc::Sender<Value>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Exporter {
+    /// `endpoint` is the base URL of the collector, like `http://localhost:4318`
+    pub fn new(endpoint: &str) -> Exporter {
+        let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+        let (sender, receiver) = mpsc::channel::<Value>();
+        let thread = thread::spawn(move || {
+            while let Ok(span) = receiver.recv() {
+                let mut spans = vec![span];
+                spans.extend(receiver.try_iter().take(MAX_BATCH - 1));
+                let count = spans.len();
+                match ureq::post(&url).send_json(request(spans)) {
+                    Ok(_) => debug!(%url, count, "spans exported"),
+                    Err(e) => warn!(%url, count, "span export failed: {}", e),
+                }
+            }
+        });
+        Exporter {
+            trace_id: random_id::<16>(),
+            root_id: random_id::<8>(),
+            started_at: now_nanos(),
+            sender,
+            thread,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn job_finished(
+        &self,
+        seq: usize,
+        slot: usize,
+        host: &str,
+        command: &[String],
+        started_at: Instant,
+        exit_code: i32,
+        outcome: Outcome,
+    ) {
+        let end = now_nanos();
+        let start = end.saturating_sub(started_at.elapsed().as_nanos());
+        let _ = self.sender.send(json!({
+            "traceId": self.trace_id,
+            "spanId": random_id::<8>(),
+            "parentSpanId": self.root_id,
+            "name": command.first().map_or("job", String::as_str),
+            "kind": 1,
+            "startTimeUnixNano": start.to_string(),
+            "endTimeUnixNano": end.to_string(),
+            "attributes": [
+                attribute("pll.seq", json!(seq)),
+                attribute("pll.slot", json!(slot)),
+                attribute("host.name", json!(host)),
+                attribute("process.command_args", json!(command)),
+                attribute("process.exit.code", json!(exit_code)),
+                attribute("pll.outcome", json!(outcome)),
+            ],
+            "status": { "code": if outcome == Outcome::Success { 0 } else { 2 } },
+        }));
+    }
+
+    /// Sends the span of the run and blocks until every span was posted
+    pub fn finish(self, jobs: usize, failed: usize) {
+        let _ = self.sender.send(json!({
+            "traceId": self.trace_id,
+            "spanId": self.root_id,
+            "name": "pll",
+            "kind": 1,
+            "startTimeUnixNano": self.started_at.to_string(),
+            "endTimeUnixNano": now_nanos().to_string(),
+            "attributes": [
+                attribute("pll.jobs", json!(jobs)),
+                attribute("pll.failed", json!(failed)),
+            ],
+            "status": { "code": if failed == 0 { 0 } else { 2 } },
+        }));
+        drop(self.sender);
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{attribute, random_id};
+    use serde_json::json;
+
+    #[test]
+    fn attribute_works() {
+        assert_eq!(
+            attribute("host.name", json!("a")),
+            json!({ "key": "host.name", "value": { "stringValue": "a" } })
+        );
+        assert_eq!(
+            attribute("pll.slot", json!(3))["value"],
+            json!({ "intValue": 3 })
+        );
+        assert_eq!(
+            attribute("process.command_args", json!(["ls", "-l"]))["value"]["arrayValue"]["values"]
+                [1],
+            json!({ "stringValue": "-l" })
+        );
+        assert_eq!(random_id::<8>().len(), 16);
+    }
+}
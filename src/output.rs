@@ -0,0 +1,227 @@
+use crate::placeholder;
+use regex::{Captures, Regex};
+use std::fs;
+
+/// Prefixes every line of `output` with the job tag
+pub fn tag_lines(tag: &str, output: &[u8]) -> String {
+    String::from_utf8_lossy(output)
+        .lines()
+        .map(|l| format!("{} {}\n", tag, l))
+        .collect()
+}
+
+/// Quotes `arg` for a POSIX shell, leaving it alone when it has nothing the shell would interpret
+pub fn shell_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return arg.to_owned();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+/// Shell running the command line of --shell, which decides how the input records are quoted in it
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shell {
+    #[default]
+    Sh,
+    Bash,
+    /// PowerShell
+    Pwsh,
+    /// The Windows command prompt
+    Cmd,
+}
+
+impl Shell {
+    pub fn program(self) -> &'static str {
+        match self {
+            Shell::Sh => "sh",
+            Shell::Bash => "bash",
+            Shell::Pwsh => "pwsh",
+            Shell::Cmd => "cmd",
+        }
+    }
+
+    /// Arguments of the program in front of the command line
+    pub fn flags(self) -> &'static [&'static str] {
+        match self {
+            Shell::Sh | Shell::Bash => &["-c"],
+            Shell::Pwsh => &["-NoProfile", "-Command"],
+            Shell::Cmd => &["/C"],
+        }
+    }
+
+    /// Quotes `arg` so the shell passes it on as a single argument, as it is
+    pub fn quote(self, arg: &str) -> String {
+        match self {
+            Shell::Sh | Shell::Bash => shell_quote(arg),
+            Shell::Pwsh => pwsh_quote(arg),
+            Shell::Cmd => cmd_quote(arg),
+        }
+    }
+}
+
+/// Quotes `arg` for PowerShell, in which typographic single quotes end a string too
+fn pwsh_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./:".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return arg.to_owned();
+    }
+    let mut quoted = String::from("'");
+    for c in arg.chars() {
+        if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}') {
+            quoted.push(c);
+        }
+        quoted.push(c);
+    }
+    quoted.push('\'');
+    quoted
+}
+
+/// Quotes `arg` for cmd.exe: first the way programs split their command line, with double quotes and backslashes,
+/// then with a caret in front of everything cmd.exe would interpret itself
+fn cmd_quote(arg: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "-_./:".contains(c);
+    if !arg.is_empty() && arg.chars().all(plain) {
+        return arg.to_owned();
+    }
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+            continue;
+        }
+        // backslashes only escape what follows them when it is a double quote
+        let escapes = if c == '"' {
+            backslashes * 2 + 1
+        } else {
+            backslashes
+        };
+        quoted.extend(std::iter::repeat_n('\\', escapes));
+        quoted.push(c);
+        backslashes = 0;
+    }
+    quoted.extend(std::iter::repeat_n('\\', backslashes * 2));
+    quoted.push('"');
+    let mut escaped = String::with_capacity(quoted.len());
+    for c in quoted.chars() {
+        if "()%!^\"<>&|".contains(c) {
+            escaped.push('^');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Builds a shell command line from a template, replacing `{}` with the quoted arguments or appending them when
+/// there is no `{}`
+pub fn command_line(template: &str, args: &[impl AsRef<str>]) -> String {
+    let quoted: Vec<String> = args.iter().map(|a| shell_quote(a.as_ref())).collect();
+    let quoted = quoted.join(" ");
+    if template.contains("{}") {
+        template.replace("{}", &quoted)
+    } else {
+        format!("{} {}", template, quoted)
+    }
+}
+
+/// Path of the --tee file of a job, replacing `{#}` with its sequence number and `{}` with its arguments
+///
+/// Slashes in the arguments are replaced so each job gets a file right where the template says.
+pub fn tee_path(template: &str, seq: usize, args: &[String]) -> String {
+    template
+        .replace("{#}", &seq.to_string())
+        .replace("{}", &args.join(" ").replace('/', "_"))
+}
+
+/// Path of the --stdin-from file of a job, replacing `{N}` with its Nth input record and `{}` with all of them
+pub fn stdin_path(template: &str, inputs: &[String]) -> String {
+    let placeholder = Regex::new(r"\{(\d*)\}").unwrap();
+    placeholder
+        .replace_all(template, |caps: &Captures| match caps[1].parse::<usize>() {
+            Ok(idx) => inputs.get(idx).cloned().unwrap_or_default(),
+            Err(_) => inputs.join(" "),
+        })
+        .into_owned()
+}
+
+/// Path of the --skip-if-newer output of a record, `{}` being the record and `{0}` the record too, with the
+/// arithmetic and filters of placeholders, like `{0|noext}.png`
+pub fn fresh_path(template: &str, record: &str) -> String {
+    let inputs = [record.to_owned()];
+    let path = match placeholder::parse_word(template) {
+        Ok(Some(parts)) => placeholder::render(&parts, &inputs),
+        _ => template.to_owned(),
+    };
+    path.replace("{}", record)
+}
+
+/// Whether the --skip-if-newer output of a record exists and is newer than the file the record names
+pub fn up_to_date(template: &str, record: &str) -> bool {
+    let modified = |path: &str| fs::metadata(path).and_then(|m| m.modified());
+    match (modified(record), modified(&fresh_path(template, record))) {
+        (Ok(input), Ok(output)) => output > input,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{command_line, fresh_path, shell_quote, stdin_path, tee_path, Shell};
+
+    #[test]
+    fn shell_quote_works() {
+        assert_eq!(shell_quote("foo/bar.txt"), "foo/bar.txt");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn shells_quote_their_way() {
+        assert_eq!(Shell::Bash.quote("a b"), "'a b'");
+        assert_eq!(Shell::Pwsh.quote("foo/bar.txt"), "foo/bar.txt");
+        assert_eq!(Shell::Pwsh.quote("it's $x"), "'it''s $x'");
+        assert_eq!(Shell::Pwsh.quote("a\u{2019}b"), "'a\u{2019}\u{2019}b'");
+        assert_eq!(Shell::Cmd.quote("foo/bar.txt"), "foo/bar.txt");
+        assert_eq!(Shell::Cmd.quote("a b"), "^\"a b^\"");
+        assert_eq!(Shell::Cmd.quote("%PATH% & x"), "^\"^%PATH^% ^& x^\"");
+        assert_eq!(Shell::Cmd.quote("say \"hi\""), "^\"say \\^\"hi\\^\"^\"");
+        assert_eq!(Shell::Cmd.quote("dir\\"), "^\"dir\\\\^\"");
+    }
+
+    #[test]
+    fn tee_path_works() {
+        let args = vec!["src/main.rs".to_owned(), "x".to_owned()];
+        assert_eq!(tee_path("logs/{#}.log", 3, &args), "logs/3.log");
+        assert_eq!(tee_path("logs/{}.out", 3, &args), "logs/src_main.rs x.out");
+    }
+
+    #[test]
+    fn fresh_path_works() {
+        assert_eq!(fresh_path("{}.gz", "logs/a.log"), "logs/a.log.gz");
+        assert_eq!(
+            fresh_path("out/{0|basename|noext}.png", "in/a.jpg"),
+            "out/a.png"
+        );
+    }
+
+    #[test]
+    fn stdin_path_works() {
+        let inputs = vec!["cases/a".to_owned(), "{0}".to_owned()];
+        assert_eq!(stdin_path("{0}.in", &inputs), "cases/a.in");
+        assert_eq!(stdin_path("in/{1}-{0}", &inputs), "in/{0}-cases/a");
+        assert_eq!(stdin_path("{}.txt", &inputs[..1]), "cases/a.txt");
+        assert_eq!(stdin_path("{5}", &inputs), "");
+    }
+
+    #[test]
+    fn command_line_works() {
+        assert_eq!(command_line("test -s {}", &["a b"]), "test -s 'a b'");
+        assert_eq!(command_line("test -n", &["x"]), "test -n x");
+        assert_eq!(
+            command_line("cp {} dest/", &["a", "b c"]),
+            "cp a 'b c' dest/"
+        );
+    }
+}
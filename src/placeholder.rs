@@ -0,0 +1,273 @@
+use crate::output::Shell;
+use tracing::warn;
+
+/// Piece of a template word
+#[derive(Clone, Debug, PartialEq)]
+pub enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// A `{N+K|filter|...}` placeholder: the Nth input, with arithmetic and filters applied in that order
+///
+/// `{}` stands for every input instead, joined with spaces, and `{.}`, `{/}`, `{//}` and `{/.}` for them
+/// without extension, as basenames, as dirnames and as basenames without extension.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub idx: usize,
+    all: bool,
+    op: Option<(char, i64)>,
+    filters: Vec<Filter>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Filter {
+    Lower,
+    Upper,
+    /// Everything after the last `/`
+    Basename,
+    /// Everything before the last `/`, `.` when there is none
+    Dirname,
+    /// Without the extension of the last path component
+    Noext,
+}
+
+impl Filter {
+    fn parse(name: &str) -> Result<Filter, String> {
+        match name {
+            "lower" => Ok(Filter::Lower),
+            "upper" => Ok(Filter::Upper),
+            "basename" => Ok(Filter::Basename),
+            "dirname" => Ok(Filter::Dirname),
+            "noext" => Ok(Filter::Noext),
+            _ => Err(format!(
+                "unknown filter '{}', expected lower, upper, basename, dirname or noext",
+                name
+            )),
+        }
+    }
+
+    fn apply(self, value: &str) -> String {
+        match self {
+            Filter::Lower => value.to_lowercase(),
+            Filter::Upper => value.to_uppercase(),
+            Filter::Basename => value.rsplit('/').next().unwrap_or(value).to_owned(),
+            Filter::Dirname => value
+                .rsplit_once('/')
+                .map_or(".", |(dir, _)| dir)
+                .to_owned(),
+            Filter::Noext => match value.rsplit_once('.') {
+                Some((stem, ext))
+                    if !stem.is_empty() && !stem.ends_with('/') && !ext.contains('/') =>
+                {
+                    stem.to_owned()
+                }
+                _ => value.to_owned(),
+            },
+        }
+    }
+}
+
+impl Field {
+    /// Parses what is between the braces, `None` when it doesn't start with an index and isn't a `{}` one
+    fn parse(inner: &str) -> Result<Option<Field>, String> {
+        let filters = match inner {
+            "" => Some(vec![]),
+            "." => Some(vec![Filter::Noext]),
+            "/" => Some(vec![Filter::Basename]),
+            "//" => Some(vec![Filter::Dirname]),
+            "/." => Some(vec![Filter::Basename, Filter::Noext]),
+            _ => None,
+        };
+        if let Some(filters) = filters {
+            return Ok(Some(Field {
+                idx: 0,
+                all: true,
+                op: None,
+                filters,
+            }));
+        }
+        let digits = inner
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(inner.len());
+        if digits == 0 {
+            return Ok(None);
+        }
+        let idx = inner[..digits]
+            .parse()
+            .map_err(|_| format!("invalid index in {{{}}}", inner))?;
+        let (expr, filters) = match inner[digits..].split_once('|') {
+            Some((expr, filters)) => (expr, Some(filters)),
+            None => (&inner[digits..], None),
+        };
+        let op = match expr.chars().next() {
+            None => None,
+            Some(op @ ('+' | '-' | '*' | '/' | '%')) => match expr[1..].parse::<i64>() {
+                Ok(0) if op == '/' || op == '%' => {
+                    return Err(format!("division by zero in {{{}}}", inner))
+                }
+                Ok(n) => Some((op, n)),
+                Err(_) => return Err(format!("invalid number in {{{}}}", inner)),
+            },
+            Some(_) => return Err(format!("invalid placeholder {{{}}}", inner)),
+        };
+        let filters = filters
+            .map(|f| f.split('|').map(Filter::parse).collect())
+            .transpose()?
+            .unwrap_or_default();
+        Ok(Some(Field {
+            idx,
+            all: false,
+            op,
+            filters,
+        }))
+    }
+
+    /// Whether it is a plain `{N}`
+    pub fn is_plain(&self) -> bool {
+        !self.all && self.op.is_none() && self.filters.is_empty()
+    }
+
+    fn render(&self, value: &str) -> String {
+        let mut value = match self.op {
+            None => value.to_owned(),
+            Some((op, n)) => {
+                let computed = value.trim().parse::<i64>().ok().and_then(|x| match op {
+                    '+' => x.checked_add(n),
+                    '-' => x.checked_sub(n),
+                    '*' => x.checked_mul(n),
+                    '/' => x.checked_div(n),
+                    _ => x.checked_rem(n),
+                });
+                match computed {
+                    Some(computed) => computed.to_string(),
+                    None => {
+                        warn!("no arithmetic on '{}', not a number or out of range", value);
+                        value.to_owned()
+                    }
+                }
+            }
+        };
+        for filter in &self.filters {
+            value = filter.apply(&value);
+        }
+        value
+    }
+}
+
+/// Splits a template word into text and placeholders, `None` when there is no placeholder at all
+///
+/// Braces that don't hold a placeholder, like `{#}` or `{x}`, are kept as text.
+pub fn parse_word(word: &str) -> Result<Option<Vec<Part>>, String> {
+    let mut parts = vec![];
+    let mut has_field = false;
+    let mut rest = word;
+    while let Some(open) = rest.find('{') {
+        let Some(len) = rest[open..].find('}') else {
+            break;
+        };
+        let close = open + len;
+        match Field::parse(&rest[open + 1..close])? {
+            Some(field) => {
+                if open > 0 {
+                    parts.push(Part::Text(rest[..open].to_owned()));
+                }
+                parts.push(Part::Field(field));
+                has_field = true;
+            }
+            None => parts.push(Part::Text(rest[..=close].to_owned())),
+        }
+        rest = &rest[close + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(Part::Text(rest.to_owned()));
+    }
+    Ok(has_field.then_some(parts))
+}
+
+/// Renders the parts of a word, every input the fields use must be there
+pub fn render(parts: &[Part], inputs: &[String]) -> String {
+    render_with(parts, inputs, |value| value)
+}
+
+/// Renders the parts of a word with the value of every field quoted for a shell
+pub fn render_quoted(parts: &[Part], inputs: &[String], shell: Shell) -> String {
+    render_with(parts, inputs, |value| shell.quote(&value))
+}
+
+fn render_with(parts: &[Part], inputs: &[String], fix: impl Fn(String) -> String) -> String {
+    parts
+        .iter()
+        .map(|part| match part {
+            Part::Text(text) => text.clone(),
+            Part::Field(field) if field.all => {
+                let values: Vec<String> = inputs.iter().map(|v| fix(field.render(v))).collect();
+                values.join(" ")
+            }
+            Part::Field(field) => fix(field.render(&inputs[field.idx])),
+        })
+        .collect()
+}
+
+/// Whether the parts have a `{}` placeholder, which takes every input
+pub fn uses_all(parts: &[Part]) -> bool {
+    parts
+        .iter()
+        .any(|part| matches!(part, Part::Field(field) if field.all))
+}
+
+/// Highest input index used by the parts
+pub fn max_idx(parts: &[Part]) -> usize {
+    parts
+        .iter()
+        .filter_map(|part| match part {
+            Part::Field(field) => Some(field.idx),
+            Part::Text(_) => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_word, render, render_quoted};
+    use crate::output::Shell;
+
+    fn expand(word: &str, inputs: &[&str]) -> String {
+        let inputs: Vec<String> = inputs.iter().map(|s| s.to_string()).collect();
+        render(&parse_word(word).unwrap().unwrap(), &inputs)
+    }
+
+    #[test]
+    fn placeholders_work() {
+        assert_eq!(expand("{0}-{1|lower}.out", &["a", "BC"]), "a-bc.out");
+        assert_eq!(expand("--part={0+1}", &["41"]), "--part=42");
+        assert_eq!(expand("{0*3}/{0%4}", &["7"]), "21/3");
+        assert_eq!(expand("{0-1}", &["x"]), "x");
+        assert_eq!(
+            expand("{0|dirname}/{0|basename|noext}.png", &["in/pic.jpg"]),
+            "in/pic.png"
+        );
+        assert_eq!(expand("{0|noext}", &[".bashrc"]), ".bashrc");
+        assert_eq!(expand("{}{0|upper}", &["a"]), "aA");
+        assert_eq!(
+            expand("{.}:{/}:{//}:{/.}", &["in/pic.jpg"]),
+            "in/pic:pic.jpg:in:pic"
+        );
+        assert_eq!(expand("{/}", &["a/b", "c/d"]), "b d");
+        assert_eq!(parse_word("{#}"), Ok(None));
+        assert_eq!(parse_word("{x}"), Ok(None));
+        let inputs = ["it's".to_owned(), "b".to_owned()];
+        assert_eq!(
+            render_quoted(
+                &parse_word("cat {0}>{1}").unwrap().unwrap(),
+                &inputs,
+                Shell::Sh
+            ),
+            "cat 'it'\\''s'>b"
+        );
+        assert!(parse_word("{0|nope}").is_err());
+        assert!(parse_word("{0/0}").is_err());
+        assert!(parse_word("{0^2}").is_err());
+    }
+}
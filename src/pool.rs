@@ -0,0 +1,2319 @@
+use crate::agent::{AgentConn, RemoteJob};
+use crate::alloc::Allocation;
+use crate::args::{fill_words, wrap_words, ArgBuilder, ArgBuilderMaker, FILE_PLACEHOLDER};
+use crate::backoff::Backoff;
+use crate::control::Board;
+use crate::output::{command_line, shell_quote, stdin_path, tee_path, up_to_date};
+use crate::remaining::Remaining;
+use crate::sandbox::Sandbox;
+use crate::schedule::{self, Schedule};
+use crate::simulate::{Script, SimulatedJob};
+use crate::{
+    bg, changes, chat, heartbeat, history, joblog, notify, power, printer, snapshot, status, stop,
+    trace, tune, usage, wakeup, webhook,
+};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::marker::PhantomData;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{env, fmt};
+use std::{process, thread};
+use tracing::{debug, info, info_span, trace, warn};
+
+/// Knobs controlling how a pool runs its jobs
+pub struct PoolOptions {
+    pub max_parallelism: usize,
+    /// Picks how many of the max_parallelism slots are used with --auto-tune
+    pub tuner: Option<tune::Tuner>,
+    /// Prints the stdout and stderr of local jobs instead of letting them inherit pll's
+    pub printer: Option<Arc<printer::Printer>>,
+    pub webhook: Option<webhook::Webhook>,
+    /// Chat service told about failed jobs and the run summary
+    pub on_fail_notify: Option<chat::Notifier>,
+    pub notify: bool,
+    pub no_run_if_empty: bool,
+    /// Environment variable receiving the slot index of each local process
+    pub process_slot_var: Option<String>,
+    /// Exit instead of running arguments too long for the size limit on their own
+    pub exit_on_oversize: bool,
+    /// Connect the stdin of local processes to /dev/tty
+    pub open_tty: bool,
+    /// Print the commands instead of running them
+    pub dry_run: bool,
+    /// Sequence number of the first job
+    pub seq_start: usize,
+    /// Scripted outcomes used instead of running anything
+    pub simulate: Option<Script>,
+    /// Pids of the local jobs currently running, shared with the signal watcher, only reaped with the lock held
+    pub running: Arc<Mutex<HashSet<u32>>>,
+    /// Kill local jobs whose processes together use more resident memory than this many bytes
+    pub kill_if_rss: Option<u64>,
+    /// Kill local jobs once they printed more than this many bytes to stdout
+    pub kill_if_output: Option<u64>,
+    /// Wait for every process a local job started, daemons included, before counting it as done
+    pub reap_orphans: bool,
+    /// Read the whole input before starting any job, so the job count is known up front
+    pub count_first: bool,
+    /// State kept up to date when running detached
+    pub background: Option<bg::Run>,
+    /// When not empty jobs are sent to these agents instead of being spawned locally
+    pub agents: Vec<AgentConn>,
+    /// Patterns of files brought back from the agents after each job
+    pub return_rsync: Vec<String>,
+    /// Shell command fed the output of every local job, in input order, once they all finished
+    pub reduce: Option<String>,
+    /// Stop at the first job that succeeds, killing the others and printing only its output
+    pub any: bool,
+    /// What happens once a job fails
+    pub halt: status::Halt,
+    /// Receives every line printed by local jobs, instead of it going to stdout
+    pub then: Option<SyncSender<String>>,
+    /// Shell command templates each getting its own job for every argument list, with their output tagged
+    pub each: Vec<String>,
+    /// Commands argument lists are handed to in turn with --round-robin, with their output tagged
+    pub alternatives: Vec<Vec<String>>,
+    /// Template of the file each local job also writes its output to, on top of printing it tagged
+    pub tee: Option<String>,
+    /// 1-based whitespace separated field of the first argument of a job used as its group key
+    pub group_by: Option<usize>,
+    /// Max number of jobs of the same group running at once
+    pub group_limit: usize,
+    /// Pauses dispatch after consecutive failures
+    pub backoff: Option<Backoff>,
+    /// Local jobs running for longer are killed
+    pub timeout: Option<Duration>,
+    /// Sent to local jobs killed because of --timeout, --halt now or --any
+    pub stop_signal: libc::c_int,
+    /// How long local jobs get to exit after `stop_signal` before being sent SIGKILL
+    pub kill_grace: Duration,
+    /// Times a failed job is run again
+    pub retries: usize,
+    /// How long a failed job waits before it is run again
+    pub retry_delay: Duration,
+    /// Least time between two jobs being started
+    pub delay: Option<Duration>,
+    /// Shell command that must succeed before each job is started
+    pub limit: Option<String>,
+    /// Lowers the parallelism or holds jobs back with --power-aware
+    pub power: Option<power::Power>,
+    /// Jobs table shared with the control socket
+    pub control: Option<Arc<Board>>,
+    /// Commands run one after the other once the job's program succeeds, split in words
+    pub and_then: Vec<Vec<String>>,
+    /// Command each job runs through, split in words
+    pub wrap: Option<Vec<String>>,
+    /// Shell command templates run in the slot of each local job right before and after its commands
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Shell command run once every job finished
+    pub teardown: Option<String>,
+    /// Written to the stdin of every local process
+    pub stdin_broadcast: Option<Arc<Vec<u8>>>,
+    /// Template of the file connected to the stdin of each local job's program
+    pub stdin_from: Option<String>,
+    /// Counts printed periodically with --heartbeat
+    pub heartbeat: Option<Arc<Mutex<heartbeat::Counts>>>,
+    pub status_file: Option<snapshot::StatusFile>,
+    /// Start and end of every job, for --trace-out
+    pub trace: Option<trace::Trace>,
+    /// Every finished job, also telling which ones to skip with --resume
+    pub joblog: Option<joblog::Joblog>,
+    /// Span of every job and of the run, for --otlp-endpoint
+    #[cfg(feature = "otel")]
+    pub otel: Option<crate::otel::Exporter>,
+    /// Durations of past jobs, updated with the ones of this run, with --runtime-cache
+    pub history: Option<history::History>,
+    /// Records jobs succeeded for in previous runs, which are skipped
+    pub changed_only: Option<changes::Changes>,
+    /// Template of the file made from a record, which is skipped when that file is newer than the record's
+    pub skip_if_newer: Option<String>,
+    /// Where the input left unused is written when pll is stopped by a signal
+    pub remaining: Option<Remaining>,
+    /// Order jobs are started in, anything but FIFO holds them until the input ends like --count-first
+    pub schedule: Schedule,
+    /// File mode creation mask of local processes
+    pub umask: Option<libc::mode_t>,
+    /// User and group local processes switch to before running
+    pub uid: Option<libc::uid_t>,
+    pub gid: Option<libc::gid_t>,
+    pub sandbox: Option<Arc<Sandbox>>,
+    /// Values reserved for each slot with --alloc-ports and --alloc
+    pub allocations: Vec<Allocation>,
+    /// Keep how every job ended for `ProcPool::wait_all` to return
+    pub keep_results: bool,
+}
+
+impl PoolOptions {
+    /// Runs up to `max_parallelism` jobs at once with nothing else on, and nothing run without input
+    pub fn new(max_parallelism: usize) -> PoolOptions {
+        PoolOptions {
+            max_parallelism,
+            tuner: None,
+            printer: None,
+            webhook: None,
+            on_fail_notify: None,
+            notify: false,
+            no_run_if_empty: true,
+            process_slot_var: None,
+            exit_on_oversize: false,
+            open_tty: false,
+            dry_run: false,
+            seq_start: 0,
+            simulate: None,
+            running: Arc::default(),
+            kill_if_rss: None,
+            kill_if_output: None,
+            reap_orphans: false,
+            count_first: false,
+            background: None,
+            agents: vec![],
+            return_rsync: vec![],
+            reduce: None,
+            any: false,
+            halt: status::Halt::Never,
+            then: None,
+            each: vec![],
+            alternatives: vec![],
+            tee: None,
+            group_by: None,
+            group_limit: 1,
+            backoff: None,
+            timeout: None,
+            stop_signal: libc::SIGTERM,
+            kill_grace: Duration::from_secs(10),
+            retries: 0,
+            retry_delay: Duration::ZERO,
+            delay: None,
+            limit: None,
+            power: None,
+            control: None,
+            and_then: vec![],
+            wrap: None,
+            before: None,
+            after: None,
+            teardown: None,
+            stdin_broadcast: None,
+            stdin_from: None,
+            heartbeat: None,
+            status_file: None,
+            trace: None,
+            joblog: None,
+            #[cfg(feature = "otel")]
+            otel: None,
+            history: None,
+            changed_only: None,
+            skip_if_newer: None,
+            remaining: None,
+            schedule: Schedule::Fifo,
+            umask: None,
+            uid: None,
+            gid: None,
+            sandbox: None,
+            allocations: vec![],
+            keep_results: false,
+        }
+    }
+}
+
+/// How a job ended, returned by `ProcPool::wait_all` with `PoolOptions::keep_results`
+///
+/// A job run again with --retries only has the result of its last attempt.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JobResult {
+    pub seq: usize,
+    /// Program and arguments the job ran
+    pub command: Vec<String>,
+    /// Exit code of the job, 128 plus the signal number when it was killed by one
+    pub exit_code: i32,
+    pub outcome: status::Outcome,
+    /// Set when the pool killed the job, with --timeout for instance
+    pub killed: Option<status::Kill>,
+    /// Why the job couldn't be started, with `Outcome::SpawnFailed`
+    pub error: Option<String>,
+}
+
+/// What stopped the pool, jobs failing aren't errors and show up in their results instead
+#[derive(Debug)]
+pub enum Error {
+    /// An argument doesn't fit the size limit on its own, with `exit_on_oversize`
+    Oversized(String),
+    /// The --reduce command couldn't be run
+    Reducer(io::Error),
+    /// The --reduce command failed
+    ReducerFailed(process::ExitStatus),
+    /// The --teardown command failed with this exit code, it was reported already
+    Teardown(i32),
+    /// pll was stopped by this signal, the input left was written to the --remaining file already
+    Stopped(libc::c_int),
+}
+
+impl Error {
+    /// Exit code pll ends with because of the error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Oversized(_) | Error::Reducer(_) => 1,
+            Error::ReducerFailed(status) => status::exit_code(*status),
+            Error::Teardown(code) => *code,
+            Error::Stopped(signal) => 128 + signal,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Oversized(arg) => write!(f, "argument too long for --max-chars: {}", arg),
+            Error::Reducer(e) => write!(f, "unable to run reducer: {}", e),
+            Error::ReducerFailed(status) => write!(f, "reducer failed with {}", status),
+            Error::Teardown(code) => write!(f, "--teardown failed with exit code {}", code),
+            Error::Stopped(signal) => write!(f, "stopped by signal {}", signal),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// An argument list ready to run, along with what goes with it
+#[derive(Clone, Default)]
+pub(crate) struct Batch {
+    arg_list: Vec<String>,
+    /// Index of the arguments with `{#}` in the template, filled in once the job gets its sequence number
+    numbered: Vec<usize>,
+    /// Group key with --group-by
+    key: Option<String>,
+    /// Argv of the --and-then commands
+    steps: Vec<Vec<String>>,
+    /// Input records the argument list was built from, given to the --before and --after hooks
+    inputs: Vec<String>,
+    /// Times it ran and failed before, with --retries
+    attempt: usize,
+    /// Sequence number of the job once it ran, which it keeps when it runs again with --retries
+    seq: Option<usize>,
+    /// Chunk of the input written to the stdin of the job, with --pipe
+    block: Option<Arc<Vec<u8>>>,
+}
+
+/// What the program of a local job reads, when it isn't the same for every job
+enum Stdin {
+    /// Path of the file connected to it, with --stdin-from
+    File(String),
+    /// Written to it, with --pipe
+    Block(Arc<Vec<u8>>),
+}
+
+/// A failed job waiting to run again with --retries
+struct Retry {
+    at: Instant,
+    batch: Batch,
+    tag: Option<String>,
+}
+
+pub struct ProcPool<T: ArgBuilder, U: ArgBuilderMaker<T>> {
+    program: String,
+    proc_builder: T,
+    proc_builder_fn: U,
+    procs: Vec<Job>,
+    options: PoolOptions,
+    spawned: usize,
+    failures: status::Failures,
+    /// Whether any argument was pushed at all
+    had_input: bool,
+    usage: usage::Summary,
+    /// Input records dropped by --skip-regex
+    skipped: usize,
+    /// Jobs skipped since the --joblog shows they succeeded before, they still take their sequence number
+    resumed: usize,
+    rss_checked_at: Instant,
+    /// Commands waiting for the input to end before being started, with --count-first
+    held: Option<VecDeque<Batch>>,
+    /// Group key of the argument list being built, with --group-by
+    key: Option<String>,
+    /// Argument lists waiting for a job of their group to finish, at most max_parallelism of them
+    deferred: VecDeque<Batch>,
+    /// Failed jobs to run again, requested through the control socket
+    retries: VecDeque<Batch>,
+    /// Failed jobs to run again with --retries, in the order they are due
+    requeued: VecDeque<Retry>,
+    /// Jobs that failed and were run again with --retries
+    retried: usize,
+    /// When the last job was started, for --delay
+    last_started: Option<Instant>,
+    /// Output of the finished jobs by sequence number, kept for --reduce
+    outputs: BTreeMap<usize, Vec<u8>>,
+    /// Set once the whole input was read
+    input_done: bool,
+    /// Argument lists handed to --round-robin alternatives so far
+    alternated: usize,
+    /// Sequence number of the job that won the --any race
+    won: Option<usize>,
+    /// Set once a job failed with --halt soon or now
+    halting: bool,
+    /// Input records of the jobs that ended without succeeding once pll was stopping, by sequence number
+    interrupted: Vec<(usize, Vec<String>)>,
+    /// How the jobs ended so far, with `keep_results`
+    results: Vec<JobResult>,
+}
+
+/// Sets up a `ProcPool`, with the options of `PoolOptions::new` unless told otherwise
+pub struct PoolBuilder<T: ArgBuilder, U: ArgBuilderMaker<T>> {
+    program: String,
+    proc_builder_fn: U,
+    options: PoolOptions,
+    builder: PhantomData<T>,
+}
+
+impl<T: ArgBuilder, U: ArgBuilderMaker<T>> PoolBuilder<T, U> {
+    pub fn max_parallelism(mut self, max_parallelism: usize) -> Self {
+        self.options.max_parallelism = max_parallelism;
+        self
+    }
+
+    /// Kills the jobs running for longer
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    /// Runs a failed job again up to `retries` times, waiting `delay` before each attempt
+    pub fn retries(mut self, retries: usize, delay: Duration) -> Self {
+        self.options.retries = retries;
+        self.options.retry_delay = delay;
+        self
+    }
+
+    /// Waits at least `delay` between the start of two jobs
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.options.delay = Some(delay);
+        self
+    }
+
+    pub fn halt(mut self, halt: status::Halt) -> Self {
+        self.options.halt = halt;
+        self
+    }
+
+    /// Gives the rest of the options, which the command line sets
+    pub fn options(mut self, f: impl FnOnce(&mut PoolOptions)) -> Self {
+        f(&mut self.options);
+        self
+    }
+
+    /// Makes the pool, notified of its jobs exiting from then on
+    ///
+    /// Best called before other threads are started, SIGCHLD can't be waited for when another thread takes it, jobs
+    /// are only looked at every second then.
+    pub fn build(self) -> io::Result<ProcPool<T, U>> {
+        if self.options.max_parallelism == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "max_parallelism must be at least 1",
+            ));
+        }
+        wakeup::watch_children()?;
+        Ok(ProcPool::new(
+            self.program,
+            self.proc_builder_fn,
+            PoolOptions {
+                keep_results: true,
+                ..self.options
+            },
+        ))
+    }
+}
+
+enum Proc {
+    Local(process::Child),
+    /// Reaped right away as a job that failed to start, with why
+    SpawnFailed(String),
+    Remote(RemoteJob),
+    Simulated(SimulatedJob),
+    /// Exited, with processes it left behind still running under --reap-orphans
+    Lingering,
+}
+
+/// A running process along with the span tracking its lifetime
+struct Job {
+    proc: Proc,
+    span: tracing::Span,
+    seq: usize,
+    started_at: Instant,
+    /// Index in 0..max_parallelism not used by any other running job
+    slot: usize,
+    command: Vec<String>,
+    /// Input records the job was started for
+    inputs: Vec<String>,
+    /// Set once pll killed the job
+    killed: Option<status::Kill>,
+    /// When the job is sent SIGKILL, if it is still running after pll sent it the stop signal
+    kill_deadline: Option<Instant>,
+    /// Set by the thread reading the stdout of the job once it went over --kill-if-output
+    overflowed: Arc<AtomicBool>,
+    /// Group of the job with --group-by
+    key: Option<String>,
+    /// --and-then commands left to run, in the same slot
+    steps: VecDeque<Vec<String>>,
+    /// Thread draining the output of the job when it is captured
+    output: Option<thread::JoinHandle<Vec<u8>>>,
+    /// What the lines printed by the job are tagged with, kept for its --and-then commands
+    tag: Option<String>,
+    /// What the job was started from, kept to run it again with --retries
+    batch: Option<Batch>,
+    /// Program arguments waiting for the --before hook to succeed
+    pending: Option<Pending>,
+    /// --after hook command line, run once the job's commands are done
+    after: Option<String>,
+    /// How the job ended, kept while its --after hook runs
+    result: Option<(i32, status::Outcome, Option<usage::Usage>)>,
+    /// File holding the records of the job in place of `{f}`, removed once it finished
+    records: Option<PathBuf>,
+    /// Why the job or one of its steps couldn't be started
+    error: Option<String>,
+    /// Processes the job started, as last seen with --reap-orphans
+    descendants: Vec<u32>,
+    /// Process groups of the local processes of the job that exited, with --reap-orphans
+    ///
+    /// What was started in the background keeps the group of its parent, so it is found this way even when
+    /// the parent exited too quickly for its children to be sampled.
+    groups: Vec<u32>,
+}
+
+impl Job {
+    /// Sends `signal` to a local job and everything it started, it gets SIGKILL if still running after `grace`
+    fn stop(&mut self, cause: status::KillCause, signal: libc::c_int, grace: Duration) {
+        let Proc::Local(child) = &self.proc else {
+            return;
+        };
+        stop::kill_tree(child.id(), signal);
+        self.killed = Some(status::Kill { cause, signal });
+        self.kill_deadline = Some(Instant::now() + grace);
+    }
+}
+
+/// What's needed to start the program of a job after its --before hook
+struct Pending {
+    program: String,
+    arg_list: Vec<String>,
+    stdin: Option<Stdin>,
+    tee: Option<File>,
+}
+
+/// How long to wait before running the --limit probe again after it refused a job
+const LIMIT_RETRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the memory of running jobs is sampled with --kill-if-rss
+const RSS_SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// How often the processes started by the jobs are sampled with --reap-orphans, short ones are missed otherwise
+const DESCENDANTS_SAMPLE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Longest the pool waits without looking at its jobs, for what has no notification of its own, like --auto-tune,
+/// --power-aware or the status file
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_secs(1);
+
+impl<T: ArgBuilder, U: ArgBuilderMaker<T>> ProcPool<T, U> {
+    /// Pool running `program` with the argument lists `proc_builder_fn` makes, one job at a time until told
+    /// otherwise
+    pub fn builder(program: String, proc_builder_fn: U) -> PoolBuilder<T, U> {
+        PoolBuilder {
+            program,
+            proc_builder_fn,
+            options: PoolOptions::new(1),
+            builder: PhantomData,
+        }
+    }
+
+    pub fn new(program: String, proc_builder_fn: U, options: PoolOptions) -> ProcPool<T, U> {
+        ProcPool {
+            program,
+            proc_builder: proc_builder_fn.make(),
+            proc_builder_fn,
+            procs: vec![],
+            spawned: 0,
+            failures: status::Failures::default(),
+            had_input: false,
+            usage: usage::Summary::default(),
+            skipped: 0,
+            resumed: 0,
+            rss_checked_at: Instant::now(),
+            held: (options.count_first || options.schedule != Schedule::Fifo).then(VecDeque::new),
+            key: None,
+            deferred: VecDeque::new(),
+            retries: VecDeque::new(),
+            requeued: VecDeque::new(),
+            retried: 0,
+            last_started: None,
+            outputs: BTreeMap::new(),
+            input_done: false,
+            alternated: 0,
+            won: None,
+            halting: false,
+            interrupted: vec![],
+            results: vec![],
+            options,
+        }
+    }
+
+    /// Writes every input record that wasn't dealt with to the --remaining file once pll was stopped, failing with
+    /// `Error::Stopped` then so the caller can exit
+    ///
+    /// Those are the records of the jobs that were interrupted, of the ones not started yet and `rest`, the input
+    /// that wasn't read, in that order. Does nothing when pll isn't being stopped.
+    pub fn halt(&mut self, rest: impl Iterator<Item = String>) -> Result<(), Error> {
+        let Some(signal) = stop::stopping() else {
+            return Ok(());
+        };
+        let Some(mut remaining) = self.options.remaining.take() else {
+            self.write_trace();
+            return Err(Error::Stopped(signal));
+        };
+        let running = self.procs.iter().map(|job| (job.seq, job.inputs.clone()));
+        let mut interrupted: Vec<(usize, Vec<String>)> =
+            self.interrupted.drain(..).chain(running).collect();
+        interrupted.sort_by_key(|&(seq, _)| seq);
+        // --each jobs of the same argument list follow each other
+        interrupted.dedup_by(|a, b| a.1 == b.1);
+        let waiting = self
+            .deferred
+            .iter()
+            .chain(self.requeued.iter().map(|retry| &retry.batch))
+            .chain(self.held.iter().flatten())
+            .map(|batch| batch.inputs.clone());
+        let mut records = interrupted
+            .into_iter()
+            .map(|(_, inputs)| inputs)
+            .chain(waiting)
+            .chain(std::iter::once(self.proc_builder.inputs()))
+            .flatten()
+            .chain(rest);
+        let written = records
+            .try_for_each(|record| remaining.push(&record))
+            .and_then(|()| {
+                let path = remaining.path().display().to_string();
+                remaining.finish().map(|count| (path, count))
+            });
+        match written {
+            Ok((path, count)) => eprintln!("{} input records left, written to {}", count, path),
+            Err(e) => eprintln!("failed to write the remaining input: {}", e),
+        }
+        self.write_trace();
+        Err(Error::Stopped(signal))
+    }
+
+    /// Writes the --trace-out file with the jobs finished so far
+    fn write_trace(&mut self) {
+        if let Some(trace) = &mut self.options.trace {
+            if let Err(e) = trace.finish() {
+                eprintln!("failed to write {}: {}", trace.path().display(), e);
+            }
+        }
+    }
+
+    /// Whether a job won the --any race
+    pub fn won(&self) -> bool {
+        self.won.is_some()
+    }
+
+    /// Whether the run is over because of --any or --halt, nothing is started anymore then
+    pub fn halted(&self) -> bool {
+        self.won.is_some() || self.halting
+    }
+
+    /// Number of jobs that didn't succeed so far
+    pub fn failed(&self) -> usize {
+        self.failures.failed
+    }
+
+    /// How the jobs that didn't succeed so far ended
+    pub fn failures(&self) -> &status::Failures {
+        &self.failures
+    }
+
+    /// Counts an input record that was dropped instead of pushed
+    pub fn skip_arg(&mut self) {
+        self.skipped += 1;
+    }
+
+    /// Adds an input record to the argument list being built, starting a job once it is complete
+    ///
+    /// Waits for a job to finish when as many as allowed are running already.
+    pub fn push_arg(&mut self, arg: &str) -> Result<(), Error> {
+        self.had_input = true;
+        if let Some(output) = &self.options.skip_if_newer {
+            if up_to_date(output, arg) {
+                debug!(record = arg, "output is up to date");
+                self.skipped += 1;
+                return Ok(());
+            }
+        }
+        if let Some(changes) = &mut self.options.changed_only {
+            if changes.unchanged(arg) {
+                debug!(record = arg, "unchanged since it last succeeded");
+                self.skipped += 1;
+                return Ok(());
+            }
+        }
+        if !self.proc_builder.fits(arg) {
+            self.flush();
+            if !self.proc_builder.fits(arg) && self.options.exit_on_oversize {
+                self.wait_until_len(0);
+                if let Some(teardown) = &self.options.teardown {
+                    run_once("--teardown", teardown);
+                }
+                return Err(Error::Oversized(arg.to_owned()));
+            }
+        }
+        if let (Some(col), None) = (self.options.group_by, &self.key) {
+            let key = arg.split_whitespace().nth(col - 1).unwrap_or_default();
+            self.key = Some(key.to_owned());
+        }
+        let finalized = self.proc_builder.push_arg(arg);
+        if finalized {
+            self.wait_for_room();
+            self.spawn();
+        }
+        Ok(())
+    }
+
+    /// Runs `records` as a job of their own, without the records pushed before
+    pub fn push_job<S: AsRef<str>>(
+        &mut self,
+        records: impl IntoIterator<Item = S>,
+    ) -> Result<(), Error> {
+        self.flush();
+        for record in records {
+            self.push_arg(record.as_ref())?;
+        }
+        self.flush();
+        Ok(())
+    }
+
+    /// Spawns the arguments collected so far without waiting for the builder to fill up
+    pub fn flush(&mut self) {
+        if self.proc_builder.viable() {
+            self.wait_for_room();
+            self.spawn();
+        }
+    }
+
+    /// Number of jobs allowed to run at the same time right now
+    fn parallelism(&self) -> usize {
+        let parallelism = self
+            .options
+            .tuner
+            .as_ref()
+            .map_or(self.options.max_parallelism, tune::Tuner::limit);
+        match &self.options.power {
+            Some(power) => power.limit(parallelism),
+            None => parallelism,
+        }
+    }
+
+    fn power_paused(&self) -> bool {
+        self.options
+            .power
+            .as_ref()
+            .is_some_and(power::Power::paused)
+    }
+
+    fn wait_for_room(&mut self) {
+        loop {
+            self.wait_until_len(self.parallelism().saturating_sub(1));
+            let paused = self.options.backoff.as_ref().and_then(Backoff::remaining);
+            match paused.or_else(|| self.delay_left()) {
+                // jobs keep being reaped meanwhile
+                Some(remaining) => wakeup::wait(remaining.min(HOUSEKEEPING_INTERVAL)),
+                None if !self.power_paused() && self.limit_allows() => break,
+                None => {
+                    let retry_at = Instant::now() + LIMIT_RETRY_INTERVAL;
+                    while let Some(left) = retry_at.checked_duration_since(Instant::now()) {
+                        wakeup::wait(left);
+                        self.wait_until_len(self.parallelism().saturating_sub(1));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs the --limit probe, a job can start when there is none or it succeeds
+    fn limit_allows(&self) -> bool {
+        let Some(limit) = &self.options.limit else {
+            return true;
+        };
+        match process::Command::new("sh")
+            .args(["-c", limit])
+            .stdin(process::Stdio::null())
+            .stdout(process::Stdio::null())
+            .status()
+        {
+            Ok(status) => {
+                debug!(%status, "ran limit probe");
+                status.success()
+            }
+            Err(e) => {
+                eprintln!("unable to run --limit probe: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Starts what is left of the input and waits for every job to finish, returning how they ended by sequence
+    /// number with `keep_results`
+    pub fn wait_all(&mut self) -> Result<Vec<JobResult>, Error> {
+        if self.had_input || !self.options.no_run_if_empty {
+            self.flush();
+        }
+        self.input_done = true;
+        if let Some(held) = &mut self.held {
+            let history = self.options.history.as_ref();
+            schedule::order(held, self.options.schedule, |batch| {
+                let command: Vec<String> = std::iter::once(self.program.clone())
+                    .chain(batch.arg_list.iter().cloned())
+                    .collect();
+                history?.predict(&command)
+            });
+            let jobs = held.len() * self.options.each.len().max(1);
+            info!(jobs, "counted jobs");
+            if let Some(run) = &mut self.options.background {
+                run.set_total(jobs);
+            }
+            // drained in place so the ETA still accounts for the jobs not started yet
+            while self.held.as_ref().is_some_and(|held| !held.is_empty())
+                && stop::stopping().is_none()
+                && !self.halted()
+            {
+                self.wait_for_room();
+                let batch = self.held.as_mut().and_then(VecDeque::pop_front).unwrap();
+                self.start(batch);
+            }
+            if stop::stopping().is_some() {
+                return Ok(self.take_results());
+            }
+            self.held = None;
+        }
+        if let Some(run) = &mut self.options.background {
+            run.input_done();
+        }
+        self.wait_until_len(0);
+        if stop::stopping().is_some() {
+            return Ok(self.take_results());
+        }
+        if let Some(status_file) = &self.options.status_file {
+            status_file.finish();
+        }
+        let teardown_code = self
+            .options
+            .teardown
+            .as_ref()
+            .map_or(0, |teardown| run_once("--teardown", teardown));
+        if let Some(run) = &mut self.options.background {
+            run.finish();
+        }
+        if let Some(history) = &self.options.history {
+            if let Err(e) = history.save() {
+                warn!("failed to save the runtime cache: {}", e);
+            }
+        }
+        if let Some(changes) = &self.options.changed_only {
+            if let Err(e) = changes.save() {
+                eprintln!("failed to save the --changed-only cache: {}", e);
+            }
+        }
+        self.write_trace();
+        let total = &self.usage.total;
+        info!(
+            jobs = self.spawned,
+            failed = self.failures.failed,
+            nonzero = self.failures.nonzero,
+            signaled = self.failures.signaled,
+            spawn_failed = self.failures.spawn_failed,
+            killed = self.failures.killed,
+            memkilled = self.failures.memkilled,
+            output_killed = self.failures.output_killed,
+            lost = self.failures.lost,
+            timed_out = self.failures.timed_out,
+            retried = self.retried,
+            skipped = self.skipped,
+            resumed = self.resumed,
+            max_rss_kb = total.max_rss_kb,
+            max_rss_seq = self.usage.max_rss_seq,
+            user_ms = total.user_ms,
+            system_ms = total.system_ms,
+            "run finished"
+        );
+        if let Some(reduce) = &self.options.reduce {
+            match run_reducer(reduce, &self.outputs) {
+                Ok(status) if status.success() => {}
+                Ok(status) => return Err(Error::ReducerFailed(status)),
+                Err(e) => return Err(Error::Reducer(e)),
+            }
+        }
+        if let Some(webhook) = self.options.webhook.take() {
+            webhook.finish(self.spawned, &self.failures, &self.usage);
+        }
+        #[cfg(feature = "otel")]
+        if let Some(exporter) = self.options.otel.take() {
+            exporter.finish(self.spawned, self.failures.failed);
+        }
+        if let Some(notifier) = self.options.on_fail_notify.take() {
+            notifier.finish(self.spawned, &self.failures);
+        }
+        if self.options.notify {
+            notify::run_finished(self.spawned, self.failures.failed);
+        }
+        if teardown_code != 0 {
+            return Err(Error::Teardown(teardown_code));
+        }
+        Ok(self.take_results())
+    }
+
+    fn take_results(&mut self) -> Vec<JobResult> {
+        let mut results = std::mem::take(&mut self.results);
+        results.sort_by_key(|result| result.seq);
+        results
+    }
+
+    /// Sends the job to the agent with the most free slots
+    fn spawn_remote(&mut self, seq: usize, command: Vec<String>) -> Proc {
+        let Some((idx, agent)) = self
+            .options
+            .agents
+            .iter_mut()
+            .enumerate()
+            .max_by_key(|(_, a)| a.slots as isize - a.in_flight as isize)
+        else {
+            return spawn_failed("no agents to send jobs to".into());
+        };
+        match agent.run(idx, seq, command) {
+            Ok(job) => {
+                debug!(agent = %agent.addr, "sent to agent");
+                Proc::Remote(job)
+            }
+            Err(e) => spawn_failed(format!("unable to send job to {}: {}", agent.addr, e)),
+        }
+    }
+
+    /// Sequence number of the next job, counted from --seq-start
+    fn next_seq(&self) -> usize {
+        self.options.seq_start + self.spawned
+    }
+
+    /// Starts a job reading `block` on stdin, with the arguments given up front and no input record
+    ///
+    /// Waits for a job to finish when as many as allowed are running already.
+    pub fn push_block(&mut self, block: Vec<u8>) -> Result<(), Error> {
+        self.had_input = true;
+        self.wait_for_room();
+        self.spawn_with(Some(Arc::new(block)));
+        Ok(())
+    }
+
+    fn spawn(&mut self) {
+        self.spawn_with(None);
+    }
+
+    fn spawn_with(&mut self, block: Option<Arc<Vec<u8>>>) {
+        let inputs = self.proc_builder.inputs();
+        let batch = Batch {
+            arg_list: self.proc_builder.arg_list(),
+            numbered: self.proc_builder.numbered(),
+            key: self.key.take(),
+            steps: self
+                .options
+                .and_then
+                .iter()
+                .map(|words| fill_words(words, &inputs))
+                .collect(),
+            inputs,
+            attempt: 0,
+            seq: None,
+            block,
+        };
+        self.proc_builder = self.proc_builder_fn.make();
+        match &mut self.held {
+            Some(held) => held.push_back(batch),
+            None => self.start(batch),
+        }
+        self.beat();
+    }
+
+    /// Time left until every job is done, from the past durations of the running and waiting ones
+    ///
+    /// Only known when all of them ran before. Waiting jobs are handed in order to the slot that frees up first.
+    fn predict_eta(&self) -> Option<Duration> {
+        let history = self.options.history.as_ref()?;
+        let mut slots = vec![Duration::ZERO; self.options.max_parallelism.max(self.procs.len())];
+        for (slot, job) in slots.iter_mut().zip(&self.procs) {
+            *slot = history
+                .predict(&job.command)?
+                .saturating_sub(job.started_at.elapsed());
+        }
+        for batch in self.deferred.iter().chain(self.held.iter().flatten()) {
+            let command: Vec<String> = std::iter::once(self.program.clone())
+                .chain(batch.arg_list.iter().cloned())
+                .collect();
+            *slots.iter_mut().min().unwrap() += history.predict(&command)?;
+        }
+        slots.into_iter().max()
+    }
+
+    /// Updates the --heartbeat counts and the --status-file snapshot
+    fn beat(&self) {
+        let counts = heartbeat::Counts {
+            running: self.procs.len(),
+            done: self.spawned - self.procs.len(),
+            failed: self.failures.failed,
+            queued: self.deferred.len()
+                + self.retries.len()
+                + self.held.as_ref().map_or(0, VecDeque::len),
+        };
+        if let Some(heartbeat) = &self.options.heartbeat {
+            *heartbeat.lock().unwrap() = counts;
+        }
+        if let Some(status_file) = &self.options.status_file {
+            let mut slots = vec![None; self.options.max_parallelism];
+            for job in &self.procs {
+                if job.slot >= slots.len() {
+                    slots.resize(job.slot + 1, None);
+                }
+                slots[job.slot] = Some(snapshot::Slot {
+                    seq: job.seq,
+                    command: job.command.clone(),
+                    pid: match &job.proc {
+                        Proc::Local(child) => Some(child.id()),
+                        _ => None,
+                    },
+                    elapsed_ms: job.started_at.elapsed().as_millis() as u64,
+                });
+            }
+            status_file.update(snapshot::Snapshot {
+                counts,
+                slots,
+                ..Default::default()
+            });
+        }
+    }
+
+    /// Starts the job for an argument list, or one job per --each template, waiting for room between them
+    ///
+    /// Argument lists whose group is already running --group-limit jobs are deferred instead.
+    fn start(&mut self, batch: Batch) {
+        if self.halted() {
+            return;
+        }
+        // kept for --remaining, nothing starts anymore
+        if self.group_full(batch.key.as_deref()) || stop::stopping().is_some() {
+            self.deferred.push_back(batch);
+            return;
+        }
+        if !self.options.alternatives.is_empty() {
+            let alternative =
+                &self.options.alternatives[self.alternated % self.options.alternatives.len()];
+            self.alternated += 1;
+            let words: Vec<String> = alternative
+                .iter()
+                .chain(&batch.arg_list)
+                .map(|w| shell_quote(w))
+                .collect();
+            let tag = format!("[{}]", alternative.join(" "));
+            let tee = self.tee_file(&batch.arg_list);
+            let job = Batch {
+                arg_list: vec!["-c".into(), words.join(" ")],
+                numbered: vec![],
+                ..batch
+            };
+            return self.start_one(job, Some(tag), tee);
+        }
+        if self.options.each.is_empty() {
+            let tag = self
+                .options
+                .tee
+                .as_ref()
+                .map(|_| format!("[{}]", self.next_seq()));
+            let tee = self.tee_file(&batch.arg_list);
+            return self.start_one(batch, tag, tee);
+        }
+        for idx in 0..self.options.each.len() {
+            if idx > 0 {
+                self.wait_for_room();
+            }
+            let line = command_line(&self.options.each[idx], &batch.arg_list);
+            let tag = format!("[{}]", self.options.each[idx]);
+            let tee = self.tee_file(&batch.arg_list);
+            let job = Batch {
+                arg_list: vec!["-c".into(), line],
+                numbered: vec![],
+                key: batch.key.clone(),
+                steps: vec![],
+                inputs: batch.inputs.clone(),
+                attempt: batch.attempt,
+                seq: None,
+                block: batch.block.clone(),
+            };
+            self.start_one(job, Some(tag), tee);
+        }
+    }
+
+    /// Creates the --tee file of the next job, it only gets its output printed when that fails
+    fn tee_file(&self, arg_list: &[String]) -> Option<File> {
+        if self.options.dry_run {
+            return None;
+        }
+        let path = tee_path(self.options.tee.as_ref()?, self.next_seq(), arg_list);
+        File::create(&path)
+            .map_err(|e| eprintln!("unable to create {}: {}", path, e))
+            .ok()
+    }
+
+    fn group_full(&self, key: Option<&str>) -> bool {
+        key.is_some_and(|key| {
+            let running = self
+                .procs
+                .iter()
+                .filter(|job| job.key.as_deref() == Some(key));
+            running.count() >= self.options.group_limit
+        })
+    }
+
+    /// Starts the retried and deferred argument lists whose group has room again, as long as the pool has room too
+    fn start_deferred(&mut self) {
+        if stop::stopping().is_some() || self.power_paused() || self.halted() {
+            return;
+        }
+        if let Some(board) = &self.options.control {
+            // a job of its own, the printer is done with the one that failed
+            let retries = board.take_retries().into_iter().map(|batch| Batch {
+                attempt: 0,
+                seq: None,
+                ..batch
+            });
+            self.retries.extend(retries);
+        }
+        while self.procs.len() < self.parallelism() {
+            let Some(batch) = self.retries.pop_front() else {
+                break;
+            };
+            self.start_one(batch, None, None);
+        }
+        while self.procs.len() < self.parallelism() && self.delay_left().is_none() {
+            if self.requeued.front().is_none_or(|r| r.at > Instant::now()) {
+                break;
+            }
+            let Retry { batch, tag, .. } = self.requeued.pop_front().unwrap();
+            self.start_one(batch, tag, None);
+        }
+        let mut idx = 0;
+        while idx < self.deferred.len()
+            && self.procs.len() < self.parallelism()
+            && self.delay_left().is_none()
+        {
+            if self.group_full(self.deferred[idx].key.as_deref()) {
+                idx += 1;
+                continue;
+            }
+            let batch = self.deferred.remove(idx).unwrap();
+            self.start(batch);
+        }
+    }
+
+    /// Starts the job for an argument list, with the sequence number of its first attempt when it runs again
+    fn start_one(&mut self, batch: Batch, tag: Option<String>, tee: Option<File>) {
+        let seq = batch.seq.unwrap_or_else(|| self.next_seq());
+        // attempts run again with --retries don't count as jobs of their own
+        let spawned = usize::from(batch.seq.is_none());
+        let kept = Batch {
+            seq: Some(seq),
+            ..batch.clone()
+        };
+        let Batch {
+            mut arg_list,
+            numbered,
+            key,
+            steps,
+            inputs,
+            block,
+            ..
+        } = batch;
+        for idx in numbered {
+            arg_list[idx] = self
+                .proc_builder_fn
+                .fill_numbered(&arg_list[idx], seq, &inputs);
+        }
+        let (program, arg_list) = match &self.options.wrap {
+            Some(words) => {
+                let mut wrapped = wrap_words(words, seq, &inputs);
+                let program = wrapped.remove(0);
+                wrapped.push(self.program.clone());
+                wrapped.extend(arg_list);
+                (program, wrapped)
+            }
+            None => (self.program.clone(), arg_list),
+        };
+        if let Some(joblog) = &self.options.joblog {
+            let command: Vec<String> = std::iter::once(program.clone())
+                .chain(arg_list.iter().cloned())
+                .collect();
+            if joblog.done(seq, &command) {
+                debug!(seq, "already succeeded");
+                if let Some(printer) = &self.options.printer {
+                    printer.finish(seq);
+                }
+                self.spawned += spawned;
+                self.resumed += 1;
+                return;
+            }
+        }
+        if self.options.dry_run {
+            let words: Vec<String> = std::iter::once(program.as_str())
+                .chain(arg_list.iter().map(String::as_str))
+                .map(shell_quote)
+                .collect();
+            println!("{}", words.join(" "));
+            self.spawned += spawned;
+            return;
+        }
+        let tag = tag.or_else(|| self.options.printer.as_ref()?.tag(seq, &inputs));
+        let span = info_span!("job", seq, program = %program, args = ?arg_list);
+        let command: Vec<String> = std::iter::once(program.clone())
+            .chain(arg_list.iter().cloned())
+            .collect();
+        let slot = (0..)
+            .find(|s| self.procs.iter().all(|job| job.slot != *s))
+            .unwrap();
+        let before = self
+            .options
+            .before
+            .as_ref()
+            .map(|t| command_line(t, &inputs));
+        let after = self
+            .options
+            .after
+            .as_ref()
+            .map(|t| command_line(t, &inputs));
+        let stdin = match block {
+            Some(block) => Some(Stdin::Block(block)),
+            None => self
+                .options
+                .stdin_from
+                .as_ref()
+                .map(|t| Stdin::File(stdin_path(t, &inputs))),
+        };
+        let local = self.options.simulate.is_none() && self.options.agents.is_empty();
+        let mut records = None;
+        let mut arg_list = arg_list;
+        if local && arg_list.iter().any(|a| a.contains(FILE_PLACEHOLDER)) {
+            match write_records(seq, &inputs) {
+                Ok(path) => {
+                    let placeholder = path.to_string_lossy();
+                    for arg in &mut arg_list {
+                        *arg = arg.replace(FILE_PLACEHOLDER, &placeholder);
+                    }
+                    records = Some(Ok(path));
+                }
+                Err(e) => records = Some(Err(e)),
+            }
+        }
+        let proc = span.in_scope(|| {
+            if let Some(script) = &self.options.simulate {
+                Proc::Simulated(script.start(&command))
+            } else if !self.options.agents.is_empty() {
+                self.spawn_remote(seq, command.clone())
+            } else if let Some(Err(e)) = &records {
+                spawn_failed(format!("unable to write the records of {}: {}", program, e))
+            } else if let Some(line) = &before {
+                spawn_hook(&self.options, line, slot, None)
+            } else {
+                spawn_local(&self.options, &program, &arg_list, slot, stdin.as_ref())
+            }
+        });
+        let mut proc = proc;
+        let overflowed = Arc::new(AtomicBool::new(false));
+        let (output, pending) = match &mut proc {
+            Proc::Local(_) if before.is_some() => {
+                let pending = Pending {
+                    program,
+                    arg_list,
+                    stdin,
+                    tee,
+                };
+                (None, Some(pending))
+            }
+            Proc::Local(child) => {
+                let output = capture(
+                    &self.options,
+                    child,
+                    seq,
+                    overflowed.clone(),
+                    tag.clone(),
+                    tee,
+                );
+                (output, None)
+            }
+            _ => (None, None),
+        };
+        if let Some(board) = &self.options.control {
+            let pid = match &proc {
+                Proc::Local(child) => Some(child.id()),
+                _ => None,
+            };
+            board.started(seq, &command, pid, kept.clone());
+        }
+        self.procs.push(Job {
+            proc,
+            output,
+            tag,
+            batch: (kept.attempt < self.options.retries).then_some(kept),
+            span,
+            seq,
+            started_at: Instant::now(),
+            slot,
+            command,
+            inputs,
+            killed: None,
+            kill_deadline: None,
+            overflowed,
+            key,
+            steps: steps.into(),
+            pending,
+            after,
+            result: None,
+            records: records.and_then(Result::ok),
+            error: None,
+            descendants: vec![],
+            groups: vec![],
+        });
+        if let Some(run) = &mut self.options.background {
+            run.job_spawned();
+        }
+        self.last_started = Some(Instant::now());
+        self.spawned += spawned;
+    }
+
+    /// Kills the local jobs whose process tree uses more memory than allowed
+    fn check_rss(&mut self) {
+        let Some(limit) = self.options.kill_if_rss else {
+            return;
+        };
+        if self.rss_checked_at.elapsed() < RSS_SAMPLE_INTERVAL {
+            return;
+        }
+        self.rss_checked_at = Instant::now();
+        let mut jobs: Vec<&mut Job> = self
+            .procs
+            .iter_mut()
+            .filter(|job| job.killed.is_none() && matches!(job.proc, Proc::Local(_)))
+            .collect();
+        let pids: Vec<u32> = jobs
+            .iter()
+            .map(|job| match &job.proc {
+                Proc::Local(child) => child.id(),
+                _ => unreachable!(),
+            })
+            .collect();
+        for (job, tree) in jobs.iter_mut().zip(usage::process_trees(&pids)) {
+            let rss: u64 = tree.iter().map(|&(_, rss)| rss).sum();
+            if rss <= limit {
+                continue;
+            }
+            eprintln!(
+                "killing job {}: using {} bytes, over --kill-if-rss",
+                job.seq, rss
+            );
+            for (pid, _) in tree {
+                // SAFETY: plain kill(2) call, the root is a child that wasn't reaped yet
+                unsafe { libc::kill(pid as libc::pid_t, libc::SIGKILL) };
+            }
+            job.killed = Some(status::Kill {
+                cause: status::KillCause::Memory,
+                signal: libc::SIGKILL,
+            });
+        }
+    }
+
+    /// Kills the local jobs running for longer than --timeout
+    fn check_timeouts(&mut self) {
+        let Some(timeout) = self.options.timeout else {
+            return;
+        };
+        for job in &mut self.procs {
+            // the --after hook runs once the job is over
+            if !matches!(job.proc, Proc::Local(_))
+                || job.killed.is_some()
+                || job.result.is_some()
+                || job.started_at.elapsed() < timeout
+            {
+                continue;
+            }
+            eprintln!("killing job {}: running for longer than --timeout", job.seq);
+            job.stop(
+                status::KillCause::Timeout,
+                self.options.stop_signal,
+                self.options.kill_grace,
+            );
+        }
+    }
+
+    /// Sends SIGKILL to the local jobs still running --kill-grace after they were sent the stop signal
+    fn check_kill_grace(&mut self) {
+        let now = Instant::now();
+        for job in &mut self.procs {
+            let Proc::Local(child) = &job.proc else {
+                continue;
+            };
+            if job.kill_deadline.is_none_or(|deadline| deadline > now) {
+                continue;
+            }
+            debug!(seq = job.seq, "still running after --kill-grace");
+            stop::kill_tree(child.id(), libc::SIGKILL);
+            job.kill_deadline = None;
+            if let Some(kill) = &mut job.killed {
+                kill.signal = libc::SIGKILL;
+            }
+        }
+    }
+
+    /// Time left before the next job can start with --delay
+    fn delay_left(&self) -> Option<Duration> {
+        let left = self
+            .options
+            .delay?
+            .checked_sub(self.last_started?.elapsed())?;
+        (!left.is_zero()).then_some(left)
+    }
+
+    /// Kills the local jobs still running, once one won the --any race or failed with --halt now
+    fn kill_running(&mut self, cause: status::KillCause) {
+        for job in &mut self.procs {
+            if job.killed.is_none() {
+                job.stop(cause, self.options.stop_signal, self.options.kill_grace);
+            }
+        }
+    }
+
+    /// Records what every local job started, which has to be known before the processes get orphaned
+    fn track_descendants(&mut self) {
+        if !self.options.reap_orphans {
+            return;
+        }
+        let roots: Vec<Vec<u32>> = self
+            .procs
+            .iter()
+            .map(|job| match &job.proc {
+                Proc::Local(child) => {
+                    let mut roots = job.descendants.clone();
+                    roots.push(child.id());
+                    roots
+                }
+                _ => vec![],
+            })
+            .collect();
+        let jobs: Vec<(&[u32], &[u32])> = self
+            .procs
+            .iter()
+            .zip(&roots)
+            .map(|(job, roots)| (&roots[..], &job.groups[..]))
+            .collect();
+        let trees = usage::live_trees(&jobs);
+        for (job, tree) in self.procs.iter_mut().zip(trees) {
+            if let Proc::Local(child) = &job.proc {
+                let leader = child.id();
+                job.descendants = tree.into_iter().filter(|&pid| pid != leader).collect();
+            }
+        }
+    }
+
+    fn wait_until_len(&mut self, len: usize) {
+        loop {
+            trace!(running = self.procs.len(), target = len, "waiting for jobs");
+            self.check_rss();
+            self.check_timeouts();
+            self.check_kill_grace();
+            self.track_descendants();
+            // before reaping, what just finished would make the slots look less busy than they were
+            if let Some(tuner) = &mut self.options.tuner {
+                tuner.tick(self.procs.len());
+            }
+            if let Some(power) = &mut self.options.power {
+                power.tick();
+            }
+            self.procs.retain_mut(|job| {
+                let _entered = job.span.enter();
+                let (exit_code, outcome, usage) = match &mut job.proc {
+                    Proc::Local(child) => match reap(&self.options.running, child) {
+                        Ok(None) => return true,
+                        Ok(Some((status, usage))) => {
+                            // reaped, its pid could be reused
+                            job.kill_deadline = None;
+                            if self.options.reap_orphans && !self.options.open_tty {
+                                // every local process leads a group of its own
+                                job.groups.push(child.id());
+                            }
+                            info!(
+                                %status,
+                                max_rss_kb = usage.max_rss_kb,
+                                user_ms = usage.user_ms,
+                                system_ms = usage.system_ms,
+                                read_bytes = usage.read_bytes,
+                                write_bytes = usage.write_bytes,
+                                "exited"
+                            );
+                            if let Some(result) = job.result.take() {
+                                if !status.success() {
+                                    eprintln!("--after hook of job {} failed: {}", job.seq, status);
+                                }
+                                result
+                            } else {
+                                let outcome = status::Outcome::of_status(status);
+                                let next = if let Some(pending) = job.pending.take() {
+                                    // the program only runs once its --before hook succeeded
+                                    (status.success() && job.killed.is_none()).then(|| {
+                                        let mut proc = spawn_local(
+                                            &self.options,
+                                            &pending.program,
+                                            &pending.arg_list,
+                                            job.slot,
+                                            pending.stdin.as_ref(),
+                                        );
+                                        if let Proc::Local(child) = &mut proc {
+                                            job.output = capture(
+                                                &self.options,
+                                                child,
+                                                job.seq,
+                                                job.overflowed.clone(),
+                                                job.tag.clone(),
+                                                pending.tee,
+                                            );
+                                        }
+                                        proc
+                                    })
+                                } else {
+                                    match job.steps.pop_front() {
+                                        Some(step) if status.success() && job.killed.is_none() => {
+                                            let mut proc = spawn_local(
+                                                &self.options,
+                                                &step[0],
+                                                &step[1..],
+                                                job.slot,
+                                                None,
+                                            );
+                                            if let (Some(_), Proc::Local(child)) =
+                                                (&self.options.printer, &mut proc)
+                                            {
+                                                // printed as the output of the same job, after the previous step's
+                                                if let Some(output) = job.output.take() {
+                                                    let _ = output.join();
+                                                }
+                                                job.output = capture(
+                                                    &self.options,
+                                                    child,
+                                                    job.seq,
+                                                    job.overflowed.clone(),
+                                                    job.tag.clone(),
+                                                    None,
+                                                );
+                                            }
+                                            Some(proc)
+                                        }
+                                        _ => None,
+                                    }
+                                };
+                                match next {
+                                    Some(Proc::Local(next)) => {
+                                        *child = next;
+                                        return true;
+                                    }
+                                    Some(proc) => {
+                                        if let Proc::SpawnFailed(e) = proc {
+                                            job.error = Some(e);
+                                        }
+                                        (127, status::Outcome::SpawnFailed, Some(usage))
+                                    }
+                                    None => (status::exit_code(status), outcome, Some(usage)),
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            eprintln!("proc exited with {}", e);
+                            if let Some(printer) = &self.options.printer {
+                                printer.finish(job.seq);
+                            }
+                            return false;
+                        }
+                    },
+                    Proc::Remote(remote) => match remote.try_wait() {
+                        None => return true,
+                        Some(exit_code) => {
+                            info!(exit_code, "exited");
+                            let agent = &mut self.options.agents[remote.agent];
+                            agent.in_flight -= 1;
+                            if !self.options.return_rsync.is_empty() {
+                                if let Err(e) = agent.fetch(&self.options.return_rsync) {
+                                    eprintln!("failed to fetch files from {}: {}", agent.addr, e);
+                                }
+                            }
+                            (exit_code, status::Outcome::of_code(exit_code), None)
+                        }
+                    },
+                    Proc::Simulated(simulated) => match simulated.try_wait() {
+                        None => return true,
+                        Some(exit_code) => {
+                            info!(exit_code, "exited");
+                            (exit_code, status::Outcome::of_code(exit_code), None)
+                        }
+                    },
+                    // what a shell reports for commands it can't run
+                    Proc::SpawnFailed(e) => {
+                        job.error = Some(e.clone());
+                        (127, status::Outcome::SpawnFailed, None)
+                    }
+                    Proc::Lingering => {
+                        for &pid in &job.descendants {
+                            // SAFETY: non-blocking waitpid(2) on a pid that was a descendant of the job, it only reaps
+                            // the orphans reparented to pll and fails for the others
+                            unsafe {
+                                libc::waitpid(pid as libc::pid_t, ptr::null_mut(), libc::WNOHANG)
+                            };
+                        }
+                        job.descendants =
+                            usage::live_trees(&[(&job.descendants, &job.groups)]).remove(0);
+                        if !job.descendants.is_empty() {
+                            return true;
+                        }
+                        debug!("left no process behind");
+                        job.result.take().expect("lingering jobs keep their result")
+                    }
+                };
+                if self.options.reap_orphans
+                    && !matches!(job.proc, Proc::Lingering)
+                    && !(job.descendants.is_empty() && job.groups.is_empty())
+                {
+                    debug!(
+                        processes = job.descendants.len(),
+                        "waiting for the processes left behind"
+                    );
+                    job.proc = Proc::Lingering;
+                    job.result = Some((exit_code, outcome, usage));
+                    return true;
+                }
+                if let Some(line) = job.after.take() {
+                    if let Proc::Local(hook) =
+                        spawn_hook(&self.options, &line, job.slot, Some(exit_code))
+                    {
+                        job.proc = Proc::Local(hook);
+                        job.result = Some((exit_code, outcome, usage));
+                        return true;
+                    }
+                }
+                if let Some(path) = job.records.take() {
+                    if let Err(e) = fs::remove_file(&path) {
+                        warn!("failed to remove {}: {}", path.display(), e);
+                    }
+                }
+                if job.overflowed.load(Ordering::Relaxed) {
+                    job.killed.get_or_insert(status::Kill {
+                        cause: status::KillCause::Output,
+                        signal: libc::SIGKILL,
+                    });
+                }
+                if let Some(board) = &self.options.control {
+                    if let Some(kill) = board.finished(job.seq, exit_code) {
+                        job.killed.get_or_insert(kill);
+                    }
+                }
+                let timed_out = job
+                    .killed
+                    .is_some_and(|k| k.cause == status::KillCause::Timeout);
+                let outcome = match outcome {
+                    status::Outcome::Success => outcome,
+                    _ if timed_out => status::Outcome::TimedOut,
+                    _ => outcome,
+                };
+                let success = outcome == status::Outcome::Success;
+                // a job pll killed on purpose is done with, whatever --retries says
+                let retry = !success
+                    && stop::stopping().is_none()
+                    && self.won.is_none()
+                    && !self.halting
+                    && !job.killed.is_some_and(|k| {
+                        matches!(
+                            k.cause,
+                            status::KillCause::Control
+                                | status::KillCause::Lost
+                                | status::KillCause::Halt
+                        )
+                    });
+                let requeued = match job.batch.take().filter(|_| retry) {
+                    Some(mut batch) => {
+                        batch.attempt += 1;
+                        eprintln!(
+                            "job {} failed, running it again ({} of {})",
+                            job.seq, batch.attempt, self.options.retries
+                        );
+                        self.retried += 1;
+                        self.requeued.push_back(Retry {
+                            at: Instant::now() + self.options.retry_delay,
+                            batch,
+                            tag: job.tag.clone(),
+                        });
+                        true
+                    }
+                    None => {
+                        self.failures.add(outcome, job.killed);
+                        if self.options.keep_results {
+                            self.results.push(JobResult {
+                                seq: job.seq,
+                                command: job.command.clone(),
+                                exit_code,
+                                outcome,
+                                killed: job.killed,
+                                error: job.error.clone(),
+                            });
+                        }
+                        false
+                    }
+                };
+                if let Some(kill) = job.killed {
+                    info!(cause = ?kill.cause, signal = kill.signal, "killed by pll");
+                }
+                if !success && stop::stopping().is_some() {
+                    self.interrupted.push((job.seq, job.inputs.clone()));
+                }
+                let halts = !success
+                    && !requeued
+                    && !self.halting
+                    && self.options.halt != status::Halt::Never
+                    && !job.killed.is_some_and(|k| {
+                        matches!(k.cause, status::KillCause::Lost | status::KillCause::Halt)
+                    });
+                if halts {
+                    self.halting = true;
+                    match self.options.halt {
+                        status::Halt::Now => {
+                            eprintln!("job {} failed, killing the running jobs", job.seq)
+                        }
+                        _ => eprintln!("job {} failed, waiting for the running jobs", job.seq),
+                    }
+                }
+                if let Some(pause) = self
+                    .options
+                    .backoff
+                    .as_mut()
+                    .and_then(|b| b.job_finished(success))
+                {
+                    eprintln!("too many failures in a row, pausing for {:?}", pause);
+                }
+                if let Some(output) = job.output.take() {
+                    let output = output.join().unwrap_or_default();
+                    if self.options.reduce.is_some() {
+                        self.outputs.insert(job.seq, output);
+                    } else if self.options.any && success && self.won.is_none() {
+                        info!("won the race");
+                        self.won = Some(job.seq);
+                        if let Err(e) = std::io::stdout().write_all(&output) {
+                            eprintln!("failed to write stdout: {}", e);
+                        }
+                    }
+                }
+                if let Some(printer) = &self.options.printer {
+                    if outcome == status::Outcome::TimedOut {
+                        printer.mark(job.seq, job.tag.as_deref(), "TIMEOUT");
+                    }
+                    // the output of the next attempt follows, under the same sequence number
+                    if !requeued {
+                        printer.finish(job.seq);
+                    }
+                }
+                if let Some(usage) = &usage {
+                    self.usage.add(job.seq, usage);
+                }
+                if let Some(changes) = &mut self.options.changed_only {
+                    changes.finished(&job.inputs, success);
+                }
+                if let Some(tuner) = &mut self.options.tuner {
+                    tuner.job_finished();
+                }
+                #[cfg(feature = "otel")]
+                if let Some(exporter) = &self.options.otel {
+                    let host = agent_addr(&self.options, &job.proc)
+                        .map_or_else(crate::store::hostname, str::to_owned);
+                    exporter.job_finished(
+                        job.seq,
+                        job.slot,
+                        &host,
+                        &job.command,
+                        job.started_at,
+                        exit_code,
+                        outcome,
+                    );
+                }
+                let host = agent_addr(&self.options, &job.proc)
+                    .unwrap_or(":")
+                    .to_owned();
+                if let Some(joblog) = &mut self.options.joblog {
+                    let logged = joblog.job_finished(
+                        job.seq,
+                        &host,
+                        job.started_at,
+                        exit_code,
+                        outcome,
+                        &job.command,
+                    );
+                    if let Err(e) = logged {
+                        eprintln!("failed to write {}: {}", joblog.path().display(), e);
+                    }
+                }
+                if let Some(trace) = &mut self.options.trace {
+                    trace.job_finished(
+                        job.seq,
+                        job.slot,
+                        &job.command,
+                        job.started_at,
+                        exit_code,
+                        outcome,
+                    );
+                }
+                if let (Some(history), true) = (&mut self.options.history, success) {
+                    history.record(&job.command, job.started_at.elapsed());
+                }
+                if let Some(run) = &mut self.options.background {
+                    run.job_finished(exit_code);
+                }
+                if let Some(webhook) = &self.options.webhook {
+                    webhook.job_finished(
+                        job.seq,
+                        &job.command,
+                        exit_code,
+                        outcome,
+                        usage.as_ref(),
+                        job.killed,
+                    );
+                }
+                if let Some(notifier) = &mut self.options.on_fail_notify {
+                    if outcome != status::Outcome::Success && !requeued {
+                        notifier.job_failed(job.seq, &job.command, exit_code, outcome);
+                    }
+                }
+                false
+            });
+            if self.won.is_some() {
+                self.kill_running(status::KillCause::Lost);
+            } else if self.halting && self.options.halt == status::Halt::Now {
+                self.kill_running(status::KillCause::Halt);
+            }
+            self.start_deferred();
+            self.beat();
+            // the prediction is only shown by `pll status`
+            if self.input_done && self.options.background.is_some() {
+                let eta = self.predict_eta().map(|eta| eta.as_secs());
+                if let Some(run) = &mut self.options.background {
+                    run.set_eta(eta);
+                }
+            }
+            // deferred argument lists count against the target too so they can't pile up
+            if self.procs.len() <= len && self.deferred.len() <= len && self.requeued.len() <= len
+                || stop::stopping().is_some()
+            {
+                break;
+            }
+            wakeup::wait(self.next_wakeup());
+        }
+    }
+
+    /// How long the pool can wait for a job to finish before it has to look at the jobs anyway
+    ///
+    /// Local jobs, remote ones and the control socket all wake the pool up, see [`wakeup`].
+    fn next_wakeup(&self) -> Duration {
+        let mut timeout = HOUSEKEEPING_INTERVAL;
+        if self.options.kill_if_rss.is_some() {
+            timeout =
+                timeout.min(RSS_SAMPLE_INTERVAL.saturating_sub(self.rss_checked_at.elapsed()));
+        }
+        if self.options.reap_orphans && !self.procs.is_empty() {
+            timeout = timeout.min(DESCENDANTS_SAMPLE_INTERVAL);
+        }
+        for job in &self.procs {
+            if let Proc::Simulated(sim) = &job.proc {
+                timeout = timeout.min(sim.remaining());
+            }
+            if let (Some(limit), Proc::Local(_)) = (self.options.timeout, &job.proc) {
+                timeout = timeout.min(limit.saturating_sub(job.started_at.elapsed()));
+            }
+            if let Some(deadline) = job.kill_deadline {
+                timeout = timeout.min(deadline.saturating_duration_since(Instant::now()));
+            }
+        }
+        if let Some(retry) = self.requeued.front() {
+            timeout = timeout.min(retry.at.saturating_duration_since(Instant::now()));
+        }
+        if let (Some(left), false) = (
+            self.delay_left(),
+            self.deferred.is_empty() && self.requeued.is_empty(),
+        ) {
+            timeout = timeout.min(left);
+        }
+        timeout
+    }
+}
+
+/// Address of the agent running a remote job
+fn agent_addr<'a>(options: &'a PoolOptions, proc: &Proc) -> Option<&'a str> {
+    match proc {
+        Proc::Remote(remote) => Some(&options.agents[remote.agent].addr),
+        _ => None,
+    }
+}
+
+/// Reaps a local job once it exited, collecting its resource usage
+///
+/// Done with the lock on the running pids held, and the pid taken out of them, so the threads killing jobs never
+/// signal a pid or group that was reused.
+fn reap(
+    running: &Mutex<HashSet<u32>>,
+    child: &process::Child,
+) -> io::Result<Option<(process::ExitStatus, usage::Usage)>> {
+    let mut running = running.lock().unwrap();
+    let reaped = usage::try_wait(child)?;
+    if reaped.is_some() {
+        running.remove(&child.id());
+    }
+    Ok(reaped)
+}
+
+/// Writes the records of a job to a file of their own, one per line, for `{f}`
+fn write_records(seq: usize, inputs: &[String]) -> std::io::Result<PathBuf> {
+    let path = env::temp_dir().join(format!("pll-{}-{}.txt", process::id(), seq));
+    let mut file = File::options().write(true).create_new(true).open(&path)?;
+    for input in inputs {
+        writeln!(file, "{}", input)?;
+    }
+    Ok(path)
+}
+
+/// Runs a --setup or --teardown shell command, returning 0 when it succeeded and its exit code otherwise
+pub fn run_once(flag: &str, command: &str) -> i32 {
+    match process::Command::new("sh").args(["-c", command]).status() {
+        Ok(status) if status.success() => 0,
+        Ok(status) => {
+            eprintln!("{} failed with {}", flag, status);
+            status::exit_code(status)
+        }
+        Err(e) => {
+            eprintln!("unable to run {}: {}", flag, e);
+            1
+        }
+    }
+}
+
+/// Runs the --reduce command with the output of the jobs concatenated on its stdin
+fn run_reducer(
+    command: &str,
+    outputs: &BTreeMap<usize, Vec<u8>>,
+) -> std::io::Result<process::ExitStatus> {
+    let mut child = process::Command::new("sh")
+        .args(["-c", command])
+        .stdin(process::Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    for output in outputs.values() {
+        if let Err(e) = stdin.write_all(output) {
+            // the reducer doesn't need to read everything
+            if e.kind() != std::io::ErrorKind::BrokenPipe {
+                return Err(e);
+            }
+            break;
+        }
+    }
+    drop(stdin);
+    child.wait()
+}
+
+/// Drains the stdout of a local job on a thread when it is captured, it could block on a full pipe otherwise
+///
+/// The thread returns the whole output with --reduce and --any, with --then, --each and --tee it hands every line over as soon
+/// as it is read.
+fn capture(
+    options: &PoolOptions,
+    child: &mut process::Child,
+    seq: usize,
+    overflowed: Arc<AtomicBool>,
+    tag: Option<String>,
+    mut tee: Option<File>,
+) -> Option<thread::JoinHandle<Vec<u8>>> {
+    if let Some(printer) = &options.printer {
+        let stderr = child.stderr.take().map(|stderr| {
+            let printer = printer.clone();
+            let tag = tag.clone();
+            thread::spawn(move || {
+                printer.copy(seq, printer::Stream::Stderr, stderr, tag.as_deref())
+            })
+        });
+        let stdout = child.stdout.take()?;
+        let printer = printer.clone();
+        return Some(thread::spawn(move || {
+            printer.copy(seq, printer::Stream::Stdout, stdout, tag.as_deref());
+            if let Some(stderr) = stderr {
+                let _ = stderr.join();
+            }
+            vec![]
+        }));
+    }
+    let limit = options.kill_if_output;
+    if options.reduce.is_none()
+        && !options.any
+        && options.then.is_none()
+        && tag.is_none()
+        && limit.is_none()
+    {
+        return None;
+    }
+    let mut stdout = OutputLimit {
+        stdout: child.stdout.take()?,
+        left: limit.unwrap_or(u64::MAX),
+        pid: child.id(),
+        seq,
+        running: options.running.clone(),
+        overflowed,
+    };
+    if options.reduce.is_some() || options.any {
+        return Some(thread::spawn(move || {
+            let mut buf = vec![];
+            if let Err(e) = stdout.read_to_end(&mut buf) {
+                eprintln!("failed to read stdout: {}", e);
+            }
+            buf
+        }));
+    }
+    let then = options.then.clone();
+    if then.is_none() && tag.is_none() {
+        // only piped to count the bytes
+        return Some(thread::spawn(move || {
+            if let Err(e) = std::io::copy(&mut stdout, &mut std::io::stdout()) {
+                eprintln!("failed to copy stdout: {}", e);
+            }
+            vec![]
+        }));
+    }
+    Some(thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    eprintln!("failed to read stdout: {}", e);
+                    break;
+                }
+            };
+            if let Some(file) = &mut tee {
+                if let Err(e) = writeln!(file, "{}", line) {
+                    eprintln!("failed to write --tee file: {}", e);
+                    tee = None;
+                }
+            }
+            match (&then, &tag) {
+                (Some(then), _) => {
+                    if then.send(line).is_err() {
+                        break;
+                    }
+                }
+                (None, Some(tag)) => println!("{} {}", tag, line),
+                (None, None) => unreachable!(),
+            }
+        }
+        vec![]
+    }))
+}
+
+/// Stdout of a local job, which is killed once it printed more than --kill-if-output
+struct OutputLimit {
+    stdout: process::ChildStdout,
+    /// Bytes the job may still print
+    left: u64,
+    pid: u32,
+    seq: usize,
+    running: Arc<Mutex<HashSet<u32>>>,
+    overflowed: Arc<AtomicBool>,
+}
+
+impl Read for OutputLimit {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.overflowed.load(Ordering::Relaxed) {
+            return Ok(0);
+        }
+        let read = self.stdout.read(buf)?;
+        if read as u64 <= self.left {
+            self.left -= read as u64;
+            return Ok(read);
+        }
+        eprintln!(
+            "killing job {}: printed too much, over --kill-if-output",
+            self.seq
+        );
+        self.overflowed.store(true, Ordering::Relaxed);
+        // the job is only reaped with this lock held, so its pid can't have been reused
+        if self.running.lock().unwrap().contains(&self.pid) {
+            stop::kill_tree(self.pid, libc::SIGKILL);
+        }
+        // what fits is still passed on, the rest is dropped
+        Ok(self.left as usize)
+    }
+}
+
+/// Spawns a local process, with `stdin` what it reads instead of the stdin every job gets
+fn spawn_local(
+    options: &PoolOptions,
+    program: &str,
+    arg_list: &[String],
+    slot: usize,
+    stdin: Option<&Stdin>,
+) -> Proc {
+    let capture = options.reduce.is_some()
+        || options.any
+        || options.kill_if_output.is_some()
+        || options.then.is_some()
+        || !options.each.is_empty()
+        || !options.alternatives.is_empty()
+        || options.tee.is_some();
+    let stdout_cfg = if options.printer.is_some() || capture {
+        process::Stdio::piped()
+    } else {
+        process::Stdio::inherit()
+    };
+    let mut command = match local_command(options, program, arg_list, slot) {
+        Ok(command) => command,
+        Err(proc) => return proc,
+    };
+    command.stdout(stdout_cfg);
+    if options.printer.is_some() {
+        command.stderr(process::Stdio::piped());
+    }
+    let payload = match stdin {
+        Some(Stdin::Block(block)) => Some(block),
+        _ => options.stdin_broadcast.as_ref(),
+    };
+    if payload.is_some() {
+        command.stdin(process::Stdio::piped());
+    }
+    if let Some(Stdin::File(path)) = stdin {
+        match File::open(path) {
+            Ok(file) => command.stdin(file),
+            Err(e) => return spawn_failed(format!("unable to open {}: {}", path, e)),
+        };
+    }
+    let mut proc = spawn(options, command, program);
+    if let (Some(payload), Proc::Local(child)) = (payload, &mut proc) {
+        let mut stdin = child.stdin.take().unwrap();
+        let payload = payload.clone();
+        // written from a thread since the process may not read it all, or only once it wrote its own output
+        thread::spawn(move || {
+            if let Err(e) = stdin.write_all(&payload) {
+                if e.kind() != std::io::ErrorKind::BrokenPipe {
+                    eprintln!("failed to write stdin: {}", e);
+                }
+            }
+        });
+    }
+    proc
+}
+
+/// Runs a --before or --after hook, which prints to stderr so its output never mixes with the job's
+///
+/// The --after hook gets the exit code of the job in $PLL_EXIT_CODE.
+fn spawn_hook(options: &PoolOptions, line: &str, slot: usize, exit_code: Option<i32>) -> Proc {
+    let mut command = match local_command(options, "sh", &["-c".into(), line.into()], slot) {
+        Ok(command) => command,
+        Err(proc) => return proc,
+    };
+    command.stdout(std::io::stderr());
+    if let Some(code) = exit_code {
+        command.env("PLL_EXIT_CODE", code.to_string());
+    }
+    spawn(options, command, "sh")
+}
+
+/// Command of a local process with everything that comes with the slot it runs in, except for its stdout
+///
+/// Fails with the failed job in its place when the terminal can't be opened for --open-tty.
+fn local_command(
+    options: &PoolOptions,
+    program: &str,
+    arg_list: &[String],
+    slot: usize,
+) -> Result<process::Command, Proc> {
+    let stdin_cfg = if options.open_tty {
+        let tty = File::open("/dev/tty")
+            .map_err(|e| spawn_failed(format!("unable to open /dev/tty: {}", e)))?;
+        process::Stdio::from(tty)
+    } else {
+        process::Stdio::null()
+    };
+    let mut command = process::Command::new(program);
+    if let Some(var) = &options.process_slot_var {
+        command.env(var, slot.to_string());
+    }
+    let mut arg_list = arg_list.to_vec();
+    for allocation in &options.allocations {
+        command.env(&allocation.var, &allocation.values[slot]);
+        arg_list = allocation.fill(slot, &arg_list);
+    }
+    if let Some(gid) = options.gid {
+        command.gid(gid);
+    }
+    if let Some(uid) = options.uid {
+        command.uid(uid);
+    }
+    if let Some(sandbox) = options.sandbox.clone() {
+        // SAFETY: entering the sandbox only makes syscalls, with everything it needs allocated beforehand
+        unsafe {
+            command.pre_exec(move || sandbox.enter());
+        }
+    }
+    // SAFETY: unblocking the signals only makes async-signal-safe calls
+    unsafe {
+        command.pre_exec(stop::unblock_signals);
+    }
+    if let Some(mask) = options.umask {
+        // SAFETY: umask(2) is async-signal-safe and can't fail
+        unsafe {
+            command.pre_exec(move || {
+                libc::umask(mask);
+                Ok(())
+            });
+        }
+    }
+    // a group of its own so the whole tree can be stopped, except with the terminal it would need to be in front
+    if !options.open_tty {
+        command.process_group(0);
+    }
+    command.args(&arg_list).stdin(stdin_cfg);
+    Ok(command)
+}
+
+/// A job that couldn't be started, reporting why
+fn spawn_failed(error: String) -> Proc {
+    eprintln!("{}", error);
+    Proc::SpawnFailed(error)
+}
+
+fn spawn(options: &PoolOptions, mut command: process::Command, program: &str) -> Proc {
+    // held across the spawn so a stop request can't miss the new process
+    let mut running = options.running.lock().unwrap();
+    let child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return spawn_failed(format!("unable to spawn {}: {}", program, e)),
+    };
+    running.insert(child.id());
+    debug!(pid = child.id(), "spawned");
+    Proc::Local(child)
+}
+
+#[cfg(test)]
+mod test {
+    use super::ProcPool;
+    use crate::args::DynArgBuilderMaker;
+    use crate::status::{Halt, KillCause, Outcome, TIMEOUT_EXIT_CODE};
+    use std::time::Duration;
+    use std::{env, fs, process};
+
+    #[test]
+    fn no_parallelism_is_refused() {
+        let maker = DynArgBuilderMaker::append(vec![], 1);
+        let pool = ProcPool::builder("true".into(), maker)
+            .max_parallelism(0)
+            .build();
+        assert!(pool.is_err());
+    }
+
+    #[test]
+    fn spawn_failures_are_in_results() {
+        let maker = DynArgBuilderMaker::append(vec![], 1);
+        let mut pool = ProcPool::builder("/nonexistent/pll-test".into(), maker)
+            .build()
+            .unwrap();
+        pool.push_arg("a").unwrap();
+        let results = pool.wait_all().unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].outcome, Outcome::SpawnFailed);
+        assert_eq!(results[0].exit_code, 127);
+        let error = results[0].error.as_deref().unwrap();
+        assert!(
+            error.starts_with("unable to spawn /nonexistent/pll-test"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn timeouts_are_their_own_outcome() {
+        let maker = DynArgBuilderMaker::append(vec![], 1);
+        let mut pool = ProcPool::builder("sleep".into(), maker)
+            .max_parallelism(2)
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        pool.push_arg("0").unwrap();
+        pool.push_arg("10").unwrap();
+        let results = pool.wait_all().unwrap();
+        let outcomes: Vec<_> = results.iter().map(|r| r.outcome).collect();
+        assert_eq!(outcomes, [Outcome::Success, Outcome::TimedOut]);
+        assert_eq!(pool.failures().exit_code(), TIMEOUT_EXIT_CODE);
+    }
+
+    #[test]
+    fn retries_keep_their_sequence_number() {
+        let dir = env::temp_dir().join(format!("pll-test-retries-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = "[ -e $0 ] || { touch $0; exit 1; }";
+        let maker = DynArgBuilderMaker::append(vec!["-c".into(), script.into()], 1);
+        let mut pool = ProcPool::builder("sh".into(), maker)
+            .retries(1, Duration::ZERO)
+            .build()
+            .unwrap();
+        for name in ["a", "b"] {
+            pool.push_arg(&dir.join(name).to_string_lossy()).unwrap();
+        }
+        let results = pool.wait_all().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        let seqs: Vec<_> = results.iter().map(|r| (r.seq, r.outcome)).collect();
+        assert_eq!(seqs, [(0, Outcome::Success), (1, Outcome::Success)]);
+    }
+
+    #[test]
+    fn timeouts_send_the_stop_signal_first() {
+        let script = "trap 'exit 3' TERM; sleep $0 & wait";
+        let maker = DynArgBuilderMaker::append(vec!["-c".into(), script.into()], 1);
+        let mut pool = ProcPool::builder("sh".into(), maker)
+            .timeout(Duration::from_millis(100))
+            .options(|options| options.kill_grace = Duration::from_millis(200))
+            .build()
+            .unwrap();
+        pool.push_arg("10").unwrap();
+        let results = pool.wait_all().unwrap();
+        assert_eq!(results[0].outcome, Outcome::TimedOut);
+        assert_eq!(results[0].exit_code, 3);
+        assert_eq!(results[0].killed.unwrap().signal, libc::SIGTERM);
+    }
+
+    #[test]
+    fn halts_send_the_stop_signal_first() {
+        // failing once the other job set its trap
+        let script = "[ $0 = fail ] && sleep 0.5 && exit 1; trap 'exit 3' TERM; sleep 10 & wait";
+        let maker = DynArgBuilderMaker::append(vec!["-c".into(), script.into()], 1);
+        let mut pool = ProcPool::builder("sh".into(), maker)
+            .max_parallelism(2)
+            .halt(Halt::Now)
+            .build()
+            .unwrap();
+        pool.push_arg("sleep").unwrap();
+        pool.push_arg("fail").unwrap();
+        let results = pool.wait_all().unwrap();
+        assert_eq!(results[0].exit_code, 3);
+        let killed = results[0].killed.unwrap();
+        assert_eq!(killed.cause, KillCause::Halt);
+        assert_eq!(killed.signal, libc::SIGTERM);
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::info;
+
+/// How often the battery and CPU temperature are looked at again for --power-aware
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// What the machine can take right now
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum State {
+    Normal,
+    /// On battery or with the CPU throttled, half the jobs
+    Reduced,
+    /// On battery below the threshold, no new jobs until the machine is plugged in
+    Paused,
+}
+
+/// Watches the battery and the CPU temperature, for --power-aware
+pub struct Power {
+    min_battery: u8,
+    state: State,
+    checked_at: Option<Instant>,
+    /// Times the CPUs were throttled so far, any increase means they are too hot
+    throttle_count: u64,
+}
+
+fn read(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_owned())
+}
+
+/// Charge left in percent, `None` unless a battery is discharging
+fn battery() -> Option<u8> {
+    fs::read_dir("/sys/class/power_supply")
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| read(&path.join("type")).as_deref() == Some("Battery"))
+        .filter(|path| read(&path.join("status")).as_deref() == Some("Discharging"))
+        .filter_map(|path| read(&path.join("capacity"))?.parse().ok())
+        .min()
+}
+
+/// Times any CPU was throttled since boot, only Intel CPUs report it
+fn throttle_count() -> u64 {
+    let Ok(cpus) = fs::read_dir("/sys/devices/system/cpu") else {
+        return 0;
+    };
+    cpus.flatten()
+        .filter_map(|cpu| read(&cpu.path().join("thermal_throttle/package_throttle_count")))
+        .filter_map(|count| count.parse::<u64>().ok())
+        .sum()
+}
+
+/// Whether a thermal zone is past its passive trip point, where the kernel starts cooling it down
+fn overheated() -> bool {
+    let Ok(zones) = fs::read_dir("/sys/class/thermal") else {
+        return false;
+    };
+    zones.flatten().map(|zone| zone.path()).any(|zone| {
+        let Some(temp) = read(&zone.join("temp")).and_then(|t| t.parse::<i64>().ok()) else {
+            return false;
+        };
+        (0..16).any(|trip| {
+            read(&zone.join(format!("trip_point_{}_type", trip))).as_deref() == Some("passive")
+                && read(&zone.join(format!("trip_point_{}_temp", trip)))
+                    .and_then(|t| t.parse::<i64>().ok())
+                    .is_some_and(|passive| passive > 0 && temp >= passive)
+        })
+    })
+}
+
+fn state(min_battery: u8, battery: Option<u8>, hot: bool) -> State {
+    match battery {
+        Some(charge) if charge < min_battery => State::Paused,
+        Some(_) => State::Reduced,
+        None if hot => State::Reduced,
+        None => State::Normal,
+    }
+}
+
+impl Power {
+    pub fn new(min_battery: u8) -> Power {
+        Power {
+            min_battery,
+            state: State::Normal,
+            checked_at: None,
+            throttle_count: throttle_count(),
+        }
+    }
+
+    /// Looks at the machine again when it wasn't looked at for a while
+    pub fn tick(&mut self) {
+        if self
+            .checked_at
+            .is_some_and(|at| at.elapsed() < CHECK_INTERVAL)
+        {
+            return;
+        }
+        self.checked_at = Some(Instant::now());
+        let count = throttle_count();
+        let throttled = count > self.throttle_count;
+        self.throttle_count = count;
+        let battery = battery();
+        let state = state(self.min_battery, battery, throttled || overheated());
+        if state != self.state {
+            info!(?state, ?battery, "power state changed");
+            self.state = state;
+        }
+    }
+
+    /// Number of jobs allowed out of `parallelism`
+    pub fn limit(&self, parallelism: usize) -> usize {
+        match self.state {
+            State::Normal => parallelism,
+            State::Reduced | State::Paused => parallelism.div_ceil(2),
+        }
+    }
+
+    /// Whether new jobs have to wait for the machine to be plugged in
+    pub fn paused(&self) -> bool {
+        self.state == State::Paused
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{state, Power, State};
+
+    #[test]
+    fn state_works() {
+        assert_eq!(state(20, None, false), State::Normal);
+        assert_eq!(state(20, None, true), State::Reduced);
+        assert_eq!(state(20, Some(80), false), State::Reduced);
+        assert_eq!(state(20, Some(19), false), State::Paused);
+        let mut power = Power::new(20);
+        power.state = State::Reduced;
+        assert_eq!(power.limit(7), 4);
+        assert_eq!(power.limit(1), 1);
+    }
+}
@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::Mutex;
+
+/// Size of the chunks output is passed on in when lines aren't tagged
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn name(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// What every line printed by a job starts with, with --tag or --prefix
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Prefix {
+    /// The first argument of the job
+    Arg,
+    /// The sequence number of the job
+    Seq,
+}
+
+/// Output of a job that can't be printed yet
+#[derive(Debug, Default, PartialEq)]
+struct Held {
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    finished: bool,
+}
+
+#[derive(Debug)]
+struct State {
+    /// Hold the output of every job until it finished
+    group: bool,
+    /// Print the output of the jobs in sequence order
+    keep_order: bool,
+    /// Job whose output is printed next with `keep_order`
+    next: usize,
+    held: BTreeMap<usize, Held>,
+}
+
+impl State {
+    /// Whether `data` can be printed right away, it is held otherwise
+    fn write(&mut self, seq: usize, stream: Stream, data: &[u8]) -> bool {
+        if !self.group && (!self.keep_order || seq == self.next) {
+            return true;
+        }
+        let held = self.held.entry(seq).or_default();
+        match stream {
+            Stream::Stdout => held.stdout.extend_from_slice(data),
+            Stream::Stderr => held.stderr.extend_from_slice(data),
+        }
+        false
+    }
+
+    /// Output that can be printed now that the job finished, in order
+    fn finish(&mut self, seq: usize) -> Vec<Held> {
+        if !self.keep_order {
+            return self.held.remove(&seq).into_iter().collect();
+        }
+        self.held.entry(seq).or_default().finished = true;
+        let mut ready = vec![];
+        while let Some(held) = self.held.get_mut(&self.next) {
+            if !held.finished {
+                // the job is the oldest one left, what it prints from now on goes straight through
+                if !self.group {
+                    ready.push(Held {
+                        stdout: std::mem::take(&mut held.stdout),
+                        stderr: std::mem::take(&mut held.stderr),
+                        finished: false,
+                    });
+                }
+                break;
+            }
+            ready.extend(
+                self.held
+                    .remove(&self.next)
+                    .filter(|held| !held.stdout.is_empty() || !held.stderr.is_empty()),
+            );
+            self.next += 1;
+        }
+        ready
+    }
+}
+
+fn emit(stream: Stream, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+    let written = match stream {
+        // flushed so what follows on stderr doesn't overtake it
+        Stream::Stdout => {
+            let mut stdout = io::stdout().lock();
+            stdout.write_all(data).and_then(|_| stdout.flush())
+        }
+        Stream::Stderr => io::stderr().write_all(data),
+    };
+    if let Err(e) = written {
+        if e.kind() != io::ErrorKind::BrokenPipe {
+            eprintln!("failed to write {}: {}", stream.name(), e);
+        }
+    }
+}
+
+/// Prints the stdout and stderr of local jobs, with --keep-order, --pipe-stdout, --tag or --prefix
+///
+/// Output is passed on as it is read unless it has to wait for its job, or the jobs before it, to finish. Only
+/// held output is buffered, so a job printing a lot streams it when it's the oldest one running with
+/// --keep-order.
+pub struct Printer {
+    prefix: Option<Prefix>,
+    state: Mutex<State>,
+}
+
+impl Printer {
+    /// `group` holds the output of each job until it finished, `keep_order` prints it in sequence order
+    /// starting from `first_seq`
+    pub fn new(group: bool, keep_order: bool, prefix: Option<Prefix>, first_seq: usize) -> Printer {
+        Printer {
+            prefix,
+            state: Mutex::new(State {
+                group,
+                keep_order,
+                next: first_seq,
+                held: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Tag of every line printed by a job, `None` unless --tag or --prefix was given
+    pub fn tag(&self, seq: usize, inputs: &[String]) -> Option<String> {
+        match self.prefix? {
+            Prefix::Arg => Some(inputs.first().cloned().unwrap_or_default()),
+            Prefix::Seq => Some(seq.to_string()),
+        }
+    }
+
+    fn write(&self, seq: usize, stream: Stream, data: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        if state.write(seq, stream, data) {
+            emit(stream, data);
+        }
+    }
+
+    /// Reads `reader` to the end, starting every line with `tag` and a tab when there is one
+    pub fn copy(&self, seq: usize, stream: Stream, reader: impl Read, tag: Option<&str>) {
+        let copied = match tag {
+            Some(tag) => self.copy_lines(seq, stream, reader, tag),
+            None => self.copy_chunks(seq, stream, reader),
+        };
+        if let Err(e) = copied {
+            eprintln!("failed to read {}: {}", stream.name(), e);
+        }
+    }
+
+    fn copy_lines(
+        &self,
+        seq: usize,
+        stream: Stream,
+        reader: impl Read,
+        tag: &str,
+    ) -> io::Result<()> {
+        let mut reader = BufReader::new(reader);
+        let mut line = format!("{}\t", tag).into_bytes();
+        let start = line.len();
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            if !line.ends_with(b"\n") {
+                line.push(b'\n');
+            }
+            self.write(seq, stream, &line);
+            line.truncate(start);
+        }
+        Ok(())
+    }
+
+    fn copy_chunks(&self, seq: usize, stream: Stream, mut reader: impl Read) -> io::Result<()> {
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(read) => self.write(seq, stream, &buf[..read]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Prints a line of pll's own as the last one of the output of a job, tagged like the others
+    pub fn mark(&self, seq: usize, tag: Option<&str>, marker: &str) {
+        let line = match tag {
+            Some(tag) => format!("{}\t{}\n", tag, marker),
+            None => format!("{}\n", marker),
+        };
+        self.write(seq, Stream::Stdout, line.as_bytes());
+    }
+
+    /// Called once the job finished and its output was read, whether it ran or not, so the jobs after it aren't
+    /// held back forever with --keep-order
+    pub fn finish(&self, seq: usize) {
+        let mut state = self.state.lock().unwrap();
+        for held in state.finish(seq) {
+            emit(Stream::Stdout, &held.stdout);
+            emit(Stream::Stderr, &held.stderr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Held, State, Stream};
+    use std::collections::BTreeMap;
+
+    fn new_state(group: bool, keep_order: bool) -> State {
+        State {
+            group,
+            keep_order,
+            next: 0,
+            held: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn keep_order_works() {
+        let mut state = new_state(false, true);
+        assert!(!state.write(1, Stream::Stdout, b"b\n"));
+        assert!(!state.write(2, Stream::Stderr, b"c\n"));
+        assert!(state.write(0, Stream::Stdout, b"a\n"));
+        assert!(state.finish(1).is_empty());
+        let ready = state.finish(0);
+        assert_eq!(ready.len(), 2);
+        assert_eq!(ready[0].stdout, b"b\n");
+        // job 2 still runs, what it printed so far is let through and the rest streams
+        assert_eq!(ready[1].stderr, b"c\n");
+        assert!(!ready[1].finished);
+        assert!(state.write(2, Stream::Stdout, b"d\n"));
+        assert!(!state.write(3, Stream::Stdout, b"e\n"));
+        assert_eq!(state.finish(2)[0].stdout, b"e\n");
+        assert!(state.write(3, Stream::Stdout, b"f\n"));
+        assert!(state.finish(3).is_empty());
+        assert!(state.held.is_empty());
+    }
+
+    #[test]
+    fn group_works() {
+        let mut state = new_state(true, false);
+        assert!(!state.write(1, Stream::Stdout, b"a\n"));
+        assert!(!state.write(1, Stream::Stderr, b"b\n"));
+        assert_eq!(
+            state.finish(1),
+            vec![Held {
+                stdout: b"a\n".to_vec(),
+                stderr: b"b\n".to_vec(),
+                finished: false,
+            }]
+        );
+        assert!(state.finish(0).is_empty());
+        assert!(new_state(false, false).write(3, Stream::Stdout, b"c\n"));
+    }
+}
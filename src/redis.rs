@@ -0,0 +1,189 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+
+/// Minimal RESP client able to consume a Redis list as a queue
+pub struct RedisQueue {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    key: String,
+}
+
+#[derive(Debug, PartialEq)]
+enum Reply {
+    Status(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Option<Vec<Reply>>),
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Parts of a `redis://[[user]:password@]host[:port][/db]` url
+#[derive(Debug, PartialEq)]
+struct Url<'a> {
+    user: Option<&'a str>,
+    password: Option<&'a str>,
+    addr: String,
+    db: Option<&'a str>,
+}
+
+fn parse_url(url: &str) -> io::Result<Url<'_>> {
+    let rest = url
+        .strip_prefix("redis://")
+        .ok_or_else(|| invalid(format!("unsupported redis url: {}", url)))?;
+    let (auth, rest) = match rest.rsplit_once('@') {
+        Some((auth, rest)) => (Some(auth), rest),
+        None => (None, rest),
+    };
+    let (user, password) = match auth.map(|a| a.split_once(':')) {
+        Some(Some((user, password))) => ((!user.is_empty()).then_some(user), Some(password)),
+        Some(None) => (None, auth),
+        None => (None, None),
+    };
+    let (host, db) = match rest.split_once('/') {
+        Some((host, db)) => (host, (!db.is_empty()).then_some(db)),
+        None => (rest, None),
+    };
+    let addr = if host.contains(':') {
+        host.to_owned()
+    } else {
+        format!("{}:6379", host)
+    };
+    Ok(Url {
+        user,
+        password,
+        addr,
+        db,
+    })
+}
+
+fn read_reply(reader: &mut impl BufRead) -> io::Result<Reply> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let line = line.trim_end_matches("\r\n");
+    let (kind, rest) = line.split_at(line.len().min(1));
+    let int = |s: &str| {
+        s.parse::<i64>()
+            .map_err(|_| invalid(format!("bad redis reply: {}", line)))
+    };
+    match kind {
+        "+" => Ok(Reply::Status(rest.to_owned())),
+        "-" => Err(io::Error::other(format!("redis error: {}", rest))),
+        ":" => Ok(Reply::Integer(int(rest)?)),
+        "$" => match int(rest)? {
+            len if len < 0 => Ok(Reply::Bulk(None)),
+            len => {
+                let mut buf = vec![0; len as usize + 2];
+                reader.read_exact(&mut buf)?;
+                buf.truncate(len as usize);
+                Ok(Reply::Bulk(Some(buf)))
+            }
+        },
+        "*" => match int(rest)? {
+            len if len < 0 => Ok(Reply::Array(None)),
+            len => (0..len)
+                .map(|_| read_reply(reader))
+                .collect::<io::Result<_>>()
+                .map(|items| Reply::Array(Some(items))),
+        },
+        _ => Err(invalid(format!("bad redis reply: {}", line))),
+    }
+}
+
+impl RedisQueue {
+    pub fn connect(url: &str, key: &str) -> io::Result<RedisQueue> {
+        let url = parse_url(url)?;
+        let writer = TcpStream::connect(&url.addr)?;
+        let mut queue = RedisQueue {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+            key: key.to_owned(),
+        };
+        match (url.user, url.password) {
+            (Some(user), Some(password)) => queue.command(&["AUTH", user, password])?,
+            (None, Some(password)) => queue.command(&["AUTH", password])?,
+            _ => Reply::Status("OK".into()),
+        };
+        if let Some(db) = url.db {
+            queue.command(&["SELECT", db])?;
+        }
+        Ok(queue)
+    }
+
+    fn command(&mut self, args: &[&str]) -> io::Result<Reply> {
+        let mut buf = format!("*{}\r\n", args.len()).into_bytes();
+        for arg in args {
+            buf.extend_from_slice(format!("${}\r\n{}\r\n", arg.len(), arg).as_bytes());
+        }
+        self.writer.write_all(&buf)?;
+        read_reply(&mut self.reader)
+    }
+
+    /// Blocks until an item can be popped from the head of the list
+    pub fn pop(&mut self) -> io::Result<Vec<u8>> {
+        let key = self.key.clone();
+        match self.command(&["BLPOP", &key, "0"])? {
+            Reply::Array(Some(mut items)) if items.len() == 2 => match items.pop() {
+                Some(Reply::Bulk(Some(item))) => Ok(item),
+                other => Err(invalid(format!("unexpected BLPOP item: {:?}", other))),
+            },
+            other => Err(invalid(format!("unexpected BLPOP reply: {:?}", other))),
+        }
+    }
+}
+
+impl Iterator for RedisQueue {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<io::Result<Vec<u8>>> {
+        Some(self.pop())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_url, read_reply, Reply, Url};
+
+    #[test]
+    fn parse_url_works() {
+        assert_eq!(
+            parse_url("redis://:secret@example.com/2").unwrap(),
+            Url {
+                user: None,
+                password: Some("secret"),
+                addr: "example.com:6379".into(),
+                db: Some("2"),
+            }
+        );
+        assert_eq!(
+            parse_url("redis://localhost:7000").unwrap().addr,
+            "localhost:7000"
+        );
+        assert!(parse_url("http://localhost").is_err());
+    }
+
+    #[test]
+    fn read_reply_works() {
+        let raw = b"*2\r\n$4\r\njobs\r\n$5\r\na\r\nb!\r\n";
+        assert_eq!(
+            read_reply(&mut &raw[..]).unwrap(),
+            Reply::Array(Some(vec![
+                Reply::Bulk(Some(b"jobs".to_vec())),
+                Reply::Bulk(Some(b"a\r\nb!".to_vec())),
+            ]))
+        );
+        assert!(read_reply(&mut &b"-ERR nope\r\n"[..]).is_err());
+    }
+
+    #[test]
+    fn truncated_reply_fails() {
+        for raw in [&b"$5\r\nab"[..], b"$2\r\nab", b"*2\r\n$1\r\na\r\n", b""] {
+            let e = read_reply(&mut &raw[..]).unwrap_err();
+            assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+        }
+    }
+}
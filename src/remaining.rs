@@ -0,0 +1,39 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Where the input records a stopped run didn't get to are written with --remaining, each followed by a NUL
+pub struct Remaining {
+    path: PathBuf,
+    out: BufWriter<File>,
+    count: usize,
+}
+
+impl Remaining {
+    /// Creates the file up front, so a bad path is reported before any job starts
+    pub fn create(path: PathBuf) -> io::Result<Remaining> {
+        let out = BufWriter::new(File::create(&path)?);
+        Ok(Remaining {
+            path,
+            out,
+            count: 0,
+        })
+    }
+
+    pub fn push(&mut self, record: &str) -> io::Result<()> {
+        self.out.write_all(record.as_bytes())?;
+        self.out.write_all(b"\0")?;
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Flushes the file, returning how many records were written
+    pub fn finish(mut self) -> io::Result<usize> {
+        self.out.flush()?;
+        Ok(self.count)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
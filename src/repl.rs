@@ -0,0 +1,205 @@
+use crate::args::{self, ArgBuilder, ArgBuilderMaker};
+use crate::output::tag_lines;
+use crate::slots::Slots;
+use clap::builder::RangedU64ValueParser;
+use rustyline::error::ReadlineError;
+use rustyline::ExternalPrinter;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::{process, thread};
+
+#[derive(clap::Args, Debug)]
+pub struct ReplArgs {
+    #[arg(short = 'p', long, default_value_t = 16, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    /// Max number of processes running at the same time
+    max_parallelism: usize,
+
+    #[arg(short = 'l', long = "template")]
+    /// Process the program strings as a template filled with the words of each line
+    ///
+    /// Without it the words of each line are appended to the program arguments.
+    template: bool,
+
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    program: Vec<String>,
+}
+
+fn run_job(program: &str, args: Vec<String>, tag: &str) -> String {
+    match process::Command::new(program)
+        .args(args)
+        .stdin(process::Stdio::null())
+        .output()
+    {
+        Ok(output) => format!(
+            "{}{}{} {}",
+            tag_lines(tag, &output.stdout),
+            tag_lines(tag, &output.stderr),
+            tag,
+            output.status
+        ),
+        Err(e) => format!("{} unable to spawn process: {}", tag, e),
+    }
+}
+
+/// Argument list of the job for a line, `None` when its words don't fill the template
+fn job_args(maker: &args::DynArgBuilderMaker, line: &str, seq: usize) -> Option<Vec<String>> {
+    let mut builder = maker.make();
+    for word in line.split_whitespace() {
+        if builder.viable() && maker.is_template {
+            break;
+        }
+        builder.push_arg(word);
+    }
+    if !builder.viable() {
+        return None;
+    }
+    let mut arg_list = builder.arg_list();
+    for idx in builder.numbered() {
+        arg_list[idx] = maker.fill_numbered(&arg_list[idx], seq, &builder.inputs());
+    }
+    Some(arg_list)
+}
+
+/// Runs the jobs received on `jobs`, tagged argument lists, sending their results on `results`
+///
+/// A job only gets a thread once it has a slot, so no more threads than slots run jobs however many lines are
+/// typed. Returns once `jobs` is closed and every job finished.
+fn dispatch(
+    program: &str,
+    jobs: mpsc::Receiver<(String, Vec<String>)>,
+    slots: Arc<Slots>,
+    results: mpsc::Sender<String>,
+) {
+    let mut running: Vec<thread::JoinHandle<()>> = vec![];
+    for (tag, arg_list) in jobs {
+        slots.acquire();
+        running.retain(|job| !job.is_finished());
+        let (program, slots, results) = (program.to_owned(), slots.clone(), results.clone());
+        running.push(thread::spawn(move || {
+            let msg = run_job(&program, arg_list, &tag);
+            slots.release();
+            let _ = results.send(msg);
+        }));
+    }
+    for job in running {
+        let _ = job.join();
+    }
+}
+
+pub fn run(args: ReplArgs) -> i32 {
+    let mut editor = match rustyline::DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("unable to start repl: {}", e);
+            return 1;
+        }
+    };
+
+    // results are funneled through a single thread so they can be printed above the prompt
+    let (results, receiver) = mpsc::channel::<String>();
+    let printer = editor.create_external_printer().ok();
+    let printer_thread = thread::spawn(move || {
+        let mut printer = printer;
+        for msg in receiver {
+            let printed = match printer.as_mut() {
+                Some(p) => p.print(msg.clone() + "\n").is_ok(),
+                None => false,
+            };
+            if !printed {
+                println!("{}", msg);
+            }
+        }
+    });
+
+    let maker = args::DynArgBuilderMaker {
+        is_template: args.template,
+        initial_args: args.program[1..].to_vec(),
+        max_args: usize::MAX,
+        min_args: 0,
+        max_chars: usize::MAX,
+        batch_bytes: usize::MAX,
+        shell: None,
+    };
+    let program = args.program[0].clone();
+    let slots = Arc::new(Slots::new(args.max_parallelism));
+    let (jobs, queued) = mpsc::channel();
+    let dispatcher = thread::spawn(move || dispatch(&program, queued, slots, results));
+
+    let mut seq = 0;
+    loop {
+        let line = match editor.readline("pll> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("failed to read line: {}", e);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line.as_str());
+
+        let Some(arg_list) = job_args(&maker, &line, seq) else {
+            eprintln!("not enough arguments to fill the template");
+            continue;
+        };
+        let tag = format!("[{}]", seq);
+        seq += 1;
+        println!("{} {}", tag, arg_list.join(" "));
+        let _ = jobs.send((tag, arg_list));
+    }
+
+    drop(jobs);
+    let _ = dispatcher.join();
+    let _ = printer_thread.join();
+    0
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dispatch, job_args};
+    use crate::args::DynArgBuilderMaker;
+    use crate::slots::Slots;
+    use std::sync::{mpsc, Arc};
+    use std::{env, fs, process};
+
+    #[test]
+    fn job_args_works() {
+        let maker = DynArgBuilderMaker {
+            min_args: 0,
+            ..DynArgBuilderMaker::append(vec!["-n".into()], usize::MAX)
+        };
+        assert_eq!(job_args(&maker, "a  b", 0).unwrap(), ["-n", "a", "b"]);
+        let maker = DynArgBuilderMaker::template(vec!["{1}".into(), "{0}.{#}".into()]).unwrap();
+        assert_eq!(job_args(&maker, "a b c", 3).unwrap(), ["b", "a.3"]);
+        assert!(job_args(&maker, "a", 4).is_none());
+    }
+
+    #[test]
+    fn dispatch_runs_as_many_jobs_as_slots() {
+        let lock = env::temp_dir().join(format!("pll-test-repl-{}", process::id()));
+        // fails when another job holds the lock
+        let script = "mkdir $0 || exit 1; sleep 0.1; rmdir $0";
+        let (jobs, queued) = mpsc::channel();
+        let (results, received) = mpsc::channel();
+        for seq in 0..3 {
+            let arg_list = vec!["-c".into(), script.into(), lock.to_string_lossy().into()];
+            jobs.send((format!("[{}]", seq), arg_list)).unwrap();
+        }
+        drop(jobs);
+        dispatch("sh", queued, Arc::new(Slots::new(1)), results);
+        let mut results: Vec<String> = received.iter().collect();
+        results.sort();
+        assert_eq!(
+            results,
+            [
+                "[0] exit status: 0",
+                "[1] exit status: 0",
+                "[2] exit status: 0"
+            ]
+        );
+        assert!(fs::metadata(&lock).is_err());
+    }
+}
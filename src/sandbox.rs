@@ -0,0 +1,202 @@
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::ptr;
+
+/// Namespaces isolating each local job: a restricted view of the filesystem, with a read-only root and a private
+/// /tmp, and a network namespace of its own without any connectivity
+///
+/// Set up between fork and exec. When not running as root, by default or because of --user, a user namespace
+/// mapping the job's own ids is created too so the other namespaces can still be set up.
+pub struct Sandbox {
+    filesystem: bool,
+    network: bool,
+    /// Paths kept writable, bind mounted over themselves and made writable again once every mount is read-only
+    writable: Vec<CString>,
+    uid_map: CString,
+    gid_map: CString,
+}
+
+fn check(res: libc::c_int) -> io::Result<()> {
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Writes a whole file with plain syscalls, as allocating isn't safe after fork
+fn write_file(path: &CStr, content: &CStr) -> io::Result<()> {
+    // SAFETY: both strings are NUL terminated and outlive the calls
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY);
+        check(fd)?;
+        let bytes = content.to_bytes();
+        let written = libc::write(fd, bytes.as_ptr().cast(), bytes.len());
+        libc::close(fd);
+        if written != bytes.len() as isize {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn mount(
+    source: Option<&CStr>,
+    target: &CStr,
+    fstype: Option<&CStr>,
+    flags: libc::c_ulong,
+) -> io::Result<()> {
+    let ptr_of = |s: Option<&CStr>| s.map_or(ptr::null(), CStr::as_ptr);
+    // SAFETY: every string is NUL terminated or null, which mount(2) accepts for the ones passed as such
+    check(unsafe {
+        libc::mount(
+            ptr_of(source),
+            target.as_ptr(),
+            ptr_of(fstype),
+            flags,
+            ptr::null(),
+        )
+    })
+}
+
+/// Makes the mount at `path` and every mount under it read-only, or writable again
+fn set_readonly(path: &CStr, readonly: bool) -> io::Result<()> {
+    // SAFETY: mount_attr is plain integers, for which zero means no change
+    let mut attr: libc::mount_attr = unsafe { std::mem::zeroed() };
+    if readonly {
+        attr.attr_set = libc::MOUNT_ATTR_RDONLY;
+    } else {
+        attr.attr_clr = libc::MOUNT_ATTR_RDONLY;
+    }
+    // SAFETY: the path is NUL terminated and attr outlives the call, with its size passed along
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            libc::AT_RECURSIVE,
+            &attr as *const libc::mount_attr,
+            size_of::<libc::mount_attr>(),
+        )
+    };
+    check(res as libc::c_int)
+}
+
+impl Sandbox {
+    /// `uid` and `gid` are the ids the jobs end up running with
+    pub fn new(
+        filesystem: bool,
+        network: bool,
+        writable: Vec<PathBuf>,
+        uid: libc::uid_t,
+        gid: libc::gid_t,
+    ) -> io::Result<Sandbox> {
+        let writable = writable
+            .into_iter()
+            .map(|p| {
+                let p = p.canonicalize()?;
+                if p.starts_with("/tmp") {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("{} is hidden by the private /tmp", p.display()),
+                    ));
+                }
+                CString::new(p.as_os_str().as_bytes()).map_err(io::Error::from)
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Sandbox {
+            filesystem,
+            network,
+            writable,
+            uid_map: CString::new(format!("{} {} 1", uid, uid))?,
+            gid_map: CString::new(format!("{} {} 1", gid, gid))?,
+        })
+    }
+
+    /// Enters the sandbox, to be called in the child right before exec
+    pub fn enter(&self) -> io::Result<()> {
+        let mut flags = 0;
+        if self.filesystem {
+            flags |= libc::CLONE_NEWNS;
+        }
+        if self.network {
+            flags |= libc::CLONE_NEWNET;
+        }
+        // SAFETY: plain syscalls on the child's own namespaces
+        if unsafe { libc::geteuid() } != 0 {
+            // switching users from root leaves /proc/self owned by root until made dumpable again
+            check(unsafe { libc::prctl(libc::PR_SET_DUMPABLE, 1) })?;
+            check(unsafe { libc::unshare(libc::CLONE_NEWUSER | flags) })?;
+            write_file(c"/proc/self/setgroups", c"deny")?;
+            write_file(c"/proc/self/uid_map", &self.uid_map)?;
+            write_file(c"/proc/self/gid_map", &self.gid_map)?;
+        } else {
+            check(unsafe { libc::unshare(flags) })?;
+        }
+        if !self.filesystem {
+            return Ok(());
+        }
+        // keeps the mounts below from propagating back to the rest of the system
+        mount(None, c"/", None, libc::MS_REC | libc::MS_PRIVATE)?;
+        for path in &self.writable {
+            mount(Some(path), path, None, libc::MS_BIND | libc::MS_REC)?;
+        }
+        // a remount only changes the mount it is given, /dev/shm, /home and the like would stay writable
+        set_readonly(c"/", true)?;
+        for path in &self.writable {
+            set_readonly(path, false)?;
+        }
+        mount(Some(c"tmpfs"), c"/tmp", Some(c"tmpfs"), 0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Sandbox;
+    use std::os::unix::process::CommandExt;
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+    use std::sync::Arc;
+
+    /// Runs `touch path` in a filesystem sandbox, None when the sandbox can't be set up here
+    fn touch_sandboxed(path: &Path, writable: Vec<std::path::PathBuf>) -> Option<bool> {
+        // SAFETY: plain id getters
+        let (uid, gid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        let sandbox = Arc::new(Sandbox::new(true, false, writable, uid, gid).unwrap());
+        let mut command = Command::new("touch");
+        command.arg(path).stderr(Stdio::null());
+        // SAFETY: entering the sandbox only makes syscalls
+        unsafe {
+            command.pre_exec(move || sandbox.enter());
+        }
+        command.status().ok().map(|status| status.success())
+    }
+
+    #[test]
+    fn submounts_are_read_only() {
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").unwrap();
+        // any writable mount below the root will do
+        let Some(mount) = ["/dev/shm", "/run", "/home"]
+            .into_iter()
+            .map(Path::new)
+            .find(|p| {
+                mountinfo
+                    .lines()
+                    .any(|l| l.split(' ').nth(4) == Some(p.to_str().unwrap()))
+                    && std::fs::File::create(p.join(".pll-sandbox-test")).is_ok()
+            })
+        else {
+            return;
+        };
+        let probe = mount.join(".pll-sandbox-test");
+        std::fs::remove_file(&probe).unwrap();
+        let Some(touched) = touch_sandboxed(&probe, vec![]) else {
+            return;
+        };
+        assert!(!touched, "{} is still writable", mount.display());
+        assert!(!probe.exists());
+        assert_eq!(touch_sandboxed(&probe, vec![mount.into()]), Some(true));
+        std::fs::remove_file(&probe).unwrap();
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Order in which jobs are started with --schedule
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Schedule {
+    /// In input order
+    #[default]
+    Fifo,
+    /// Last input record first
+    Lifo,
+    /// Shortest job first, by how long it took in past runs, jobs that never ran go last in input order
+    Sjf,
+    /// Shuffled
+    Random,
+}
+
+/// Reorders the jobs, `predict` giving how long one is expected to take
+pub fn order<T>(
+    jobs: &mut VecDeque<T>,
+    schedule: Schedule,
+    predict: impl Fn(&T) -> Option<Duration>,
+) {
+    match schedule {
+        Schedule::Fifo => {}
+        Schedule::Lifo => jobs.make_contiguous().reverse(),
+        // stable, so ties and unknown durations keep the input order
+        Schedule::Sjf => jobs
+            .make_contiguous()
+            .sort_by_cached_key(|job| predict(job).unwrap_or(Duration::MAX)),
+        Schedule::Random => shuffle(jobs.make_contiguous(), seed()),
+    }
+}
+
+fn seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    // never zero, xorshift would get stuck there
+    (nanos ^ (u64::from(std::process::id()) << 32)) | 1
+}
+
+/// Fisher-Yates shuffle driven by xorshift, good enough to spread jobs around
+fn shuffle<T>(items: &mut [T], mut state: u64) {
+    for idx in (1..items.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        items.swap(idx, (state % (idx as u64 + 1)) as usize);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{order, shuffle, Schedule};
+    use std::collections::VecDeque;
+    use std::time::Duration;
+
+    #[test]
+    fn order_works() {
+        let predict = |&n: &u64| (n != 4).then(|| Duration::from_secs(10 - n));
+        let mut jobs: VecDeque<u64> = (1..=5).collect();
+        order(&mut jobs, Schedule::Fifo, predict);
+        assert_eq!(jobs, [1, 2, 3, 4, 5]);
+        order(&mut jobs, Schedule::Lifo, predict);
+        assert_eq!(jobs, [5, 4, 3, 2, 1]);
+        order(&mut jobs, Schedule::Sjf, predict);
+        assert_eq!(jobs, [5, 3, 2, 1, 4]);
+
+        let mut items: Vec<u64> = (0..100).collect();
+        shuffle(&mut items, 42);
+        assert_ne!(items, (0..100).collect::<Vec<_>>());
+        items.sort();
+        assert_eq!(items, (0..100).collect::<Vec<_>>());
+    }
+}
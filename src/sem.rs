@@ -0,0 +1,65 @@
+use clap::builder::RangedU64ValueParser;
+use std::fs::{self, File, OpenOptions};
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{io, process, thread};
+
+#[derive(clap::Args, Debug)]
+pub struct SemArgs {
+    #[arg(long, default_value = "default")]
+    /// Name of the semaphore
+    ///
+    /// Every invocation using the same id competes for the same set of slots.
+    id: String,
+
+    #[arg(short = 'p', long, default_value_t = 1, value_parser = RangedU64ValueParser::<usize>::new().range(1..))]
+    /// Max number of commands holding the semaphore at the same time
+    max_parallelism: usize,
+
+    #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    program: Vec<String>,
+}
+
+/// Directory holding one lock file per slot of the semaphore
+fn sem_dir(id: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("pll-sem-{}", id))
+}
+
+/// Blocks until one of the slots can be locked, the lock is held for as long as the returned file is alive
+fn acquire_slot(args: &SemArgs) -> io::Result<File> {
+    let dir = sem_dir(&args.id);
+    fs::create_dir_all(&dir)?;
+    loop {
+        for slot in 0..args.max_parallelism {
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(dir.join(format!("slot-{}", slot)))?;
+            if file.try_lock().is_ok() {
+                return Ok(file);
+            }
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+pub fn run(args: SemArgs) -> i32 {
+    let _slot = match acquire_slot(&args) {
+        Ok(slot) => slot,
+        Err(e) => {
+            eprintln!("unable to acquire semaphore '{}': {}", args.id, e);
+            return 1;
+        }
+    };
+    match process::Command::new(&args.program[0])
+        .args(&args.program[1..])
+        .status()
+    {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(e) => {
+            eprintln!("unable to spawn process: {}", e);
+            1
+        }
+    }
+}
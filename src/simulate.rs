@@ -0,0 +1,107 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Scripted outcome for the jobs whose command line contains `pattern`, or for any job when there is none
+struct Rule {
+    exit_code: i32,
+    duration: Duration,
+    pattern: Option<String>,
+}
+
+/// Outcomes handed to jobs instead of running them, so the scheduling logic can be exercised deterministically
+///
+/// Each line of the spec reads `EXIT_CODE DURATION_MS [PATTERN]`. A job gets the outcome of the first line whose
+/// pattern is part of its command line, or exits successfully right away when no line matches. Blank lines and
+/// lines starting with `#` are ignored.
+pub struct Script {
+    rules: Vec<Rule>,
+}
+
+fn invalid(line: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line + 1, msg),
+    )
+}
+
+impl Script {
+    pub fn load(path: &Path) -> io::Result<Script> {
+        Script::parse(&fs::read_to_string(path)?)
+    }
+
+    fn parse(spec: &str) -> io::Result<Script> {
+        let mut rules = vec![];
+        for (idx, line) in spec.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut fields = line.splitn(3, char::is_whitespace);
+            let exit_code = fields
+                .next()
+                .and_then(|f| f.parse().ok())
+                .ok_or_else(|| invalid(idx, "bad exit code"))?;
+            let duration = fields
+                .next()
+                .and_then(|f| f.parse().ok())
+                .map(Duration::from_millis)
+                .ok_or_else(|| invalid(idx, "bad duration"))?;
+            let pattern = fields.next().map(|p| p.trim().to_owned());
+            rules.push(Rule {
+                exit_code,
+                duration,
+                pattern,
+            });
+        }
+        Ok(Script { rules })
+    }
+
+    pub fn start(&self, command: &[String]) -> SimulatedJob {
+        let line = command.join(" ");
+        let rule = self
+            .rules
+            .iter()
+            .find(|r| r.pattern.as_ref().is_none_or(|p| line.contains(p.as_str())));
+        let (exit_code, duration) = rule.map_or((0, Duration::ZERO), |r| (r.exit_code, r.duration));
+        SimulatedJob {
+            exit_code,
+            done_at: Instant::now() + duration,
+        }
+    }
+}
+
+pub struct SimulatedJob {
+    exit_code: i32,
+    done_at: Instant,
+}
+
+impl SimulatedJob {
+    pub fn try_wait(&self) -> Option<i32> {
+        (Instant::now() >= self.done_at).then_some(self.exit_code)
+    }
+
+    /// How long until the job finishes
+    pub fn remaining(&self) -> Duration {
+        self.done_at.saturating_duration_since(Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Script;
+    use std::time::Duration;
+
+    #[test]
+    fn script_works() {
+        let script = Script::parse("# comment\n2 0 fail\n\n0 50\n").unwrap();
+        let failing = script.start(&["echo".into(), "fail".into()]);
+        assert_eq!(failing.try_wait(), Some(2));
+        let slow = script.start(&["echo".into(), "ok".into()]);
+        assert_eq!(slow.try_wait(), None);
+        std::thread::sleep(Duration::from_millis(60));
+        assert_eq!(slow.try_wait(), Some(0));
+        assert!(Script::parse("x 10").is_err());
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::{Condvar, Mutex};
+
+/// Counting semaphore limiting how many jobs run at the same time, for what runs jobs without a `ProcPool`
+pub struct Slots {
+    free: Mutex<usize>,
+    cond: Condvar,
+}
+
+impl Slots {
+    pub fn new(count: usize) -> Slots {
+        Slots {
+            free: Mutex::new(count),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// Blocks until a slot is free and takes it
+    pub fn acquire(&self) {
+        let mut free = self.free.lock().unwrap();
+        while *free == 0 {
+            free = self.cond.wait(free).unwrap();
+        }
+        *free -= 1;
+    }
+
+    pub fn release(&self) {
+        *self.free.lock().unwrap() += 1;
+        self.cond.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Slots;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn slots_work() {
+        let slots = Arc::new(Slots::new(1));
+        slots.acquire();
+        let waiter = {
+            let slots = slots.clone();
+            thread::spawn(move || slots.acquire())
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+        slots.release();
+        waiter.join().unwrap();
+    }
+}
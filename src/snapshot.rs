@@ -0,0 +1,87 @@
+use crate::heartbeat::Counts;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// How often the --status-file is rewritten while the run goes on
+const WRITE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// What a slot is running
+#[derive(Serialize, Clone, Debug)]
+pub struct Slot {
+    pub seq: usize,
+    pub command: Vec<String>,
+    /// Only known for local jobs
+    pub pid: Option<u32>,
+    pub elapsed_ms: u64,
+}
+
+/// Progress of a run as written to the --status-file
+#[derive(Serialize, Clone, Default, Debug)]
+pub struct Snapshot {
+    #[serde(flatten)]
+    pub counts: Counts,
+    /// One entry per slot, null when the slot is free
+    pub slots: Vec<Option<Slot>>,
+    /// Set once every job finished
+    pub finished: bool,
+    /// Seconds since the epoch
+    pub updated_at: u64,
+}
+
+/// JSON file rewritten every couple of seconds with the latest snapshot of the run
+pub struct StatusFile {
+    path: PathBuf,
+    snapshot: Arc<Mutex<Snapshot>>,
+}
+
+fn write(path: &Path, snapshot: &Snapshot) -> io::Result<()> {
+    let mut snapshot = snapshot.clone();
+    snapshot.updated_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    // written aside and renamed so readers never see a partial file
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(&snapshot)?)?;
+    fs::rename(&tmp, path)
+}
+
+impl StatusFile {
+    /// Writes an empty snapshot right away, so a bad path is reported before any job starts
+    pub fn create(path: PathBuf) -> io::Result<StatusFile> {
+        let snapshot = Arc::new(Mutex::new(Snapshot::default()));
+        write(&path, &Snapshot::default())?;
+        let (shared, thread_path) = (snapshot.clone(), path.clone());
+        thread::spawn(move || loop {
+            thread::sleep(WRITE_INTERVAL);
+            // written with the lock held so this can't overwrite the final snapshot
+            let snapshot = shared.lock().unwrap();
+            if snapshot.finished {
+                break;
+            }
+            if let Err(e) = write(&thread_path, &snapshot) {
+                warn!("failed to write {}: {}", thread_path.display(), e);
+            }
+        });
+        Ok(StatusFile { path, snapshot })
+    }
+
+    pub fn update(&self, snapshot: Snapshot) {
+        *self.snapshot.lock().unwrap() = snapshot;
+    }
+
+    /// Writes the final snapshot, which is left in place
+    pub fn finish(&self) {
+        let mut snapshot = self.snapshot.lock().unwrap();
+        snapshot.finished = true;
+        if let Err(e) = write(&self.path, &snapshot) {
+            warn!("failed to write {}: {}", self.path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::PathBuf;
+
+/// Separates the program from the argument lists following it, like in GNU parallel
+pub const ARGS_SEPARATOR: &str = ":::";
+
+/// Separates the program from the files following it, each a source of one record per line, like in GNU parallel
+pub const ARG_FILES_SEPARATOR: &str = "::::";
+
+type Records = Box<dyn Iterator<Item = io::Result<String>>>;
+
+/// Records given up front instead of on stdin, with `:::`, `::::` or --arg-file
+#[derive(Debug, PartialEq)]
+pub enum Source {
+    Args(Vec<String>),
+    /// One record per line, `-` being stdin
+    File(PathBuf),
+}
+
+impl Source {
+    fn records(self) -> io::Result<Records> {
+        Ok(match self {
+            Source::Args(args) => Box::new(args.into_iter().map(Ok)),
+            Source::File(path) if path.as_os_str() == "-" => Box::new(io::stdin().lines()),
+            Source::File(path) => Box::new(BufReader::new(File::open(path)?).lines()),
+        })
+    }
+}
+
+/// Splits the words of the program on the `:::` and `::::` separators, `arg_sep` and `arg_file_sep`, returning the
+/// program and the sources following it: the argument list after each `:::` and every file after a `::::`
+pub fn split_args(
+    words: &[String],
+    arg_sep: &str,
+    arg_file_sep: &str,
+) -> (Vec<String>, Vec<Source>) {
+    let mut program = vec![];
+    let mut sources = vec![];
+    // whether the words since the last separator are files
+    let mut files = false;
+    for word in words {
+        if word == arg_sep {
+            sources.push(Source::Args(vec![]));
+            files = false;
+        } else if word == arg_file_sep {
+            files = true;
+        } else if files {
+            sources.push(Source::File(word.into()));
+        } else if let Some(Source::Args(args)) = sources.last_mut() {
+            args.push(word.clone());
+        } else {
+            program.push(word.clone());
+        }
+    }
+    (program, sources)
+}
+
+/// Every combination of one record of each source, the last source changing the fastest like in nested loops
+///
+/// Only the records of the first source are read as they are needed, so it can be stdin or a large file, the other
+/// sources are read up front.
+pub struct Product {
+    first: Records,
+    rest: Vec<Vec<String>>,
+    /// Record of the first source combined with the others right now
+    head: Option<String>,
+    /// Index in each of `rest` of the next combination with `head`
+    idx: Vec<usize>,
+}
+
+impl Product {
+    pub fn open(sources: Vec<Source>) -> io::Result<Product> {
+        let mut sources = sources.into_iter();
+        let first = match sources.next() {
+            Some(source) => source.records()?,
+            None => Box::new(std::iter::empty()),
+        };
+        let rest = sources
+            .map(|source| source.records()?.collect())
+            .collect::<io::Result<Vec<Vec<String>>>>()?;
+        Ok(Product {
+            first,
+            idx: vec![0; rest.len()],
+            rest,
+            head: None,
+        })
+    }
+
+    /// Moves on to the next combination with `head`, returning false once they were all used
+    fn advance(&mut self) -> bool {
+        for (idx, records) in self.idx.iter_mut().zip(&self.rest).rev() {
+            *idx += 1;
+            if *idx < records.len() {
+                return true;
+            }
+            *idx = 0;
+        }
+        false
+    }
+}
+
+impl Iterator for Product {
+    type Item = io::Result<Vec<String>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.rest.iter().any(Vec::is_empty) {
+            return None;
+        }
+        loop {
+            if let Some(head) = &self.head {
+                let combination = std::iter::once(head.clone())
+                    .chain(self.idx.iter().zip(&self.rest).map(|(&i, r)| r[i].clone()))
+                    .collect();
+                if !self.advance() {
+                    self.head = None;
+                }
+                return Some(Ok(combination));
+            }
+            match self.first.next()? {
+                Ok(record) => self.head = Some(record),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{split_args, Product, Source, ARGS_SEPARATOR, ARG_FILES_SEPARATOR};
+
+    fn words(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    fn split(s: &str) -> (Vec<String>, Vec<Source>) {
+        split_args(&words(s), ARGS_SEPARATOR, ARG_FILES_SEPARATOR)
+    }
+
+    #[test]
+    fn split_args_works() {
+        let (program, sources) = split("echo -n ::: a b ::: x");
+        assert_eq!(program, words("echo -n"));
+        assert_eq!(
+            sources,
+            [Source::Args(words("a b")), Source::Args(words("x"))]
+        );
+        let (program, sources) = split("::: a");
+        assert!(program.is_empty());
+        assert_eq!(sources, [Source::Args(words("a"))]);
+        assert!(split("ls -l").1.is_empty());
+        let (program, sources) = split("cat :::: a.txt b.txt ::: x");
+        assert_eq!(program, words("cat"));
+        assert_eq!(
+            sources,
+            [
+                Source::File("a.txt".into()),
+                Source::File("b.txt".into()),
+                Source::Args(words("x"))
+            ]
+        );
+        // a command taking `:::` itself, with other separators
+        let (program, sources) = split_args(&words("grep ::: - -- a"), "-", "--");
+        assert_eq!(program, words("grep :::"));
+        assert_eq!(sources, [Source::Args(vec![]), Source::File("a".into())]);
+    }
+
+    #[test]
+    fn product_works() {
+        let sources = vec![
+            Source::Args(words("a b")),
+            Source::Args(words("1 2 3")),
+            Source::Args(words("x")),
+        ];
+        let combinations: Vec<String> = Product::open(sources)
+            .unwrap()
+            .map(|c| c.unwrap().join(""))
+            .collect();
+        assert_eq!(
+            combinations,
+            ["a1x", "a2x", "a3x", "b1x", "b2x", "b3x"].map(String::from)
+        );
+        let sources = vec![Source::Args(words("a b")), Source::Args(vec![])];
+        assert_eq!(Product::open(sources).unwrap().count(), 0);
+    }
+}
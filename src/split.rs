@@ -0,0 +1,308 @@
+use std::borrow::Cow;
+use std::io::BufRead;
+
+/// What input records are separated by
+#[derive(Clone, Debug)]
+pub enum Delims {
+    /// Any of these bytes
+    Bytes(Vec<u8>),
+    /// Any character Unicode considers whitespace, like the no-break or the ideographic space
+    UnicodeWs,
+}
+
+impl Delims {
+    /// Index right after the first delimiter in `buf`, `None` when there is none yet
+    ///
+    /// With `escapes` a backslash makes the character after it part of the record, delimiter or not.
+    fn record_end(&self, buf: &[u8], escapes: bool) -> Option<usize> {
+        if let Delims::Bytes(delims) = self {
+            let mut idx = 0;
+            while idx < buf.len() {
+                if escapes && buf[idx] == b'\\' {
+                    idx += 2;
+                    continue;
+                }
+                if delims.contains(&buf[idx]) {
+                    return Some(idx + 1);
+                }
+                idx += 1;
+            }
+            return None;
+        }
+        let (mut start, mut escaped) = (0, false);
+        loop {
+            // invalid sequences are skipped over, a sequence cut at the end of the buffer waits for more input
+            let (valid, skip) = match std::str::from_utf8(&buf[start..]) {
+                Ok(valid) => (valid, None),
+                Err(e) => {
+                    let valid = &buf[start..start + e.valid_up_to()];
+                    (std::str::from_utf8(valid).unwrap(), e.error_len())
+                }
+            };
+            for (idx, c) in valid.char_indices() {
+                if escaped {
+                    escaped = false;
+                } else if escapes && c == '\\' {
+                    escaped = true;
+                } else if c.is_whitespace() {
+                    return Some(start + idx + c.len_utf8());
+                }
+            }
+            start += valid.len() + skip?;
+            // an invalid sequence is what a backslash before it escaped
+            escaped = false;
+        }
+    }
+}
+
+/// Splits a reader on any of a set of delimiters
+pub trait ManySplit<B> {
+    fn split_any(self, delims: &Delims) -> SplitMany<B>;
+}
+
+pub struct SplitMany<B> {
+    buf: B,
+    delims: Delims,
+    next: Vec<u8>,
+    /// Longest record allowed, delimiter included
+    max_len: usize,
+    escapes: bool,
+}
+
+impl<B> SplitMany<B> {
+    /// Fails on records longer than `max_len` bytes instead of buffering them whole
+    pub fn max_len(mut self, max_len: usize) -> SplitMany<B> {
+        self.max_len = max_len;
+        self
+    }
+
+    /// Lets a backslash escape the character after it, delimiters included, like xargs does by default
+    pub fn escapes(mut self, escapes: bool) -> SplitMany<B> {
+        self.escapes = escapes;
+        self
+    }
+
+    /// Groups the records in blocks of up to `max_records` of them, which end once they reach `max_bytes`
+    ///
+    /// Records are never cut, so a block goes over `max_bytes` by up to one record.
+    pub fn blocks(self, max_records: usize, max_bytes: usize) -> Blocks<B> {
+        Blocks {
+            records: self,
+            max_records,
+            max_bytes,
+        }
+    }
+
+    fn too_long(&self) -> std::io::Error {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("record longer than {} bytes", self.max_len),
+        )
+    }
+}
+
+impl<B: BufRead> Iterator for SplitMany<B> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<std::io::Result<Vec<u8>>> {
+        loop {
+            if let Some(end) = self.delims.record_end(&self.next, self.escapes) {
+                if end > self.max_len {
+                    return Some(Err(self.too_long()));
+                }
+                // TODO: don't re-scan characters that we already checked
+                return Some(Ok(self.next.drain(0..end).collect()));
+            }
+            if self.next.len() > self.max_len {
+                return Some(Err(self.too_long()));
+            }
+            let amt = match self.buf.fill_buf() {
+                Ok(bytes) => {
+                    self.next.extend_from_slice(bytes);
+                    bytes.len()
+                }
+                Err(e) => return Some(Err(e)),
+            };
+            self.buf.consume(amt);
+            if amt == 0 {
+                return if self.next.is_empty() {
+                    None
+                } else {
+                    Some(Ok(self.next.drain(0..).collect()))
+                };
+            }
+        }
+    }
+}
+
+/// Input written to the stdin of the jobs with --pipe, in blocks of whole records
+pub struct Blocks<B> {
+    records: SplitMany<B>,
+    max_records: usize,
+    max_bytes: usize,
+}
+
+impl<B: BufRead> Iterator for Blocks<B> {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<std::io::Result<Vec<u8>>> {
+        let mut block = vec![];
+        for _ in 0..self.max_records {
+            match self.records.next() {
+                Some(Ok(record)) => block.extend_from_slice(&record),
+                Some(Err(e)) => return Some(Err(e)),
+                None => break,
+            }
+            if block.len() >= self.max_bytes {
+                break;
+            }
+        }
+        (!block.is_empty()).then_some(Ok(block))
+    }
+}
+
+impl<B: BufRead> ManySplit<B> for B {
+    fn split_any(self, delims: &Delims) -> SplitMany<B> {
+        SplitMany {
+            buf: self,
+            delims: delims.clone(),
+            next: vec![],
+            max_len: usize::MAX,
+            escapes: false,
+        }
+    }
+}
+
+/// Trims the delimiters around an argument, returning nothing when it is made only of delimiters
+pub fn clean_arg<'a>(delims: &Delims, arg: &'a [u8]) -> Option<&'a [u8]> {
+    let trimmed = match delims {
+        Delims::Bytes(delims) => {
+            let start = arg.iter().position(|x| !delims.contains(x))?;
+            let end = arg.iter().rposition(|x| !delims.contains(x))?;
+            &arg[start..=end]
+        }
+        Delims::UnicodeWs => match std::str::from_utf8(arg) {
+            Ok(arg) => arg.trim().as_bytes(),
+            Err(_) => arg.trim_ascii(),
+        },
+    };
+    (!trimmed.is_empty()).then_some(trimmed)
+}
+
+/// Cleans a record from the splitter: its delimiters are trimmed, or with `escapes` the one ending it is dropped
+/// and the backslash escapes are removed
+pub fn clean_record<'a>(delims: &Delims, escapes: bool, arg: &'a [u8]) -> Option<Cow<'a, [u8]>> {
+    if !escapes {
+        return clean_arg(delims, arg).map(Cow::Borrowed);
+    }
+    let content = match delims {
+        Delims::Bytes(delims) => match arg.split_last() {
+            Some((last, content)) if delims.contains(last) => content,
+            _ => arg,
+        },
+        Delims::UnicodeWs => match std::str::from_utf8(arg).ok().and_then(|s| s.chars().last()) {
+            Some(last) if last.is_whitespace() => &arg[..arg.len() - last.len_utf8()],
+            _ => arg,
+        },
+    };
+    // the delimiter only ends the record when it isn't escaped itself
+    let backslashes = content.iter().rev().take_while(|&&b| b == b'\\').count();
+    let content = if backslashes % 2 == 1 { arg } else { content };
+    if content.is_empty() {
+        return None;
+    }
+    let mut unescaped = Vec::with_capacity(content.len());
+    let mut bytes = content.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            // a backslash ending the input stays as it is
+            b'\\' => unescaped.push(*bytes.next().unwrap_or(&b'\\')),
+            _ => unescaped.push(b),
+        }
+    }
+    Some(Cow::Owned(unescaped))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{clean_arg, clean_record, Delims, ManySplit};
+    use std::io::BufReader;
+
+    #[test]
+    fn split_any_works() {
+        let delims = Delims::Bytes(b"\n ".to_vec());
+        let args: Vec<_> = b"a b\n\ncd"
+            .split_any(&delims)
+            .map(|arg| arg.unwrap())
+            .collect();
+        assert_eq!(args, [&b"a "[..], b"b\n", b"\n", b"cd"]);
+        let cleaned: Vec<_> = args.iter().filter_map(|a| clean_arg(&delims, a)).collect();
+        assert_eq!(cleaned, [&b"a"[..], b"b", b"cd"]);
+    }
+
+    #[test]
+    fn split_unicode_ws_works() {
+        let input = "a\u{a0}b\u{3000}\u{3000}é\n\u{2003}c".as_bytes();
+        // a tiny buffer cuts multi-byte characters in half
+        let args: Vec<_> = BufReader::with_capacity(1, input)
+            .split_any(&Delims::UnicodeWs)
+            .map(|arg| arg.unwrap())
+            .collect();
+        let cleaned: Vec<_> = args
+            .iter()
+            .filter_map(|a| clean_arg(&Delims::UnicodeWs, a))
+            .collect();
+        assert_eq!(cleaned, [&b"a"[..], b"b", "é".as_bytes(), b"c"]);
+        let invalid: Vec<_> = b"\xff\xfe x"
+            .split_any(&Delims::UnicodeWs)
+            .map(|arg| arg.unwrap())
+            .collect();
+        assert_eq!(invalid, [&b"\xff\xfe "[..], b"x"]);
+    }
+
+    fn split_escaped(delims: &Delims, input: &[u8]) -> Vec<Vec<u8>> {
+        input
+            .split_any(delims)
+            .escapes(true)
+            .filter_map(|arg| clean_record(delims, true, &arg.unwrap()).map(|a| a.into_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn escapes_work() {
+        let delims = Delims::Bytes(b" \n".to_vec());
+        let args = split_escaped(&delims, br"a\ b c\\ d\");
+        assert_eq!(args, [&b"a b"[..], br"c\", br"d\"]);
+        let args = split_escaped(&Delims::UnicodeWs, "x\\\u{3000}y\u{3000}z".as_bytes());
+        assert_eq!(args, ["x\u{3000}y".as_bytes(), b"z"]);
+    }
+
+    #[test]
+    fn blocks_work() {
+        let delims = Delims::Bytes(b"\n".to_vec());
+        let blocks: Vec<_> = b"a\nb\nc\nd\ne"
+            .split_any(&delims)
+            .blocks(2, usize::MAX)
+            .map(|block| block.unwrap())
+            .collect();
+        assert_eq!(blocks, [&b"a\nb\n"[..], b"c\nd\n", b"e"]);
+        let blocks: Vec<_> = b"abc\nd\nef\ng\n"
+            .split_any(&delims)
+            .blocks(usize::MAX, 3)
+            .map(|block| block.unwrap())
+            .collect();
+        assert_eq!(blocks, [&b"abc\n"[..], b"d\nef\n", b"g\n"]);
+    }
+
+    #[test]
+    fn max_len_works() {
+        let delims = Delims::Bytes(b"\n".to_vec());
+        let mut args = b"abc\nabcdef\n".split_any(&delims).max_len(4);
+        assert_eq!(args.next().unwrap().unwrap(), b"abc\n");
+        assert!(args.next().unwrap().is_err());
+        let mut args = BufReader::with_capacity(2, &b"abcdef"[..])
+            .split_any(&delims)
+            .max_len(4);
+        assert!(args.next().unwrap().is_err());
+    }
+}
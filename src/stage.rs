@@ -0,0 +1,78 @@
+use crate::output::command_line;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tracing::{info, info_span, warn};
+
+/// Second stage of a pipeline, running a shell command for every line printed by the jobs of the first one
+pub struct Stage {
+    records: SyncSender<String>,
+    workers: Vec<thread::JoinHandle<()>>,
+    spawned: Arc<AtomicUsize>,
+    failed: Arc<AtomicUsize>,
+}
+
+impl Stage {
+    pub fn new(template: String, parallelism: usize) -> Stage {
+        let (records, receiver) = mpsc::sync_channel::<String>(parallelism);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let template = Arc::new(template);
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let failed = Arc::new(AtomicUsize::new(0));
+        let workers = (0..parallelism)
+            .map(|_| {
+                let (receiver, template) = (receiver.clone(), template.clone());
+                let (spawned, failed) = (spawned.clone(), failed.clone());
+                thread::spawn(move || loop {
+                    let record = match receiver.lock().unwrap().recv() {
+                        Ok(record) => record,
+                        Err(_) => break,
+                    };
+                    let seq = spawned.fetch_add(1, Ordering::Relaxed);
+                    let line = command_line(&template, &[&record]);
+                    let _entered = info_span!("then", seq, command = %line).entered();
+                    let result = Command::new("sh")
+                        .args(["-c", &line])
+                        .stdin(Stdio::null())
+                        .status();
+                    match result {
+                        Ok(status) => info!(%status, "exited"),
+                        Err(ref e) => warn!("unable to spawn: {}", e),
+                    }
+                    if !result.is_ok_and(|s| s.success()) {
+                        failed.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+        Stage {
+            records,
+            workers,
+            spawned,
+            failed,
+        }
+    }
+
+    /// Handle for feeding records to the stage, blocking while all of its workers are busy
+    pub fn sender(&self) -> SyncSender<String> {
+        self.records.clone()
+    }
+
+    /// Waits for the stage to run out of records, returning how many of its jobs failed
+    ///
+    /// Every other sender must have been dropped already.
+    pub fn finish(self) -> usize {
+        drop(self.records);
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+        let (spawned, failed) = (
+            self.spawned.load(Ordering::Relaxed),
+            self.failed.load(Ordering::Relaxed),
+        );
+        info!(jobs = spawned, failed, "second stage finished");
+        failed
+    }
+}
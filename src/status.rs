@@ -0,0 +1,284 @@
+use serde::Serialize;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+
+/// Maps a status to the code a shell would report for it
+pub fn exit_code(status: ExitStatus) -> i32 {
+    status
+        .code()
+        .or_else(|| status.signal().map(|s| 128 + s))
+        .unwrap_or(1)
+}
+
+/// Highest exit code counting failed jobs, pll exits with it when even more failed
+const MAX_FAILED_EXIT_CODE: usize = 101;
+
+/// Exit code of pll once `failed` jobs didn't succeed, 0 when they all did
+pub fn failed_exit_code(failed: usize) -> i32 {
+    failed.min(MAX_FAILED_EXIT_CODE) as i32
+}
+
+/// Exit code of pll when every job that failed ran for longer than --timeout, the one of timeout(1)
+pub const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// Exit code pll ends a run with: 1 when some input was refused, the --then stage failed or no job won the --any
+/// race, `jobs_code` from `Failures::exit_code` otherwise
+pub fn run_exit_code(refused: bool, then_failed: usize, lost: bool, jobs_code: i32) -> i32 {
+    match refused || then_failed > 0 || lost {
+        true => 1,
+        false => jobs_code,
+    }
+}
+
+/// What happens to the rest of the run once a job fails, with --halt
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Halt {
+    /// Keep going
+    #[default]
+    Never,
+    /// Start no more jobs, waiting for the running ones
+    Soon,
+    /// Start no more jobs and kill the running ones
+    Now,
+}
+
+/// How a job ended
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    /// Exited with a nonzero code
+    Failed,
+    /// Killed by a signal
+    Signaled,
+    /// Its process couldn't be started at all
+    SpawnFailed,
+    /// Killed for running longer than --timeout
+    TimedOut,
+}
+
+impl Outcome {
+    pub fn of_status(status: ExitStatus) -> Outcome {
+        match (status.success(), status.signal()) {
+            (true, _) => Outcome::Success,
+            (false, Some(_)) => Outcome::Signaled,
+            (false, None) => Outcome::Failed,
+        }
+    }
+
+    /// For jobs only known by their exit code, like remote ones
+    pub fn of_code(exit_code: i32) -> Outcome {
+        if exit_code == 0 {
+            Outcome::Success
+        } else {
+            Outcome::Failed
+        }
+    }
+}
+
+/// Why pll killed a job itself, as opposed to the job exiting or being killed by something else
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KillCause {
+    /// Went over --kill-if-rss
+    Memory,
+    /// Asked for from `pll top`
+    Control,
+    /// Printed more than --kill-if-output
+    Output,
+    /// Still running when another job won the --any race
+    Lost,
+    /// Still running when another job failed with --halt now
+    Halt,
+    /// Ran for longer than --timeout
+    Timeout,
+}
+
+/// A job pll killed, and the signal it used
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Kill {
+    pub cause: KillCause,
+    pub signal: i32,
+}
+
+/// Count of the jobs that didn't succeed, by how they ended
+#[derive(Serialize, Default, Debug)]
+pub struct Failures {
+    /// Every job that didn't succeed, whatever the reason
+    pub failed: usize,
+    pub nonzero: usize,
+    pub signaled: usize,
+    pub spawn_failed: usize,
+    /// Jobs killed by pll itself, for any reason
+    pub killed: usize,
+    /// Jobs killed for going over --kill-if-rss, also counted as signaled and killed
+    pub memkilled: usize,
+    /// Jobs killed for going over --kill-if-output, also counted as killed
+    pub output_killed: usize,
+    /// Jobs killed once another one won the --any race, not counted as failed
+    pub lost: usize,
+    /// Jobs killed for running longer than --timeout, also counted as signaled and killed
+    pub timed_out: usize,
+}
+
+impl Failures {
+    pub fn add(&mut self, outcome: Outcome, kill: Option<Kill>) {
+        match outcome {
+            Outcome::Success => return,
+            _ if kill.is_some_and(|k| k.cause == KillCause::Lost) => {
+                self.lost += 1;
+                return;
+            }
+            Outcome::Failed => self.nonzero += 1,
+            Outcome::Signaled | Outcome::TimedOut => self.signaled += 1,
+            Outcome::SpawnFailed => self.spawn_failed += 1,
+        }
+        self.failed += 1;
+        if let Some(kill) = kill {
+            self.killed += 1;
+            match kill.cause {
+                KillCause::Memory => self.memkilled += 1,
+                KillCause::Output => self.output_killed += 1,
+                KillCause::Timeout => self.timed_out += 1,
+                KillCause::Control | KillCause::Lost | KillCause::Halt => {}
+            }
+        }
+    }
+
+    /// Exit code of pll for these jobs: 0 when they all succeeded, `TIMEOUT_EXIT_CODE` when the ones that didn't
+    /// all timed out, the count of failed jobs otherwise
+    pub fn exit_code(&self) -> i32 {
+        match self.failed {
+            0 => 0,
+            failed if failed == self.timed_out => TIMEOUT_EXIT_CODE,
+            failed => failed_exit_code(failed),
+        }
+    }
+}
+
+/// Whether a process with this pid exists, even if owned by another user
+pub fn pid_alive(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks whether the process exists
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Whether a child exited, without reaping it so its status can still be collected
+pub fn exited(pid: u32) -> io::Result<bool> {
+    let mut info = MaybeUninit::<libc::siginfo_t>::zeroed();
+    // SAFETY: info is valid for writes and waitid only inspects the child
+    let res = unsafe {
+        libc::waitid(
+            libc::P_PID,
+            pid as libc::id_t,
+            info.as_mut_ptr(),
+            libc::WEXITED | libc::WNOHANG | libc::WNOWAIT,
+        )
+    };
+    if res == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: zeroed above, waitid only fills it in when the child changed state
+    Ok(unsafe { info.assume_init().si_pid() } != 0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        failed_exit_code, run_exit_code, Failures, Kill, KillCause, Outcome, TIMEOUT_EXIT_CODE,
+    };
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    #[test]
+    fn outcome_works() {
+        assert_eq!(
+            Outcome::of_status(ExitStatus::from_raw(0)),
+            Outcome::Success
+        );
+        assert_eq!(
+            Outcome::of_status(ExitStatus::from_raw(3 << 8)),
+            Outcome::Failed
+        );
+        assert_eq!(
+            Outcome::of_status(ExitStatus::from_raw(9)),
+            Outcome::Signaled
+        );
+        let mut failures = Failures::default();
+        let memkill = Kill {
+            cause: KillCause::Memory,
+            signal: libc::SIGKILL,
+        };
+        failures.add(Outcome::Success, None);
+        failures.add(Outcome::Signaled, Some(memkill));
+        failures.add(Outcome::SpawnFailed, None);
+        failures.add(
+            Outcome::Failed,
+            Some(Kill {
+                cause: KillCause::Output,
+                signal: libc::SIGKILL,
+            }),
+        );
+        failures.add(
+            Outcome::Signaled,
+            Some(Kill {
+                cause: KillCause::Lost,
+                signal: libc::SIGKILL,
+            }),
+        );
+        failures.add(
+            Outcome::Signaled,
+            Some(Kill {
+                cause: KillCause::Timeout,
+                signal: libc::SIGKILL,
+            }),
+        );
+        assert_eq!(failures.lost, 1);
+        assert_eq!(
+            (
+                failures.failed,
+                failures.signaled,
+                failures.spawn_failed,
+                failures.killed,
+                failures.memkilled,
+                failures.output_killed,
+                failures.timed_out
+            ),
+            (4, 2, 1, 3, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn failed_exit_code_works() {
+        assert_eq!(failed_exit_code(0), 0);
+        assert_eq!(failed_exit_code(7), 7);
+        assert_eq!(failed_exit_code(1000), 101);
+    }
+
+    #[test]
+    fn timeouts_have_their_exit_code() {
+        let timeout = Kill {
+            cause: KillCause::Timeout,
+            signal: libc::SIGKILL,
+        };
+        let mut failures = Failures::default();
+        assert_eq!(failures.exit_code(), 0);
+        failures.add(Outcome::TimedOut, Some(timeout));
+        failures.add(Outcome::TimedOut, Some(timeout));
+        assert_eq!(failures.exit_code(), TIMEOUT_EXIT_CODE);
+        failures.add(Outcome::Failed, None);
+        assert_eq!(failures.exit_code(), 3);
+    }
+
+    #[test]
+    fn run_exit_code_works() {
+        assert_eq!(run_exit_code(false, 0, false, 0), 0);
+        assert_eq!(run_exit_code(false, 0, false, 5), 5);
+        assert_eq!(run_exit_code(false, 0, false, TIMEOUT_EXIT_CODE), 124);
+        assert_eq!(run_exit_code(true, 0, false, 5), 1);
+        assert_eq!(run_exit_code(false, 2, false, 0), 1);
+        assert_eq!(run_exit_code(false, 0, true, 0), 1);
+    }
+}
@@ -0,0 +1,202 @@
+use crate::{pool, status};
+use std::collections::HashSet;
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{io, process, thread};
+
+/// Signal that stopped the run while the input left is written out with --remaining, 0 until then
+static STOPPING: AtomicI32 = AtomicI32::new(0);
+
+/// Signals that make a run stop its jobs and exit
+const STOP_SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+const SIGNAL_NAMES: [(&str, libc::c_int); 8] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+];
+
+/// Parses a signal given by name, with or without the SIG prefix, or by number
+pub fn parse_signal(s: &str) -> Result<libc::c_int, String> {
+    if let Ok(n) = s.parse::<libc::c_int>() {
+        return if n > 0 && n < 65 {
+            Ok(n)
+        } else {
+            Err(format!("invalid signal number {}", n))
+        };
+    }
+    let name = s.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNAL_NAMES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|&(_, sig)| sig)
+        .ok_or_else(|| format!("unknown signal '{}'", s))
+}
+
+/// Parses a duration like `500ms`, `10s`, `1.5m` or `2h`, plain numbers being seconds
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", s))?;
+    let secs = match unit {
+        "ms" => value / 1000.0,
+        "" | "s" => value,
+        "m" => value * 60.0,
+        "h" => value * 3600.0,
+        _ => return Err(format!("unknown duration unit '{}'", unit)),
+    };
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid duration '{}'", s))
+}
+
+fn signal_set() -> libc::sigset_t {
+    let mut set = MaybeUninit::<libc::sigset_t>::zeroed();
+    // SAFETY: the set is initialized by sigemptyset before being added to
+    unsafe {
+        libc::sigemptyset(set.as_mut_ptr());
+        for sig in STOP_SIGNALS {
+            libc::sigaddset(set.as_mut_ptr(), sig);
+        }
+        set.assume_init()
+    }
+}
+
+fn exited(pid: u32) -> bool {
+    status::exited(pid).unwrap_or(true)
+}
+
+/// Sends `signal` to a job and everything it started, which share the process group the job leads
+///
+/// Falls back to the job alone when it doesn't lead a group, like with --open-tty. The pid must be of a child that
+/// wasn't reaped yet, so neither it nor its group id can have been reused.
+pub fn kill_tree(pid: u32, signal: libc::c_int) {
+    // SAFETY: plain kill(2) calls
+    unsafe {
+        if libc::kill(-(pid as libc::pid_t), signal) != 0 {
+            libc::kill(pid as libc::pid_t, signal);
+        }
+    }
+}
+
+/// Sends `signal` to every running job, then SIGKILL once `grace` elapsed
+///
+/// SIGKILL goes to the groups of the jobs that exited too, since what they started may still be running. The lock on
+/// the pids must be held throughout, the pool only reaps jobs with it so their pids can't be reused meanwhile.
+pub fn stop(pids: &HashSet<u32>, signal: libc::c_int, grace: Duration) {
+    for &pid in pids {
+        kill_tree(pid, signal);
+    }
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline && !pids.iter().all(|&pid| exited(pid)) {
+        thread::sleep(Duration::from_millis(20));
+    }
+    for &pid in pids {
+        kill_tree(pid, libc::SIGKILL);
+    }
+}
+
+/// Unblocks every signal, for a child about to run its program, which otherwise keeps the mask of pll blocking the
+/// stop signals and SIGCHLD
+///
+/// Only makes async-signal-safe calls, so it can run between fork and exec.
+pub fn unblock_signals() -> io::Result<()> {
+    let mut set = MaybeUninit::<libc::sigset_t>::zeroed();
+    // SAFETY: the set is initialized by sigemptyset before being used
+    let res = unsafe {
+        libc::sigemptyset(set.as_mut_ptr());
+        libc::pthread_sigmask(libc::SIG_SETMASK, set.as_ptr(), std::ptr::null_mut())
+    };
+    match res {
+        0 => Ok(()),
+        _ => Err(io::Error::from_raw_os_error(res)),
+    }
+}
+
+/// The signal that stopped the run, once pll is only left to write out the input it didn't use
+pub fn stopping() -> Option<libc::c_int> {
+    Some(STOPPING.load(Ordering::Relaxed)).filter(|&signal| signal != 0)
+}
+
+/// Takes over SIGINT, SIGTERM and SIGHUP: on any of them the running jobs are stopped, `teardown` is run and the
+/// process exits
+///
+/// Must be called before any other thread is started so every thread inherits the blocked signals, leaving the
+/// watcher the only one receiving them. Local jobs get a clean signal mask through [`unblock_signals`].
+///
+/// With `drain` pll doesn't exit right away, the pool stops starting jobs instead so the input it didn't use can be
+/// written out, see [`stopping`]. A second signal still exits right away.
+pub fn watch(
+    running: Arc<Mutex<HashSet<u32>>>,
+    signal: libc::c_int,
+    grace: Duration,
+    teardown: Option<String>,
+    drain: bool,
+) -> io::Result<()> {
+    let set = signal_set();
+    // SAFETY: set is a valid, initialized signal set
+    let res = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, std::ptr::null_mut()) };
+    if res != 0 {
+        return Err(io::Error::from_raw_os_error(res));
+    }
+    thread::spawn(move || {
+        let mut received = 0;
+        // SAFETY: both pointers are valid for the duration of the call
+        while unsafe { libc::sigwait(&set, &mut received) } != 0 {}
+        eprintln!("stopping running jobs");
+        if drain {
+            // set before stopping the jobs so the pool knows they were interrupted as it reaps them
+            STOPPING.store(received, Ordering::Relaxed);
+        }
+        // the lock is kept so no job starts, or is reaped, while the others are stopped
+        let running = running.lock().unwrap();
+        stop(&running, signal, grace);
+        if let Some(teardown) = teardown {
+            pool::run_once("--teardown", &teardown);
+        }
+        if !drain {
+            process::exit(128 + received);
+        }
+        drop(running);
+        // SAFETY: as above
+        while unsafe { libc::sigwait(&set, &mut received) } != 0 {}
+        process::exit(128 + received);
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_duration, parse_signal};
+    use std::time::Duration;
+
+    #[test]
+    fn parse_signal_works() {
+        assert_eq!(parse_signal("TERM"), Ok(libc::SIGTERM));
+        assert_eq!(parse_signal("sigint"), Ok(libc::SIGINT));
+        assert_eq!(parse_signal("9"), Ok(9));
+        assert!(parse_signal("0").is_err());
+        assert!(parse_signal("NOPE").is_err());
+    }
+
+    #[test]
+    fn parse_duration_works() {
+        assert_eq!(parse_duration("10"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1.5m"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert!(parse_duration("5d").is_err());
+        assert!(parse_duration("s").is_err());
+    }
+}
@@ -0,0 +1,468 @@
+use crate::status::pid_alive;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub struct QueuedJob {
+    pub id: usize,
+    pub argv: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running,
+    Done,
+    Cancelled,
+}
+
+impl JobState {
+    fn parse(s: &str) -> JobState {
+        match s {
+            "queued" => JobState::Queued,
+            "running" => JobState::Running,
+            "cancelled" => JobState::Cancelled,
+            _ => JobState::Done,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JobInfo {
+    pub id: usize,
+    pub argv: Vec<String>,
+    pub state: JobState,
+    pub attempts: usize,
+    /// Only set once the job reached its final state
+    pub exit_code: Option<i32>,
+}
+
+/// Exit code recorded for queued jobs cancelled before running, the same one a job terminated by SIGTERM reports
+const CANCELLED_EXIT_CODE: i32 = 128 + libc::SIGTERM;
+
+/// Storage for the jobs known to the daemon
+pub trait JobStore: Send {
+    fn push(&mut self, argv: Vec<String>) -> io::Result<usize>;
+    /// Marks the oldest queued job as running and returns it
+    fn claim(&mut self) -> io::Result<Option<QueuedJob>>;
+    fn finish(&mut self, id: usize, exit_code: i32, output: &str) -> io::Result<()>;
+    /// Cancels a job that hasn't finished yet, returning the state it was in
+    ///
+    /// Running jobs are only flagged, killing the process is up to the caller.
+    fn cancel(&mut self, id: usize) -> io::Result<JobState>;
+    fn get(&mut self, id: usize) -> io::Result<JobInfo>;
+    fn list(&mut self) -> io::Result<Vec<JobInfo>>;
+    /// Captured output of a finished job
+    fn output(&mut self, id: usize) -> io::Result<Option<String>>;
+    /// Returns how many jobs are not finished yet and how many finished with a failure
+    fn counts(&mut self) -> io::Result<(usize, usize)>;
+    /// Extends the lease on running jobs, stores that aren't shared between processes have nothing to do
+    fn renew(&mut self, _ids: &[usize]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn unknown_job(id: usize) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("unknown job {}", id))
+}
+
+/// Keeps the queue in memory, it is lost when the daemon exits
+#[derive(Default)]
+pub struct MemoryStore {
+    jobs: Vec<(JobInfo, Option<String>)>,
+    pending: VecDeque<usize>,
+}
+
+impl MemoryStore {
+    fn job(&mut self, id: usize) -> io::Result<&mut (JobInfo, Option<String>)> {
+        self.jobs.get_mut(id).ok_or_else(|| unknown_job(id))
+    }
+}
+
+impl JobStore for MemoryStore {
+    fn push(&mut self, argv: Vec<String>) -> io::Result<usize> {
+        let id = self.jobs.len();
+        let info = JobInfo {
+            id,
+            argv,
+            state: JobState::Queued,
+            attempts: 0,
+            exit_code: None,
+        };
+        self.jobs.push((info, None));
+        self.pending.push_back(id);
+        Ok(id)
+    }
+
+    fn claim(&mut self) -> io::Result<Option<QueuedJob>> {
+        while let Some(id) = self.pending.pop_front() {
+            let (info, _) = self.job(id)?;
+            if info.state == JobState::Queued {
+                info.state = JobState::Running;
+                info.attempts += 1;
+                let argv = info.argv.clone();
+                return Ok(Some(QueuedJob { id, argv }));
+            }
+        }
+        Ok(None)
+    }
+
+    fn finish(&mut self, id: usize, exit_code: i32, output: &str) -> io::Result<()> {
+        let (info, captured) = self.job(id)?;
+        if info.state != JobState::Cancelled {
+            info.state = JobState::Done;
+        }
+        info.exit_code = Some(exit_code);
+        *captured = Some(output.to_owned());
+        Ok(())
+    }
+
+    fn cancel(&mut self, id: usize) -> io::Result<JobState> {
+        let (info, _) = self.job(id)?;
+        let previous = info.state;
+        match previous {
+            JobState::Queued => {
+                info.state = JobState::Cancelled;
+                info.exit_code = Some(CANCELLED_EXIT_CODE);
+            }
+            JobState::Running => info.state = JobState::Cancelled,
+            JobState::Done | JobState::Cancelled => {}
+        }
+        Ok(previous)
+    }
+
+    fn get(&mut self, id: usize) -> io::Result<JobInfo> {
+        Ok(self.job(id)?.0.clone())
+    }
+
+    fn list(&mut self) -> io::Result<Vec<JobInfo>> {
+        Ok(self.jobs.iter().map(|(info, _)| info.clone()).collect())
+    }
+
+    fn output(&mut self, id: usize) -> io::Result<Option<String>> {
+        Ok(self.job(id)?.1.clone())
+    }
+
+    fn counts(&mut self) -> io::Result<(usize, usize)> {
+        let unfinished = self.jobs.iter().filter(|(i, _)| i.exit_code.is_none());
+        let failed = self
+            .jobs
+            .iter()
+            .filter(|(i, _)| i.state == JobState::Done && i.exit_code != Some(0));
+        Ok((unfinished.count(), failed.count()))
+    }
+}
+
+/// How long a claimed job stays assigned to a daemon without being renewed before others may steal it
+pub const LEASE: Duration = Duration::from_secs(30);
+
+/// Persists the queue in a SQLite database that can be shared by several daemons
+///
+/// Claimed jobs are leased, a daemon that stops renewing them (crashed, lost its host or its mount) has them
+/// picked up again by whichever daemon claims next.
+pub struct SqliteStore {
+    conn: Connection,
+    owner: String,
+}
+
+fn db_err(e: rusqlite::Error) -> io::Error {
+    io::Error::other(e)
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+pub fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for its whole length and gethostname null terminates on success
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return "localhost".into();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> io::Result<SqliteStore> {
+        let conn = Connection::open(path).map_err(db_err)?;
+        conn.busy_timeout(Duration::from_secs(30)).map_err(db_err)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                argv TEXT NOT NULL,
+                state TEXT NOT NULL DEFAULT 'queued',
+                attempts INTEGER NOT NULL DEFAULT 0,
+                owner TEXT,
+                exit_code INTEGER,
+                output TEXT,
+                submitted_at INTEGER NOT NULL,
+                started_at INTEGER,
+                finished_at INTEGER,
+                lease_until INTEGER
+            );
+            CREATE INDEX IF NOT EXISTS jobs_state ON jobs (state, id);",
+        )
+        .map_err(db_err)?;
+        let has_lease: bool = conn
+            .query_row(
+                "SELECT COUNT(*) FROM pragma_table_info('jobs') WHERE name = 'lease_until'",
+                [],
+                |r| r.get(0),
+            )
+            .map_err(db_err)?;
+        if !has_lease {
+            conn.execute("ALTER TABLE jobs ADD COLUMN lease_until INTEGER", [])
+                .map_err(db_err)?;
+        }
+        let host = hostname();
+        let store = SqliteStore {
+            owner: format!("{}:{}", host, std::process::id()),
+            conn,
+        };
+        store.requeue_orphans(&host)?;
+        Ok(store)
+    }
+
+    /// Puts back in the queue the jobs left running by daemons of this host that are gone
+    fn requeue_orphans(&self, host: &str) -> io::Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, owner FROM jobs WHERE state = 'running'")
+            .map_err(db_err)?;
+        let running = stmt
+            .query_map([], |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)))
+            .map_err(db_err)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_err)?;
+        for (id, owner) in running {
+            let orphaned = match owner.rsplit_once(':') {
+                Some((h, pid)) if h == host => pid.parse().map_or(true, |pid| !pid_alive(pid)),
+                _ => false,
+            };
+            if orphaned {
+                self.conn
+                    .execute(
+                        "UPDATE jobs SET state = 'queued', owner = NULL WHERE id = ?1 AND state = 'running'",
+                        params![id],
+                    )
+                    .map_err(db_err)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl JobStore for SqliteStore {
+    fn push(&mut self, argv: Vec<String>) -> io::Result<usize> {
+        self.conn
+            .execute(
+                "INSERT INTO jobs (argv, submitted_at) VALUES (?1, ?2)",
+                params![serde_json::to_string(&argv)?, now()],
+            )
+            .map_err(db_err)?;
+        Ok(self.conn.last_insert_rowid() as usize)
+    }
+
+    fn claim(&mut self) -> io::Result<Option<QueuedJob>> {
+        let claimed = self
+            .conn
+            .query_row(
+                "UPDATE jobs SET state = 'running', attempts = attempts + 1, owner = ?1, started_at = ?2,
+                    lease_until = ?2 + ?3
+                 WHERE id = (SELECT id FROM jobs
+                             WHERE state = 'queued' OR (state = 'running' AND lease_until < ?2)
+                             ORDER BY id LIMIT 1)
+                 RETURNING id, argv",
+                params![self.owner, now(), LEASE.as_secs() as i64],
+                |r| Ok((r.get::<_, i64>(0)?, r.get::<_, String>(1)?)),
+            )
+            .optional()
+            .map_err(db_err)?;
+        match claimed {
+            Some((id, argv)) => Ok(Some(QueuedJob {
+                id: id as usize,
+                argv: serde_json::from_str(&argv)?,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    fn finish(&mut self, id: usize, exit_code: i32, output: &str) -> io::Result<()> {
+        self.conn
+            .execute(
+                "UPDATE jobs SET state = CASE state WHEN 'cancelled' THEN 'cancelled' ELSE 'done' END,
+                    exit_code = ?2, output = ?3, finished_at = ?4
+                 WHERE id = ?1",
+                params![id as i64, exit_code, output, now()],
+            )
+            .map_err(db_err)?;
+        Ok(())
+    }
+
+    fn cancel(&mut self, id: usize) -> io::Result<JobState> {
+        let previous = self.get(id)?.state;
+        self.conn
+            .execute(
+                "UPDATE jobs SET state = 'cancelled',
+                    exit_code = CASE state WHEN 'queued' THEN ?2 ELSE exit_code END
+                 WHERE id = ?1 AND state IN ('queued', 'running')",
+                params![id as i64, CANCELLED_EXIT_CODE],
+            )
+            .map_err(db_err)?;
+        Ok(previous)
+    }
+
+    fn get(&mut self, id: usize) -> io::Result<JobInfo> {
+        self.conn
+            .query_row(
+                &format!("SELECT {} FROM jobs WHERE id = ?1", JOB_INFO_COLUMNS),
+                params![id as i64],
+                job_info,
+            )
+            .optional()
+            .map_err(db_err)?
+            .ok_or_else(|| unknown_job(id))?
+    }
+
+    fn list(&mut self) -> io::Result<Vec<JobInfo>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!(
+                "SELECT {} FROM jobs ORDER BY id",
+                JOB_INFO_COLUMNS
+            ))
+            .map_err(db_err)?;
+        let rows = stmt
+            .query_map([], job_info)
+            .map_err(db_err)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(db_err)?;
+        rows.into_iter().collect()
+    }
+
+    fn output(&mut self, id: usize) -> io::Result<Option<String>> {
+        self.conn
+            .query_row(
+                "SELECT output FROM jobs WHERE id = ?1",
+                params![id as i64],
+                |r| r.get(0),
+            )
+            .optional()
+            .map_err(db_err)?
+            .ok_or_else(|| unknown_job(id))
+    }
+
+    fn counts(&mut self) -> io::Result<(usize, usize)> {
+        self.conn
+            .query_row(
+                "SELECT COUNT(*) FILTER (WHERE exit_code IS NULL),
+                        COUNT(*) FILTER (WHERE state = 'done' AND exit_code != 0)
+                 FROM jobs",
+                [],
+                |r| Ok((r.get::<_, i64>(0)? as usize, r.get::<_, i64>(1)? as usize)),
+            )
+            .map_err(db_err)
+    }
+
+    fn renew(&mut self, ids: &[usize]) -> io::Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(
+                "UPDATE jobs SET lease_until = ?3 WHERE id = ?1 AND owner = ?2 AND state = 'running'",
+            )
+            .map_err(db_err)?;
+        let until = now() + LEASE.as_secs() as i64;
+        for &id in ids {
+            stmt.execute(params![id as i64, self.owner, until])
+                .map_err(db_err)?;
+        }
+        Ok(())
+    }
+}
+
+const JOB_INFO_COLUMNS: &str = "id, argv, state, attempts, exit_code";
+
+/// Maps a row selected with `JOB_INFO_COLUMNS`, the outer error covers the database and the inner one the argv
+/// decoding
+fn job_info(r: &Row) -> rusqlite::Result<io::Result<JobInfo>> {
+    let argv: String = r.get(1)?;
+    let state: String = r.get(2)?;
+    let (id, attempts, exit_code) = (r.get::<_, i64>(0)?, r.get::<_, i64>(3)?, r.get(4)?);
+    Ok(serde_json::from_str(&argv)
+        .map(|argv| JobInfo {
+            id: id as usize,
+            argv,
+            state: JobState::parse(&state),
+            attempts: attempts as usize,
+            exit_code,
+        })
+        .map_err(io::Error::from))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{JobState, JobStore, MemoryStore, SqliteStore};
+    use std::path::Path;
+
+    fn exercise(store: &mut dyn JobStore) {
+        let first = store.push(vec!["echo".into(), "a".into()]).unwrap();
+        let second = store.push(vec!["false".into()]).unwrap();
+        assert_eq!(store.counts().unwrap(), (2, 0));
+
+        let job = store.claim().unwrap().unwrap();
+        assert_eq!((job.id, job.argv), (first, vec!["echo".into(), "a".into()]));
+        store.finish(first, 0, "a\n").unwrap();
+        assert_eq!(store.get(first).unwrap().exit_code, Some(0));
+        assert_eq!(store.output(first).unwrap().as_deref(), Some("a\n"));
+        assert_eq!(store.get(second).unwrap().exit_code, None);
+
+        assert_eq!(store.claim().unwrap().unwrap().id, second);
+        assert!(store.claim().unwrap().is_none());
+        store.finish(second, 1, "").unwrap();
+        assert_eq!(store.counts().unwrap(), (0, 1));
+        assert!(store.get(second + 1).is_err());
+
+        let third = store.push(vec!["true".into()]).unwrap();
+        assert_eq!(store.cancel(third).unwrap(), JobState::Queued);
+        assert!(store.claim().unwrap().is_none());
+        let states: Vec<_> = store.list().unwrap().iter().map(|j| j.state).collect();
+        assert_eq!(
+            states,
+            [JobState::Done, JobState::Done, JobState::Cancelled]
+        );
+        assert_eq!(store.counts().unwrap(), (0, 1));
+    }
+
+    #[test]
+    fn memory_store_works() {
+        exercise(&mut MemoryStore::default());
+    }
+
+    #[test]
+    fn sqlite_store_works() {
+        exercise(&mut SqliteStore::open(Path::new(":memory:")).unwrap());
+    }
+
+    #[test]
+    fn expired_leases_are_stolen() {
+        let mut store = SqliteStore::open(Path::new(":memory:")).unwrap();
+        let id = store.push(vec!["sleep".into(), "60".into()]).unwrap();
+        assert_eq!(store.claim().unwrap().unwrap().id, id);
+        store.renew(&[id]).unwrap();
+        assert!(store.claim().unwrap().is_none());
+
+        store
+            .conn
+            .execute("UPDATE jobs SET lease_until = 0", [])
+            .unwrap();
+        assert_eq!(store.claim().unwrap().unwrap().id, id);
+        assert_eq!(store.get(id).unwrap().attempts, 2);
+    }
+}
@@ -0,0 +1,105 @@
+use crate::status::Outcome;
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+/// Start and end of every job, written with --trace-out in the Chrome trace event format
+///
+/// Each slot is a thread of its own, so chrome://tracing or Perfetto show the run as a Gantt chart.
+pub struct Trace {
+    path: PathBuf,
+    out: Option<BufWriter<File>>,
+    started_at: Instant,
+    events: Vec<Value>,
+    slots: BTreeSet<usize>,
+}
+
+impl Trace {
+    /// Creates the file up front, so a bad path is reported before any job starts
+    pub fn create(path: PathBuf) -> io::Result<Trace> {
+        let out = BufWriter::new(File::create(&path)?);
+        Ok(Trace {
+            path,
+            out: Some(out),
+            started_at: Instant::now(),
+            events: vec![],
+            slots: BTreeSet::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn job_finished(
+        &mut self,
+        seq: usize,
+        slot: usize,
+        command: &[String],
+        started_at: Instant,
+        exit_code: i32,
+        outcome: Outcome,
+    ) {
+        let ts = started_at.saturating_duration_since(self.started_at);
+        self.slots.insert(slot);
+        self.events.push(json!({
+            "name": command.join(" "),
+            "cat": "job",
+            "ph": "X",
+            "ts": ts.as_micros() as u64,
+            "dur": started_at.elapsed().as_micros() as u64,
+            "pid": 1,
+            "tid": slot,
+            "args": { "seq": seq, "exit_code": exit_code, "outcome": outcome },
+        }));
+    }
+
+    /// Writes the events, along with a name for each slot
+    pub fn finish(&mut self) -> io::Result<()> {
+        let Some(mut out) = self.out.take() else {
+            return Ok(());
+        };
+        let names = self.slots.iter().map(|slot| {
+            json!({
+                "name": "thread_name",
+                "ph": "M",
+                "pid": 1,
+                "tid": slot,
+                "args": { "name": format!("slot {}", slot) },
+            })
+        });
+        let events: Vec<Value> = names.chain(self.events.drain(..)).collect();
+        serde_json::to_writer(
+            &mut out,
+            &json!({ "traceEvents": events, "displayTimeUnit": "ms" }),
+        )?;
+        out.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Trace;
+    use crate::status::Outcome;
+    use std::fs;
+    use std::time::Instant;
+
+    #[test]
+    fn trace_works() {
+        let path = std::env::temp_dir().join(format!("pll-trace-{}.json", std::process::id()));
+        let mut trace = Trace::create(path.clone()).unwrap();
+        let command = ["sleep".to_owned(), "1".to_owned()];
+        trace.job_finished(0, 2, &command, Instant::now(), 0, Outcome::Success);
+        trace.finish().unwrap();
+        let written: serde_json::Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let events = written["traceEvents"].as_array().unwrap();
+        assert_eq!(events[0]["args"]["name"], "slot 2");
+        assert_eq!(events[1]["name"], "sleep 1");
+        assert_eq!(events[1]["tid"], 2);
+        assert_eq!(events[1]["args"]["outcome"], "success");
+    }
+}
@@ -0,0 +1,224 @@
+use rustls::client::WebPkiServerVerifier;
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConfig, ClientConnection, RootCertStore, ServerConfig, ServerConnection};
+use rustls_pki_types::pem::PemObject;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tracing::debug;
+
+/// Byte stream between a controller and an agent
+///
+/// TLS connections are driven by a pump thread so the rest of the code can keep using cheap clones of a
+/// blocking stream for reading and writing from different threads.
+pub enum Channel {
+    Tcp(TcpStream),
+    Tls(UnixStream),
+}
+
+impl Channel {
+    pub fn try_clone(&self) -> io::Result<Channel> {
+        match self {
+            Channel::Tcp(s) => s.try_clone().map(Channel::Tcp),
+            Channel::Tls(s) => s.try_clone().map(Channel::Tls),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Channel::Tcp(s) => s.set_read_timeout(timeout),
+            Channel::Tls(s) => s.set_read_timeout(timeout),
+        }
+    }
+}
+
+impl Read for Channel {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Channel::Tcp(s) => s.read(buf),
+            Channel::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Channel {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Channel::Tcp(s) => s.write(buf),
+            Channel::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Channel::Tcp(s) => s.flush(),
+            Channel::Tls(s) => s.flush(),
+        }
+    }
+}
+
+fn tls_err(e: impl std::error::Error + Send + Sync + 'static) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn provider() -> Arc<CryptoProvider> {
+    Arc::new(ring::default_provider())
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(tls_err)
+}
+
+fn load_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(tls_err)
+}
+
+fn load_roots(path: &Path) -> io::Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots.add(cert).map_err(tls_err)?;
+    }
+    Ok(Arc::new(roots))
+}
+
+/// Server side configuration, client certificates signed by `client_ca` are required when it is given
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> io::Result<Arc<ServerConfig>> {
+    let builder = ServerConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_err)?;
+    let builder = match client_ca {
+        Some(ca) => builder.with_client_cert_verifier(
+            WebPkiClientVerifier::builder_with_provider(load_roots(ca)?, provider())
+                .build()
+                .map_err(tls_err)?,
+        ),
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(load_certs(cert)?, load_key(key)?)
+        .map_err(tls_err)?;
+    Ok(Arc::new(config))
+}
+
+/// Client side configuration trusting agents signed by `ca`, presenting `identity` when the agent asks for it
+pub fn client_config(ca: &Path, identity: Option<(&Path, &Path)>) -> io::Result<Arc<ClientConfig>> {
+    let verifier = WebPkiServerVerifier::builder_with_provider(load_roots(ca)?, provider())
+        .build()
+        .map_err(tls_err)?;
+    let builder = ClientConfig::builder_with_provider(provider())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_err)?
+        .with_webpki_verifier(verifier);
+    let config = match identity {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+            .map_err(tls_err)?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+/// Shuttles bytes between the TLS connection and the local end of the channel until either side closes
+fn pump(mut tls: rustls::Connection, mut tcp: TcpStream, mut local: UnixStream) -> io::Result<()> {
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        while tls.wants_write() {
+            tls.write_tls(&mut tcp)?;
+        }
+        let mut fds = [
+            libc::pollfd {
+                fd: tcp.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: local.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        // SAFETY: fds is a valid array of pollfd for its whole length
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+        if fds[0].revents != 0 {
+            if tls.read_tls(&mut tcp)? == 0 {
+                return Ok(());
+            }
+            tls.process_new_packets().map_err(tls_err)?;
+            loop {
+                match tls.reader().read(&mut buf) {
+                    Ok(0) => return Ok(()),
+                    Ok(n) => local.write_all(&buf[..n])?,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        if fds[1].revents != 0 {
+            let n = local.read(&mut buf)?;
+            if n == 0 {
+                tls.send_close_notify();
+                while tls.wants_write() {
+                    tls.write_tls(&mut tcp)?;
+                }
+                return Ok(());
+            }
+            tls.writer().write_all(&buf[..n])?;
+        }
+    }
+}
+
+/// How long a peer may stay silent while the connection is being set up
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Completes the handshake and starts pumping the connection in the background
+fn start(mut tls: rustls::Connection, mut tcp: TcpStream) -> io::Result<Channel> {
+    tcp.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    while tls.is_handshaking() {
+        tls.complete_io(&mut tcp)?;
+    }
+    tcp.set_read_timeout(None)?;
+    let (local, remote) = UnixStream::pair()?;
+    thread::spawn(move || {
+        if let Err(e) = pump(tls, tcp, remote) {
+            debug!("tls connection closed: {}", e);
+        }
+    });
+    Ok(Channel::Tls(local))
+}
+
+pub fn accept(config: &Arc<ServerConfig>, tcp: TcpStream) -> io::Result<Channel> {
+    let conn = ServerConnection::new(config.clone()).map_err(tls_err)?;
+    start(conn.into(), tcp)
+}
+
+/// Host part of a `HOST:PORT` address, without the brackets around IPv6 addresses
+pub fn host(addr: &str) -> &str {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Connects to `addr` (`HOST:PORT`), the host part being the name the agent certificate must match
+pub fn connect(config: &Arc<ClientConfig>, addr: &str) -> io::Result<Channel> {
+    let name = ServerName::try_from(host(addr).to_owned()).map_err(tls_err)?;
+    let conn = ClientConnection::new(config.clone(), name).map_err(tls_err)?;
+    start(conn.into(), TcpStream::connect(addr)?)
+}
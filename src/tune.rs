@@ -0,0 +1,140 @@
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long throughput is measured for before --auto-tune changes the parallelism
+const WINDOW: Duration = Duration::from_secs(5);
+
+/// Load average per CPU above which --auto-tune only ever lowers the parallelism
+const MAX_LOAD_PER_CPU: f64 = 1.5;
+
+/// Jobs started per CPU with --io-bound
+pub const IO_BOUND_JOBS_PER_CPU: usize = 8;
+
+/// Hill climbing on the number of jobs finished per second, for --auto-tune
+///
+/// The parallelism keeps moving one step in the same direction while throughput improves and turns around once
+/// it gets worse, so it ends up going back and forth around the best value.
+pub struct Tuner {
+    limit: usize,
+    max: usize,
+    /// The load average is ignored, it counts processes waiting on disk
+    io_bound: bool,
+    /// Direction of the next change
+    up: bool,
+    window_start: Instant,
+    finished: usize,
+    /// Samples of the window, and how many of them had every slot taken
+    ticks: usize,
+    busy_ticks: usize,
+    last_throughput: Option<f64>,
+}
+
+fn cpus() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// One minute load average per CPU
+fn load_per_cpu() -> Option<f64> {
+    let loadavg = fs::read_to_string("/proc/loadavg").ok()?;
+    let load: f64 = loadavg.split_whitespace().next()?.parse().ok()?;
+    Some(load / cpus() as f64)
+}
+
+impl Tuner {
+    /// Starts from as many jobs as there are CPUs, or a multiple with `io_bound`, never going over `max`
+    pub fn new(max: usize, io_bound: bool) -> Tuner {
+        let start = match io_bound {
+            true => cpus() * IO_BOUND_JOBS_PER_CPU,
+            false => cpus(),
+        };
+        Tuner {
+            limit: start.min(max),
+            max,
+            io_bound,
+            up: true,
+            window_start: Instant::now(),
+            finished: 0,
+            ticks: 0,
+            busy_ticks: 0,
+            last_throughput: None,
+        }
+    }
+
+    /// Number of jobs allowed to run at the same time
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn job_finished(&mut self) {
+        self.finished += 1;
+    }
+
+    /// Accounts for the jobs running now, changing the parallelism at the end of each window
+    pub fn tick(&mut self, running: usize) {
+        self.ticks += 1;
+        if running >= self.limit {
+            self.busy_ticks += 1;
+        }
+        let elapsed = self.window_start.elapsed();
+        if elapsed < WINDOW {
+            return;
+        }
+        let throughput = self.finished as f64 / elapsed.as_secs_f64();
+        let overloaded =
+            !self.io_bound && load_per_cpu().is_some_and(|load| load > MAX_LOAD_PER_CPU);
+        self.adjust(throughput, overloaded);
+        self.window_start = Instant::now();
+        self.finished = 0;
+        self.ticks = 0;
+        self.busy_ticks = 0;
+    }
+
+    /// Whether the slots were taken most of the window, more slots can't help otherwise
+    fn saturated(&self) -> bool {
+        self.busy_ticks * 2 >= self.ticks
+    }
+
+    fn adjust(&mut self, throughput: f64, overloaded: bool) {
+        if overloaded {
+            self.up = false;
+        } else if self.last_throughput.is_some_and(|last| throughput < last) {
+            self.up = !self.up;
+        }
+        let limit = match self.up {
+            true if self.saturated() => (self.limit + 1).min(self.max),
+            true => self.limit,
+            false => self.limit.saturating_sub(1).max(1),
+        };
+        if limit != self.limit {
+            tracing::info!(parallelism = limit, throughput, overloaded, "auto-tuned");
+        }
+        self.limit = limit;
+        self.last_throughput = Some(throughput);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Tuner;
+
+    #[test]
+    fn tuner_works() {
+        let mut tuner = Tuner::new(6, false);
+        tuner.limit = 4;
+        tuner.adjust(8.0, false);
+        assert_eq!(tuner.limit(), 5);
+        tuner.adjust(10.0, false);
+        assert_eq!(tuner.limit(), 6);
+        tuner.adjust(12.0, false);
+        assert_eq!(tuner.limit(), 6);
+        // worse, so it turns around
+        tuner.adjust(9.0, false);
+        assert_eq!(tuner.limit(), 5);
+        tuner.adjust(9.5, true);
+        assert_eq!(tuner.limit(), 4);
+        tuner.ticks = 1;
+        tuner.adjust(1.0, false);
+        assert_eq!(tuner.limit(), 4);
+    }
+}
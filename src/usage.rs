@@ -0,0 +1,287 @@
+use crate::status;
+use serde::Serialize;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Child, ExitStatus};
+use std::time::Duration;
+
+/// Resources a finished process used, as reported by wait4 and, on Linux, /proc/PID/io
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct Usage {
+    pub max_rss_kb: u64,
+    pub user_ms: u64,
+    pub system_ms: u64,
+    /// Bytes the process caused to be fetched from or sent to storage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_bytes: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub write_bytes: Option<u64>,
+}
+
+/// Reads the storage I/O counters of a process, which stay readable until it is reaped
+#[cfg(target_os = "linux")]
+fn proc_io(pid: u32) -> Option<(u64, u64)> {
+    let io = std::fs::read_to_string(format!("/proc/{}/io", pid)).ok()?;
+    let counter = |name: &str| {
+        io.lines()
+            .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|v| v.trim().parse().ok())
+    };
+    Some((counter("read_bytes")?, counter("write_bytes")?))
+}
+
+fn timeval_ms(tv: libc::timeval) -> u64 {
+    let d = Duration::new(tv.tv_sec as u64, tv.tv_usec as u32 * 1000);
+    d.as_millis() as u64
+}
+
+/// Like `Child::try_wait` but also collects the resource usage of the child
+///
+/// The child is reaped here, so `Child::wait` and `Child::try_wait` must not be used on it afterwards.
+pub fn try_wait(child: &Child) -> io::Result<Option<(ExitStatus, Usage)>> {
+    #[cfg(target_os = "linux")]
+    let io = match status::exited(child.id())? {
+        true => proc_io(child.id()),
+        false => return Ok(None),
+    };
+    #[cfg(not(target_os = "linux"))]
+    let io = None;
+
+    let mut status = 0;
+    let mut rusage = MaybeUninit::<libc::rusage>::zeroed();
+    // SAFETY: both pointers are valid for writes and the pid is a child of this process that wasn't reaped yet
+    let pid = unsafe {
+        libc::wait4(
+            child.id() as libc::pid_t,
+            &mut status,
+            libc::WNOHANG,
+            rusage.as_mut_ptr(),
+        )
+    };
+    match pid {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(None),
+        _ => {
+            // SAFETY: wait4 filled the struct since it reaped the child
+            let rusage = unsafe { rusage.assume_init() };
+            let usage = Usage {
+                // Linux reports kilobytes, macOS bytes
+                max_rss_kb: if cfg!(target_os = "macos") {
+                    rusage.ru_maxrss as u64 / 1024
+                } else {
+                    rusage.ru_maxrss as u64
+                },
+                user_ms: timeval_ms(rusage.ru_utime),
+                system_ms: timeval_ms(rusage.ru_stime),
+                read_bytes: io.map(|(read, _)| read),
+                write_bytes: io.map(|(_, write)| write),
+            };
+            Ok(Some((ExitStatus::from_raw(status), usage)))
+        }
+    }
+}
+
+/// Parses a size like `512M`, with an optional K, M, G or T binary suffix, into bytes
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, shift) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => {
+            let shift = match c.to_ascii_uppercase() {
+                'K' => 10,
+                'M' => 20,
+                'G' => 30,
+                'T' => 40,
+                _ => return Err(format!("unknown size suffix '{}'", c)),
+            };
+            (&s[..i], shift)
+        }
+        _ => (s, 0),
+    };
+    let n: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid size '{}'", s))?;
+    n.checked_shl(shift)
+        .filter(|bytes| bytes >> shift == n)
+        .ok_or_else(|| format!("size '{}' is too large", s))
+}
+
+/// Processes as listed in /proc: pid, parent pid, process group and resident memory in bytes
+fn processes() -> Vec<(u32, u32, u32, u64)> {
+    // SAFETY: sysconf has no preconditions
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(0) as u64;
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return vec![];
+    };
+    entries
+        .filter_map(|entry| {
+            let pid: u32 = entry.ok()?.file_name().to_str()?.parse().ok()?;
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+            // the command name may contain spaces and parentheses, the other fields come after the last ')'
+            let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+            let ppid = fields.get(1)?.parse().ok()?;
+            let pgrp = fields.get(2)?.parse().ok()?;
+            let rss_pages: u64 = fields.get(21)?.parse().ok()?;
+            Some((pid, ppid, pgrp, rss_pages * page_size))
+        })
+        .collect()
+}
+
+/// Each of `roots` along with all of its descendants
+pub fn process_trees(roots: &[u32]) -> Vec<Vec<(u32, u64)>> {
+    let procs = processes();
+    roots
+        .iter()
+        .map(|&root| {
+            let mut tree = vec![(root, 0)];
+            let mut idx = 0;
+            while idx < tree.len() {
+                let parent = tree[idx].0;
+                if let Some(&(_, _, _, rss)) = procs.iter().find(|p| p.0 == parent) {
+                    tree[idx].1 = rss;
+                }
+                tree.extend(
+                    procs
+                        .iter()
+                        .filter(|p| p.1 == parent)
+                        .map(|&(pid, _, _, _)| (pid, 0)),
+                );
+                idx += 1;
+            }
+            tree
+        })
+        .collect()
+}
+
+/// For each job given as its root processes and process groups, the roots still alive along with all of their
+/// descendants and every process left in the groups
+pub fn live_trees(jobs: &[(&[u32], &[u32])]) -> Vec<Vec<u32>> {
+    let procs = processes();
+    jobs.iter()
+        .map(|(roots, groups)| {
+            let mut tree: Vec<u32> = procs
+                .iter()
+                .filter(|p| roots.contains(&p.0) || groups.contains(&p.2))
+                .map(|p| p.0)
+                .collect();
+            let mut idx = 0;
+            while idx < tree.len() {
+                let parent = tree[idx];
+                for &(pid, ppid, _, _) in &procs {
+                    if ppid == parent && !tree.contains(&pid) {
+                        tree.push(pid);
+                    }
+                }
+                idx += 1;
+            }
+            tree
+        })
+        .collect()
+}
+
+/// Makes pll the parent of every orphaned descendant instead of init, so it can tell when they are gone
+pub fn become_subreaper() -> io::Result<()> {
+    // SAFETY: plain prctl(2) call taking an integer argument
+    if unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Aggregated usage of every job of a run, remembering which one peaked in memory
+#[derive(Default)]
+pub struct Summary {
+    pub total: Usage,
+    /// Sequence number of the job with the largest max RSS
+    pub max_rss_seq: Option<usize>,
+}
+
+impl Summary {
+    pub fn add(&mut self, seq: usize, usage: &Usage) {
+        if self.max_rss_seq.is_none() || usage.max_rss_kb > self.total.max_rss_kb {
+            self.total.max_rss_kb = usage.max_rss_kb;
+            self.max_rss_seq = Some(seq);
+        }
+        self.total.user_ms += usage.user_ms;
+        self.total.system_ms += usage.system_ms;
+        let add = |total: &mut Option<u64>, bytes: Option<u64>| {
+            if let Some(bytes) = bytes {
+                *total = Some(total.unwrap_or(0) + bytes);
+            }
+        };
+        add(&mut self.total.read_bytes, usage.read_bytes);
+        add(&mut self.total.write_bytes, usage.write_bytes);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_size, process_trees, try_wait, Summary, Usage};
+    use std::process::Command;
+    use std::{thread, time::Duration};
+
+    #[test]
+    #[allow(clippy::zombie_processes)] // reaped by try_wait
+    fn try_wait_works() {
+        let child = Command::new("true").spawn().unwrap();
+        let (status, usage) = loop {
+            if let Some(done) = try_wait(&child).unwrap() {
+                break done;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(status.success());
+        assert!(usage.max_rss_kb > 0);
+        if cfg!(target_os = "linux") {
+            assert!(usage.write_bytes.is_some());
+        }
+    }
+
+    #[test]
+    fn parse_size_works() {
+        assert_eq!(parse_size("100"), Ok(100));
+        assert_eq!(parse_size("2k"), Ok(2048));
+        assert_eq!(parse_size("512M"), Ok(512 << 20));
+        assert!(parse_size("1X").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("99999999999T").is_err());
+    }
+
+    #[test]
+    #[allow(clippy::zombie_processes)] // reaped by try_wait
+    fn process_trees_works() {
+        let child = Command::new("sh")
+            .args(["-c", "sleep 1 & wait"])
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_millis(200));
+        let trees = process_trees(&[child.id()]);
+        if cfg!(target_os = "linux") {
+            assert_eq!(trees[0].len(), 2);
+            assert!(trees[0].iter().all(|&(_, rss)| rss > 0));
+        }
+        while try_wait(&child).unwrap().is_none() {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn summary_works() {
+        let mut summary = Summary::default();
+        let usage = |max_rss_kb, user_ms| Usage {
+            max_rss_kb,
+            user_ms,
+            system_ms: 1,
+            ..Usage::default()
+        };
+        summary.add(0, &usage(10, 5));
+        summary.add(1, &usage(30, 5));
+        summary.add(2, &usage(20, 5));
+        assert_eq!(summary.max_rss_seq, Some(1));
+        let total = summary.total;
+        assert_eq!(
+            (total.max_rss_kb, total.user_ms, total.system_ms),
+            (30, 15, 3)
+        );
+    }
+}
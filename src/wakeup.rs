@@ -0,0 +1,88 @@
+use std::mem::MaybeUninit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+use std::{io, thread};
+
+/// Set by `notify` until the next `wait`, so a notification sent while the pool looks at its jobs isn't lost
+static PENDING: Mutex<bool> = Mutex::new(false);
+static NOTIFIED: Condvar = Condvar::new();
+
+/// Wakes the pool up, a job may have finished
+pub fn notify() {
+    *PENDING.lock().unwrap() = true;
+    NOTIFIED.notify_all();
+}
+
+/// Blocks until `notify` is called or `timeout` elapsed, returning right away when it was called since the last
+/// wait
+pub fn wait(timeout: Duration) {
+    let pending = PENDING.lock().unwrap();
+    let (mut pending, _) = NOTIFIED
+        .wait_timeout_while(pending, timeout, |pending| !*pending)
+        .unwrap();
+    *pending = false;
+}
+
+/// Set once a thread waits for SIGCHLD
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+/// Calls `notify` every time a child exits, from a thread waiting for SIGCHLD
+///
+/// Must be called before any other thread is started, SIGCHLD could be delivered to a thread that doesn't block it
+/// and be lost otherwise. Calling it again does nothing.
+pub fn watch_children() -> io::Result<()> {
+    if WATCHING.swap(true, Ordering::SeqCst) {
+        return Ok(());
+    }
+    let mut set = MaybeUninit::<libc::sigset_t>::zeroed();
+    let mut all = MaybeUninit::<libc::sigset_t>::zeroed();
+    // SAFETY: the sets are initialized by sigemptyset and sigfillset before being added to
+    let (set, all) = unsafe {
+        libc::sigemptyset(set.as_mut_ptr());
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGCHLD);
+        libc::sigfillset(all.as_mut_ptr());
+        (set.assume_init(), all.assume_init())
+    };
+    let mut mask = MaybeUninit::<libc::sigset_t>::zeroed();
+    // SAFETY: all is a valid, initialized signal set and mask is valid for writes
+    let res = unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &all, mask.as_mut_ptr()) };
+    if res != 0 {
+        return Err(io::Error::from_raw_os_error(res));
+    }
+    // the thread inherits every signal blocked, the stop signals are for stop::watch to take
+    thread::spawn(move || loop {
+        let mut received = 0;
+        // SAFETY: both pointers are valid for the duration of the call
+        if unsafe { libc::sigwait(&set, &mut received) } == 0 {
+            notify();
+        }
+    });
+    // SAFETY: mask was filled in by pthread_sigmask above
+    let mut mask = unsafe { mask.assume_init() };
+    // SAFETY: mask is a valid, initialized signal set
+    let res = unsafe {
+        libc::sigaddset(&mut mask, libc::SIGCHLD);
+        libc::pthread_sigmask(libc::SIG_SETMASK, &mask, std::ptr::null_mut())
+    };
+    if res != 0 {
+        return Err(io::Error::from_raw_os_error(res));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{notify, wait};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn wait_works() {
+        notify();
+        let start = Instant::now();
+        wait(Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        wait(Duration::from_millis(20));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
@@ -0,0 +1,77 @@
+use crate::status::{Failures, Kill, KillCause, Outcome};
+use crate::usage::{Summary, Usage};
+use serde_json::{json, Value};
+use std::sync::mpsc;
+use std::thread;
+use tracing::{debug, warn};
+
+/// How many events may wait for delivery before the pool blocks on the endpoint
+const MAX_PENDING_EVENTS: usize = 1024;
+
+/// Posts JSON events to a URL from a background thread so slow endpoints don't hold back the pool
+///
+/// Only a bounded number of events is buffered, past it a slow endpoint does slow the pool down rather than
+/// letting memory grow without limit.
+pub struct Webhook {
+    sender: mpsc::SyncSender<Value>,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Webhook {
+    pub fn new(url: String) -> Webhook {
+        let (sender, receiver) = mpsc::sync_channel::<Value>(MAX_PENDING_EVENTS);
+        let thread = thread::spawn(move || {
+            for payload in receiver {
+                match ureq::post(&url).send_json(&payload) {
+                    Ok(_) => debug!(%url, "webhook delivered"),
+                    Err(e) => warn!(%url, "webhook delivery failed: {}", e),
+                }
+            }
+        });
+        Webhook { sender, thread }
+    }
+
+    /// `usage` is only known for jobs run locally, `killed` tells why pll killed the job when it did
+    pub fn job_finished(
+        &self,
+        seq: usize,
+        command: &[String],
+        exit_code: i32,
+        outcome: Outcome,
+        usage: Option<&Usage>,
+        killed: Option<Kill>,
+    ) {
+        let _ = self.sender.send(json!({
+            "event": "job_finished",
+            "seq": seq,
+            "command": command,
+            "exit_code": exit_code,
+            "success": outcome == Outcome::Success,
+            "outcome": outcome,
+            "usage": usage,
+            "memkill": killed.is_some_and(|k| k.cause == KillCause::Memory),
+            "killed": killed,
+        }));
+    }
+
+    /// Sends the run summary and blocks until every pending event was delivered
+    pub fn finish(self, jobs: usize, failures: &Failures, usage: &Summary) {
+        let _ = self.sender.send(json!({
+            "event": "run_finished",
+            "jobs": jobs,
+            "failed": failures.failed,
+            "nonzero": failures.nonzero,
+            "signaled": failures.signaled,
+            "spawn_failed": failures.spawn_failed,
+            "killed": failures.killed,
+            "memkilled": failures.memkilled,
+            "output_killed": failures.output_killed,
+            "timed_out": failures.timed_out,
+            "lost": failures.lost,
+            "usage": usage.total,
+            "max_rss_seq": usage.max_rss_seq,
+        }));
+        drop(self.sender);
+        let _ = self.thread.join();
+    }
+}